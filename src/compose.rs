@@ -0,0 +1,1629 @@
+//! Bringing compose projects up and down on the shared network.
+
+use crate::config::Configuration;
+use crate::docker::{self, DockerBackend, Leftover, NetworkOptions, Project, Runtime};
+use crate::dotenv;
+use crate::error::Failure;
+use crate::hosts;
+use crate::limits;
+use crate::output::{Report, Runner};
+use crate::ports::{self, PortRemap, PublishedPort};
+use crate::proxy::{self, Proxy};
+use crate::temp::TempDir;
+use crate::template;
+use crate::wait;
+use crate::workspace;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+/// Files compose looks for in a project directory when none is given.
+pub const COMPOSE_FILES: [&str; 4] = [
+    "compose.yaml",
+    "compose.yml",
+    "docker-compose.yaml",
+    "docker-compose.yml",
+];
+
+/// Whether compose finds a project in `dir` without being told which file to use.
+pub fn has_compose_file(dir: &Path) -> bool {
+    COMPOSE_FILES.iter().any(|file| dir.join(file).is_file())
+}
+
+/// Seconds to wait for services to become ready when nothing says otherwise.
+pub const READY_TIMEOUT: u64 = 120;
+
+/// How each project is brought up by [`ComposeOps::start`].
+#[derive(Debug, Default, Clone)]
+pub struct StartOptions {
+    /// Wait until services are running and healthy
+    pub wait: bool,
+    /// Seconds to wait for services to become ready
+    pub wait_timeout: u64,
+    /// Rebuild images before starting the containers
+    pub build: bool,
+    /// Recreate containers even if their configuration hasn't changed
+    pub force_recreate: bool,
+    /// Start projects whose services all run with their current configuration too
+    pub force: bool,
+    /// Extra arguments of `compose up`, after the ones comphost passes
+    pub compose_args: Vec<String>,
+    /// Reverse proxy to route `<name>.localhost` through
+    pub proxy: Option<Proxy>,
+    /// Override template of configurations without their own
+    pub override_template: Option<String>,
+    /// Secret environment variables of each configuration
+    pub secrets: BTreeMap<String, BTreeMap<String, String>>,
+    /// Seconds to wait for the `wait_for` endpoints, [`wait::TIMEOUT`] when unset
+    pub wait_for_timeout: Option<u64>,
+    /// Seconds `compose up` may run before it is killed, no limit when unset
+    pub up_timeout: Option<u64>,
+}
+
+/// How each project is brought down by [`ComposeOps::stop`].
+#[derive(Debug, Default, Clone)]
+pub struct StopOptions {
+    /// Remove named volumes and anonymous ones too
+    pub volumes: bool,
+    /// Remove images, `local` for those without a custom tag or `all`
+    pub remove_images: Option<String>,
+    /// Seconds `compose down` may run before it is killed, no limit when unset
+    pub down_timeout: Option<u64>,
+}
+
+/// A container of the `ps` output.
+#[derive(Serialize)]
+pub struct ContainerEntry {
+    pub service: String,
+    pub id: String,
+    pub status: String,
+    pub ports: Vec<String>,
+}
+
+/// A row of `list`, from [`ComposeOps::status`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigStatus {
+    pub name: String,
+    pub active: bool,
+    pub url: String,
+    pub clone_path: Option<String>,
+    /// Whether the project has running containers, `None` when that's unknown
+    pub running: Option<bool>,
+}
+
+/// A container on the shared network whose compose project no longer belongs
+/// to a configuration, found by [`ComposeOps::orphans`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Orphan {
+    pub id: String,
+    pub name: String,
+    /// Compose project the container was started for
+    pub project: String,
+    /// Directory compose started the project in
+    pub working_dir: String,
+}
+
+/// Outcome of [`ComposeOps::check_ports`].
+#[derive(Debug, Default)]
+pub struct PortCheck {
+    /// Configurations that can't start because of their ports
+    pub conflicting: BTreeSet<String>,
+    /// New `remapped_ports` of the configurations whose ports moved
+    pub remapped: BTreeMap<String, Vec<PortRemap>>,
+}
+
+/// Runs compose for configurations with one container runtime and attaches
+/// their containers to the shared network.
+pub struct ComposeOps<'a> {
+    runner: &'a Runner<'a>,
+    runtime: Runtime,
+    docker: Box<dyn DockerBackend>,
+    network: String,
+    network_options: NetworkOptions,
+}
+
+impl<'a> ComposeOps<'a> {
+    pub fn new(
+        runner: &'a Runner<'a>,
+        runtime: Runtime,
+        network: String,
+        network_options: NetworkOptions,
+    ) -> Self {
+        ComposeOps {
+            runner,
+            runtime,
+            docker: docker::backend(runtime),
+            network,
+            network_options,
+        }
+    }
+
+    pub fn runtime(&self) -> Runtime {
+        self.runtime
+    }
+
+    pub fn network(&self) -> &str {
+        &self.network
+    }
+
+    /// Checks that the container runtime can be reached, reporting it when not.
+    /// A dry run only mentions it, as it runs nothing.
+    pub fn check_runtime(&self) -> bool {
+        let Err(err) = self.docker.ping(self.runner) else {
+            return true;
+        };
+        if self.runner.dry_run {
+            self.runner.output.notice(format!(
+                "Can't reach {}, this dry run can't tell what it would find: {}",
+                self.runtime.binary(),
+                err.trim_end()
+            ));
+            return true;
+        }
+        let mut report = Report::global();
+        report.failed_as(
+            Failure::RuntimeUnavailable,
+            format!("Failed to reach {}", self.runtime.binary()),
+            err.as_bytes(),
+        );
+        self.runner.output.report(report);
+        false
+    }
+
+    /// Makes sure the shared network exists, creating it if needed.
+    pub fn ensure_network(&self) -> bool {
+        let runner = self.runner;
+        let docker = &*self.docker;
+        let network = self.network.as_str();
+        let mut report = Report::global();
+        let ready = match docker.network_exists(runner, network) {
+            Ok(true) => true,
+            // Create the network if it does not exist
+            Ok(false) => match runner.retry(&format!("Creating {} network", network), || {
+                docker.create_network(runner, network, &self.network_options)
+            }) {
+                Ok(()) => {
+                    report.ok(format!("Created {} network", network));
+                    true
+                }
+                Err(err) => {
+                    report.failed(
+                        format!("Failed to create {} network", network),
+                        err.as_bytes(),
+                    );
+                    false
+                }
+            },
+            Err(err) => {
+                report.failed(
+                    format!("Failed to inspect {} network", network),
+                    err.as_bytes(),
+                );
+                false
+            }
+        };
+        runner.output.report(report);
+        ready
+    }
+
+    /// Removes the shared network unless a configuration other than `stopped`
+    /// still has running containers. Containers left attached to it, such as
+    /// stopped ones or ones attached by hand, are disconnected first.
+    pub fn remove_unused_network(
+        &self,
+        configs: &BTreeMap<String, Configuration>,
+        stopped: &[String],
+    ) {
+        let runner = self.runner;
+        let docker = &*self.docker;
+        let network = self.network.as_str();
+        let output = runner.output;
+        match docker.network_exists(runner, network) {
+            Ok(true) => {}
+            Ok(false) => return,
+            Err(err) => {
+                let mut report = Report::global();
+                report.failed(
+                    format!("Failed to inspect {} network", network),
+                    err.as_bytes(),
+                );
+                output.report(report);
+                return;
+            }
+        }
+
+        for (config_name, config) in configs {
+            if stopped.contains(config_name) {
+                continue;
+            }
+            let Some(project) = config.project(config_name) else {
+                continue;
+            };
+            if docker
+                .project_containers(runner, &project)
+                .is_ok_and(|containers| !containers.is_empty())
+            {
+                output.notice(format!(
+                    "Keeping {} network, '{}' is still running",
+                    network, config_name
+                ));
+                return;
+            }
+        }
+
+        let mut report = Report::global();
+        let removed = self
+            .remove_proxy()
+            .and_then(|()| docker.network_containers(runner, network))
+            .and_then(|containers| {
+                containers
+                    .iter()
+                    .try_for_each(|container| docker.disconnect_network(runner, network, container))
+            })
+            .and_then(|()| docker.remove_network(runner, network));
+        match removed {
+            Ok(()) => report.ok(format!("Removed {} network", network)),
+            Err(err) => report.failed(
+                format!("Failed to remove {} network", network),
+                err.as_bytes(),
+            ),
+        }
+        output.report(report);
+    }
+
+    /// Brings up one compose project and attaches its containers to the shared network.
+    pub fn start(&self, options: &StartOptions, config_name: &str, config: &Configuration) -> bool {
+        let runner = self.runner;
+        let runtime = self.runtime;
+        let mut report = Report::new(config_name);
+        let project = config
+            .project(config_name)
+            .expect("Started configurations are cloned");
+        // Secrets only reach compose and the hooks through their environment,
+        // which dry-run output would show
+        let env: BTreeMap<String, String>;
+        let project = match options.secrets.get(config_name) {
+            Some(secrets) if runner.dry_run => {
+                let keys: Vec<&str> = secrets.keys().map(String::as_str).collect();
+                runner.output.notice(format!(
+                    "Would pass secrets {} to '{}'",
+                    keys.join(", "),
+                    config_name
+                ));
+                project
+            }
+            Some(secrets) => {
+                env = config
+                    .env
+                    .clone()
+                    .into_iter()
+                    .chain(secrets.clone())
+                    .collect();
+                Project {
+                    env: &env,
+                    ..project
+                }
+            }
+            None => project,
+        };
+
+        // Regenerate the managed .env file before compose reads it
+        if !config.dotenv.is_empty() {
+            let builtins = BTreeMap::from([
+                ("COMPHOST_NAME", config_name.to_string()),
+                ("COMPHOST_CLONE_PATH", project.clone_path.to_string()),
+            ]);
+            let written = dotenv::render(&config.dotenv, &builtins).and_then(|content| {
+                let dir = project.working_dir();
+                if runner.dry_run {
+                    runner
+                        .output
+                        .notice(format!("Would write '{}'", dir.join(".env").display()));
+                    return Ok(());
+                }
+                dotenv::write(&dir, &content).map_err(|err| err.to_string())
+            });
+            if let Err(err) = written {
+                report.failed(
+                    format!("Failed to write .env for '{}'", config_name),
+                    err.as_bytes(),
+                );
+                runner.output.report(report);
+                return false;
+            }
+        }
+
+        // Overrides are removed once compose is done with them, or once it is
+        // clear it won't run
+        let (overrides_dir, overrides) =
+            match self.write_overrides(options, config_name, config, &mut report) {
+                Ok(overrides) => overrides,
+                Err(err) => {
+                    report.failed(
+                        format!("Failed to write compose overrides for '{}'", config_name),
+                        err.as_bytes(),
+                    );
+                    runner.output.report(report);
+                    return false;
+                }
+            };
+        let remove_overrides = || {
+            if let Some(dir) = &overrides_dir {
+                dir.remove();
+            }
+        };
+        let override_paths: Vec<&Path> = overrides.iter().map(PathBuf::as_path).collect();
+
+        if !(options.force || options.force_recreate || options.build)
+            && self.is_up_to_date(&project, &override_paths) == Some(true)
+        {
+            // Running isn't ready, so it is still waited for, dependents
+            // included; compose has nothing to recreate
+            if options.wait {
+                let mut wait_command = project.command_with_overrides(runtime, &override_paths);
+                wait_command
+                    .args([
+                        "up",
+                        "--detach",
+                        "--no-recreate",
+                        "--wait",
+                        "--wait-timeout",
+                    ])
+                    .arg(options.wait_timeout.to_string());
+                let progress = runner
+                    .output
+                    .progress(format!("Waiting for '{}' to be ready", config_name));
+                let waited = runner.run(&mut wait_command);
+                drop(progress);
+                remove_overrides();
+                let failure = match waited {
+                    Ok(waited) if waited.status.success() => None,
+                    Ok(waited) => Some(waited.stderr),
+                    Err(err) => Some(err.to_string().into_bytes()),
+                };
+                if let Some(stderr) = failure {
+                    report.failed(
+                        format!("'{}' is running but did not get ready", config_name),
+                        &stderr,
+                    );
+                    runner.output.report(report);
+                    return false;
+                }
+                report.skipped(format!(
+                    "'{}' is already running, all services are ready",
+                    config_name
+                ));
+            } else {
+                remove_overrides();
+                report.skipped(format!("'{}' is already running", config_name));
+            }
+            runner.output.report(report);
+            return true;
+        }
+
+        if !config.wait_for.is_empty() {
+            let endpoints = config.wait_for.join(", ");
+            if runner.dry_run {
+                runner
+                    .output
+                    .notice(format!("Would wait for {}", endpoints));
+            } else {
+                let timeout = options
+                    .wait_for_timeout
+                    .map_or(wait::TIMEOUT, Duration::from_secs);
+                let progress = runner.output.progress(format!(
+                    "Waiting for {} before starting '{}'",
+                    endpoints, config_name
+                ));
+                let waited = wait::wait(&config.wait_for, timeout);
+                drop(progress);
+                if let Err((endpoint, err)) = waited {
+                    report.failed(
+                        format!(
+                            "'{}' was not up within {}s, not starting '{}'",
+                            endpoint,
+                            timeout.as_secs(),
+                            config_name
+                        ),
+                        err.as_bytes(),
+                    );
+                    remove_overrides();
+                    runner.output.report(report);
+                    return false;
+                }
+            }
+        }
+
+        if !self.run_hook(
+            "pre_start",
+            &config.pre_start,
+            config_name,
+            &project,
+            &mut report,
+        ) {
+            remove_overrides();
+            runner.output.report(report);
+            return false;
+        }
+
+        let mut up_command = project.command_with_overrides(runtime, &override_paths);
+        up_command.arg("up").arg("--detach");
+        if options.build {
+            up_command.arg("--build");
+        }
+        if options.force_recreate {
+            up_command.arg("--force-recreate");
+        }
+        if options.wait {
+            up_command
+                .arg("--wait")
+                .arg("--wait-timeout")
+                .arg(options.wait_timeout.to_string());
+        }
+        up_command.args(&options.compose_args);
+        let progress = runner
+            .output
+            .progress(format!("Starting '{}'", config_name));
+        let start_command =
+            runner.run_timeout(&mut up_command, options.up_timeout.map(Duration::from_secs));
+        drop(progress);
+        remove_overrides();
+        let start_command = match start_command {
+            Ok(start_command) => start_command,
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => {
+                report.failed(
+                    format!("'{}' did not start in time", config_name),
+                    err.to_string().as_bytes(),
+                );
+                runner.output.report(report);
+                return false;
+            }
+            Err(err) => {
+                report.failed(
+                    format!("Failed to execute {} compose up command", runtime.binary()),
+                    err.to_string().as_bytes(),
+                );
+                runner.output.report(report);
+                return false;
+            }
+        };
+
+        let mut started = start_command.status.success();
+        if started {
+            if options.wait {
+                report.ok(format!(
+                    "Started Docker Compose for '{}', all services are ready",
+                    config_name
+                ));
+            } else {
+                report.ok(format!("Started Docker Compose for '{}'", config_name));
+            }
+
+            started = self.run_hook(
+                "post_start",
+                &config.post_start,
+                config_name,
+                &project,
+                &mut report,
+            );
+        } else if options.wait {
+            report.failed(
+                format!(
+                    "Failed to start Docker Compose for '{}' or it was not ready within {}s",
+                    config_name, options.wait_timeout
+                ),
+                &start_command.stderr,
+            );
+        } else {
+            report.failed(
+                format!("Failed to start Docker Compose for '{}'", config_name),
+                &start_command.stderr,
+            );
+        }
+        runner.output.report(report);
+        started
+    }
+
+    /// Runs the shell commands of a lifecycle hook in the clone directory,
+    /// stopping at the first one that fails.
+    fn run_hook(
+        &self,
+        hook: &str,
+        commands: &[String],
+        config_name: &str,
+        project: &Project,
+        report: &mut Report,
+    ) -> bool {
+        for command in commands {
+            #[cfg(windows)]
+            let mut hook_command = {
+                let mut hook_command = Command::new("cmd");
+                hook_command.arg("/C").arg(command);
+                hook_command
+            };
+            #[cfg(not(windows))]
+            let mut hook_command = {
+                let mut hook_command = Command::new("sh");
+                hook_command.arg("-c").arg(command);
+                hook_command
+            };
+            hook_command
+                .current_dir(project.clone_path)
+                .envs(project.env)
+                .env("COMPHOST_NAME", config_name)
+                .env("COMPHOST_CLONE_PATH", project.clone_path);
+
+            match self.runner.run(&mut hook_command) {
+                Ok(hook_output) if hook_output.status.success() => report.ok(format!(
+                    "Ran {} hook of '{}': {}",
+                    hook, config_name, command
+                )),
+                Ok(hook_output) => {
+                    // Scripts often report their errors on stdout
+                    let details = if hook_output.stderr.is_empty() {
+                        &hook_output.stdout
+                    } else {
+                        &hook_output.stderr
+                    };
+                    report.failed(
+                        format!("The {} hook of '{}' failed: {}", hook, config_name, command),
+                        details,
+                    );
+                    return false;
+                }
+                Err(err) => {
+                    report.failed(
+                        format!("Failed to run {} hook of '{}'", hook, config_name),
+                        err.to_string().as_bytes(),
+                    );
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Pulls the images of one compose project ahead of starting it.
+    pub fn pull_images(&self, config_name: &str, project: &Project) -> bool {
+        let runner = self.runner;
+        let runtime = self.runtime;
+        let mut report = Report::new(config_name);
+        let progress = runner
+            .output
+            .progress(format!("Pulling images for '{}'", config_name));
+        let pull_command = runner.run_retrying(
+            &format!("Pulling images for '{}'", config_name),
+            project.command(runtime).arg("pull"),
+        );
+        drop(progress);
+        let pull_command = match pull_command {
+            Ok(pull_command) => pull_command,
+            Err(err) => {
+                report.failed(
+                    format!(
+                        "Failed to execute {} compose pull command",
+                        runtime.binary()
+                    ),
+                    err.to_string().as_bytes(),
+                );
+                runner.output.report(report);
+                return false;
+            }
+        };
+
+        let pulled = pull_command.status.success();
+        if pulled {
+            report.ok(format!("Pulled images for '{}'", config_name));
+        } else {
+            report.failed(
+                format!("Failed to pull images for '{}'", config_name),
+                &pull_command.stderr,
+            );
+        }
+        runner.output.report(report);
+        pulled
+    }
+
+    /// Brings down one compose project.
+    pub fn stop(&self, options: &StopOptions, config_name: &str, config: &Configuration) -> bool {
+        let runner = self.runner;
+        let runtime = self.runtime;
+        let mut report = Report::new(config_name);
+        let project = config
+            .project(config_name)
+            .expect("Stopped configurations are cloned");
+        if !self.run_hook(
+            "pre_stop",
+            &config.pre_stop,
+            config_name,
+            &project,
+            &mut report,
+        ) {
+            runner.output.report(report);
+            return false;
+        }
+        let mut down_command = project.command(runtime);
+        down_command.arg("down");
+        if options.volumes {
+            down_command.arg("--volumes");
+        }
+        if let Some(ref remove_images) = options.remove_images {
+            down_command.arg("--rmi").arg(remove_images);
+        }
+        let stop_command = match runner.run_timeout(
+            &mut down_command,
+            options.down_timeout.map(Duration::from_secs),
+        ) {
+            Ok(stop_command) => stop_command,
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => {
+                report.failed(
+                    format!("'{}' did not stop in time", config_name),
+                    err.to_string().as_bytes(),
+                );
+                runner.output.report(report);
+                return false;
+            }
+            Err(err) => {
+                report.failed(
+                    format!(
+                        "Failed to execute {} compose down command",
+                        runtime.binary()
+                    ),
+                    err.to_string().as_bytes(),
+                );
+                runner.output.report(report);
+                return false;
+            }
+        };
+
+        let mut stopped = stop_command.status.success();
+        if stopped {
+            report.ok(format!("Stopped Docker Compose for '{}'", config_name));
+            stopped = self.run_hook(
+                "post_stop",
+                &config.post_stop,
+                config_name,
+                &project,
+                &mut report,
+            );
+        } else {
+            report.failed(
+                format!("Failed to stop Docker Compose for '{}'", config_name),
+                &stop_command.stderr,
+            );
+        }
+        runner.output.report(report);
+        stopped
+    }
+
+    /// Removes the stopped containers, dangling images and unused volumes of
+    /// one compose project, leaving those of other projects alone.
+    pub fn prune(&self, config_name: &str, project: &Project) -> bool {
+        let runner = self.runner;
+        let mut report = Report::new(config_name);
+        let mut pruned = Vec::new();
+        for leftover in Leftover::ALL {
+            match self.docker.prune(runner, leftover, &project.name) {
+                Ok(0) => {}
+                Ok(1) => pruned.push(format!("1 {}", leftover.describe().trim_end_matches('s'))),
+                Ok(count) => pruned.push(format!("{} {}", count, leftover.describe())),
+                Err(err) => {
+                    report.failed(
+                        format!(
+                            "Failed to prune {} of '{}'",
+                            leftover.describe(),
+                            config_name
+                        ),
+                        err.as_bytes(),
+                    );
+                    runner.output.report(report);
+                    return false;
+                }
+            }
+        }
+        if runner.dry_run || !pruned.is_empty() {
+            let pruned = match pruned.split_last() {
+                None => "stopped containers, dangling images and unused volumes".to_string(),
+                Some((last, [])) => last.clone(),
+                Some((last, rest)) => format!("{} and {}", rest.join(", "), last),
+            };
+            report.ok(format!("Pruned {} of '{}'", pruned, config_name));
+        } else {
+            report.skipped(format!("Nothing to prune for '{}'", config_name));
+        }
+        runner.output.report(report);
+        true
+    }
+
+    /// Whether the project of each cloned configuration is running.
+    pub fn status(&self, configs: &BTreeMap<String, Configuration>) -> Vec<ConfigStatus> {
+        configs
+            .iter()
+            .map(|(config_name, config)| ConfigStatus {
+                name: config_name.clone(),
+                active: config.active,
+                url: config.url.clone(),
+                clone_path: config.clone_path.clone(),
+                running: config
+                    .project(config_name)
+                    .and_then(|project| self.is_running(&project)),
+            })
+            .collect()
+    }
+
+    /// Finds the containers compose started on the shared network for projects
+    /// no configuration points at anymore, such as those of removed
+    /// configurations or of clones that moved. Containers attached by hand and
+    /// the reverse proxy aren't orphans.
+    pub fn orphans(
+        &self,
+        configs: &BTreeMap<String, Configuration>,
+    ) -> Result<Vec<Orphan>, docker::Error> {
+        let runner = self.runner;
+        let docker = &*self.docker;
+        if !docker.network_exists(runner, &self.network)? {
+            return Ok(Vec::new());
+        }
+
+        // Compose records the absolute directory, symlinks or not
+        let mut known = Vec::new();
+        for (config_name, config) in configs {
+            let Some(project) = config.project(config_name) else {
+                continue;
+            };
+            let working_dir = project.working_dir();
+            known.extend(working_dir.canonicalize().ok());
+            known.push(working_dir);
+        }
+        let proxy_project = proxy::project_name(&self.network);
+
+        let mut orphans = Vec::new();
+        for id in docker.network_containers(runner, &self.network)? {
+            let container = docker.inspect_container(runner, &id)?;
+            let (Some(project), Some(working_dir)) = (
+                container.labels.get("com.docker.compose.project"),
+                container
+                    .labels
+                    .get("com.docker.compose.project.working_dir"),
+            ) else {
+                continue;
+            };
+            if *project == proxy_project || known.contains(&PathBuf::from(working_dir)) {
+                continue;
+            }
+            orphans.push(Orphan {
+                id,
+                name: container.name,
+                project: project.clone(),
+                working_dir: working_dir.clone(),
+            });
+        }
+        Ok(orphans)
+    }
+
+    /// Stops and removes the [`orphans`](Self::orphans) on the shared network.
+    pub fn remove_orphans(&self, configs: &BTreeMap<String, Configuration>) -> bool {
+        let runner = self.runner;
+        let network = self.network.as_str();
+        let mut report = Report::global();
+        let removed = match self.orphans(configs) {
+            Ok(orphans) if orphans.is_empty() => {
+                report.skipped(format!("No orphaned containers on {} network", network));
+                true
+            }
+            Ok(orphans) => orphans.iter().fold(true, |removed, orphan| {
+                match self.docker.remove_container(runner, &orphan.id) {
+                    Ok(()) => {
+                        report.ok(format!(
+                            "Removed orphaned container {} of '{}'",
+                            orphan.name, orphan.working_dir
+                        ));
+                        removed
+                    }
+                    Err(err) => {
+                        report.failed(
+                            format!("Failed to remove orphaned container {}", orphan.name),
+                            err.as_bytes(),
+                        );
+                        false
+                    }
+                }
+            }),
+            Err(err) => {
+                report.failed(
+                    format!("Failed to find orphaned containers on {} network", network),
+                    err.as_bytes(),
+                );
+                false
+            }
+        };
+        runner.output.report(report);
+        removed
+    }
+
+    /// Lists the containers of a project from `compose ps`.
+    pub fn containers(&self, project: &Project) -> Result<Vec<ContainerEntry>, docker::Error> {
+        let ps_command = self
+            .runner
+            .query(
+                project
+                    .command(self.runtime)
+                    .args(["ps", "--format", "json"]),
+            )
+            .map_err(|err| {
+                format!(
+                    "Failed to execute {} compose ps command: {}",
+                    self.runtime.binary(),
+                    err
+                )
+            })?;
+        if !ps_command.status.success() {
+            return Err(String::from_utf8_lossy(&ps_command.stderr)
+                .trim_end()
+                .to_string());
+        }
+        parse_compose_ps(&String::from_utf8_lossy(&ps_command.stdout)).map_err(|err| {
+            format!(
+                "Unexpected {} compose ps output: {}",
+                self.runtime.binary(),
+                err
+            )
+        })
+    }
+
+    /// The last `tail` lines of the logs of a project, without colors.
+    pub fn logs(&self, project: &Project, tail: usize) -> Result<String, docker::Error> {
+        let logs_command = self
+            .runner
+            .query(project.command(self.runtime).args([
+                "logs",
+                "--no-color",
+                "--tail",
+                &tail.to_string(),
+            ]))
+            .map_err(|err| {
+                format!(
+                    "Failed to execute {} compose logs command: {}",
+                    self.runtime.binary(),
+                    err
+                )
+            })?;
+        if !logs_command.status.success() {
+            return Err(String::from_utf8_lossy(&logs_command.stderr)
+                .trim_end()
+                .to_string());
+        }
+        Ok(String::from_utf8_lossy(&logs_command.stdout).into_owned())
+    }
+
+    /// The host ports the services of a project publish.
+    pub fn published_ports(&self, project: &Project) -> Result<Vec<PublishedPort>, docker::Error> {
+        ports::parse_compose_config(&self.compose_config(project)?)
+    }
+
+    /// The resolved compose file of a project as JSON.
+    fn compose_config(&self, project: &Project) -> Result<String, docker::Error> {
+        let config_command = self
+            .runner
+            .query(
+                project
+                    .command(self.runtime)
+                    .args(["config", "--format", "json"]),
+            )
+            .map_err(|err| {
+                format!(
+                    "Failed to execute {} compose config command: {}",
+                    self.runtime.binary(),
+                    err
+                )
+            })?;
+        if !config_command.status.success() {
+            return Err(String::from_utf8_lossy(&config_command.stderr)
+                .trim_end()
+                .to_string());
+        }
+        Ok(String::from_utf8_lossy(&config_command.stdout).into_owned())
+    }
+
+    /// Checks the host ports of the selected projects against each other and
+    /// against ports already in use, after their recorded remaps. The first
+    /// configuration to claim a port keeps it; later ones get it moved to a free
+    /// port when `remap` is set, and are reported as conflicting otherwise.
+    pub fn check_ports(
+        &self,
+        configs: &BTreeMap<String, Configuration>,
+        selected: &[String],
+        remap: bool,
+    ) -> PortCheck {
+        let mut check = PortCheck::default();
+        let mut claimed: Vec<(&str, PublishedPort)> = Vec::new();
+        for config_name in selected {
+            let config = &configs[config_name];
+            let Some(project) = config.project(config_name) else {
+                continue;
+            };
+            let ports = match self.published_ports(&project) {
+                Ok(ports) => ports,
+                Err(err) => {
+                    let mut report = Report::new(config_name);
+                    report.skipped(format!(
+                        "Couldn't check the ports of '{}': {}",
+                        config_name, err
+                    ));
+                    self.runner.output.report(report);
+                    continue;
+                }
+            };
+
+            // Forget remaps of ports the project no longer publishes
+            let mut remaps = config.remapped_ports.clone();
+            remaps.retain(|remap| ports.iter().any(|port| remap.applies_to(port)));
+
+            // A running project already holds its own ports
+            let running = self.is_running(&project) == Some(true);
+            let mut report = Report::new(config_name);
+            let mut problems = Vec::new();
+            let mut own: Vec<PublishedPort> = Vec::new();
+            for port in &ports {
+                let published = ports::remapped(port, &remaps);
+                let claimed_by = |candidate: &PublishedPort| {
+                    claimed
+                        .iter()
+                        .find(|(_, other)| other.overlaps(candidate))
+                        .map(|(other_config, _)| *other_config)
+                        .or_else(|| {
+                            let own_port = own.iter().any(|other| other.overlaps(candidate));
+                            own_port.then_some(config_name.as_str())
+                        })
+                };
+                let problem = match claimed_by(&published) {
+                    Some(other_config) => format!(
+                        "Port {} of '{}' is also published by '{}'",
+                        published, port.service, other_config
+                    ),
+                    None if !running && published.is_bound() => {
+                        format!("Port {} of '{}' is already in use", published, port.service)
+                    }
+                    None => {
+                        own.push(published);
+                        continue;
+                    }
+                };
+                if !remap {
+                    problems.push(problem);
+                    continue;
+                }
+                let free = ports::free_port(&published, |candidate| {
+                    claimed_by(candidate).is_some()
+                        || ports.iter().any(|other| other.overlaps(candidate))
+                        || candidate.is_bound()
+                });
+                let Some(host_port) = free else {
+                    problems.push(format!("{}, and no free port is left", problem));
+                    continue;
+                };
+                report.skipped(format!(
+                    "{}, publishing it on {} instead",
+                    problem, host_port
+                ));
+                remaps.retain(|remap| !remap.applies_to(port));
+                remaps.push(PortRemap {
+                    service: port.service.clone(),
+                    port: port.port,
+                    protocol: port.protocol.clone(),
+                    host_port,
+                });
+                own.push(ports::remapped(port, &remaps));
+            }
+
+            if problems.is_empty() {
+                claimed.extend(own.into_iter().map(|port| (config_name.as_str(), port)));
+                if remaps != config.remapped_ports {
+                    check.remapped.insert(config_name.clone(), remaps);
+                }
+            } else {
+                report.failed(
+                    format!("Not starting '{}', its ports conflict", config_name),
+                    problems.join("\n").as_bytes(),
+                );
+                check.conflicting.insert(config_name.clone());
+            }
+            self.runner.output.report(report);
+        }
+        check
+    }
+
+    /// Writes the compose overrides a project starts with: one publishing its
+    /// remapped ports, one routing the proxy to it and its rendered template,
+    /// which comes last so it has the final say. They go to a private
+    /// directory, deleted with the returned `TempDir`, so nobody else can
+    /// read them or slip their own in.
+    fn write_overrides(
+        &self,
+        options: &StartOptions,
+        config_name: &str,
+        config: &Configuration,
+        report: &mut Report,
+    ) -> Result<(Option<TempDir>, Vec<PathBuf>), docker::Error> {
+        let override_template = config
+            .override_template
+            .as_deref()
+            .or(options.override_template.as_deref());
+        let project = config
+            .project(config_name)
+            .expect("Started configurations are cloned");
+        let compose_config = self.compose_config(&project)?;
+        let published = ports::parse_compose_config(&compose_config)?;
+
+        let mut files = Vec::new();
+        if let Some(content) = network_override(&self.network, config_name, &compose_config)? {
+            files.push(("network", content));
+        }
+        if !config.remapped_ports.is_empty() {
+            let content = ports::override_file(&published, &config.remapped_ports);
+            files.push(("ports", content));
+        }
+        if let Some(proxy) = options.proxy {
+            match proxy::route(&published, config.proxy_service.as_deref()) {
+                Some(route) => {
+                    let hostname = proxy::hostname(config_name);
+                    let content = proxy.override_file(&self.network, &route, config_name, &hostname);
+                    files.push(("proxy", content));
+                    report.ok(format!(
+                        "Routing http://{} to '{}' of '{}'",
+                        hostname, route.service, config_name
+                    ));
+                }
+                None => report.skipped(format!(
+                    "Not routing '{}' through the proxy, none of its services publishes a port; set proxy_service",
+                    config_name
+                )),
+            }
+        }
+        if config.cpu_limit.is_some() || config.memory_limit.is_some() {
+            let content = limits::override_file(
+                &limits::services(&compose_config)?,
+                config.cpu_limit,
+                config.memory_limit.as_deref(),
+            );
+            files.push(("limits", content));
+        }
+        if let Some(override_template) = override_template {
+            let content =
+                self.render_template(config_name, config, override_template, published)?;
+            files.push(("override", content));
+        }
+
+        if files.is_empty() {
+            return Ok((None, Vec::new()));
+        }
+        if self.runner.dry_run {
+            let paths = files
+                .into_iter()
+                .map(|(kind, _)| {
+                    let path = std::env::temp_dir().join(format!("{}.{}.yaml", config_name, kind));
+                    self.runner
+                        .output
+                        .notice(format!("Would write '{}'", path.display()));
+                    path
+                })
+                .collect();
+            return Ok((None, paths));
+        }
+        let dir = TempDir::create("overrides").map_err(|err| err.to_string())?;
+        let mut paths = Vec::new();
+        for (kind, content) in files {
+            let path = dir
+                .write(&format!("{}.{}.yaml", config_name, kind), &content)
+                .map_err(|err| err.to_string())?;
+            paths.push(path);
+        }
+        Ok((Some(dir), paths))
+    }
+
+    /// Renders the override template of a configuration, `default_template`
+    /// when it has none, or `None` when neither is set.
+    pub fn rendered_override(
+        &self,
+        config_name: &str,
+        config: &Configuration,
+        default_template: Option<&str>,
+    ) -> Result<Option<String>, docker::Error> {
+        let Some(override_template) = config.override_template.as_deref().or(default_template)
+        else {
+            return Ok(None);
+        };
+        let project = config
+            .project(config_name)
+            .expect("Rendered configurations are cloned");
+        let published = self.published_ports(&project)?;
+        self.render_template(config_name, config, override_template, published)
+            .map(Some)
+    }
+
+    fn render_template(
+        &self,
+        config_name: &str,
+        config: &Configuration,
+        override_template: &str,
+        published: Vec<PublishedPort>,
+    ) -> Result<String, docker::Error> {
+        let path = template::path(override_template).map_err(|err| err.to_string())?;
+        let source = fs::read_to_string(&path)
+            .map_err(|err| format!("Failed to read '{}': {}", path.display(), err))?;
+        let context = template::Context {
+            name: config_name,
+            clone_path: config.clone_path.as_deref().unwrap_or_default(),
+            network: &self.network,
+            hostname: proxy::hostname(config_name),
+            env: &config.env,
+            ports: published
+                .iter()
+                .map(|port| ports::remapped(port, &config.remapped_ports).into())
+                .collect(),
+        };
+        template::render(override_template, &source, &context)
+    }
+
+    /// Brings up the reverse proxy on the shared network, recreating it when
+    /// its kind changed.
+    pub fn ensure_proxy(&self, proxy: Proxy) -> bool {
+        let runner = self.runner;
+        let mut report = Report::global();
+        let started = self.proxy_file().and_then(|path| {
+            let content = proxy.compose_file(&self.network);
+            if runner.dry_run {
+                runner
+                    .output
+                    .notice(format!("Would write '{}'", path.display()));
+            } else {
+                fs::create_dir_all(path.parent().expect("The proxy file is in a directory"))
+                    .and_then(|()| fs::write(&path, content))
+                    .map_err(|err| err.to_string())?;
+            }
+            let up_command = runner
+                .run(
+                    self.proxy_command(&path)
+                        .args(["up", "--detach", "--remove-orphans"]),
+                )
+                .map_err(|err| err.to_string())?;
+            if !up_command.status.success() {
+                return Err(String::from_utf8_lossy(&up_command.stderr)
+                    .trim_end()
+                    .to_string());
+            }
+            Ok(())
+        });
+        let ready = match started {
+            Ok(()) => {
+                report.ok(format!(
+                    "Started {} as the reverse proxy on {} network",
+                    proxy.name(),
+                    self.network
+                ));
+                true
+            }
+            Err(err) => {
+                report.failed(
+                    format!("Failed to start {} as the reverse proxy", proxy.name()),
+                    err.as_bytes(),
+                );
+                false
+            }
+        };
+        runner.output.report(report);
+        ready
+    }
+
+    /// Takes down the reverse proxy of the shared network if one was started.
+    fn remove_proxy(&self) -> Result<(), docker::Error> {
+        let path = self.proxy_file()?;
+        if !path.is_file() {
+            return Ok(());
+        }
+        let down_command = self
+            .runner
+            .run(self.proxy_command(&path).arg("down"))
+            .map_err(|err| err.to_string())?;
+        if !down_command.status.success() {
+            return Err(String::from_utf8_lossy(&down_command.stderr)
+                .trim_end()
+                .to_string());
+        }
+        if !self.runner.dry_run {
+            let _ = fs::remove_file(&path);
+        }
+        Ok(())
+    }
+
+    /// Where the compose file of the proxy of the shared network is kept.
+    fn proxy_file(&self) -> Result<PathBuf, docker::Error> {
+        let dir = workspace::config_dir().map_err(|err| err.to_string())?;
+        Ok(dir.join("proxy").join(format!("{}.yaml", self.network)))
+    }
+
+    fn proxy_command(&self, path: &Path) -> Command {
+        let mut command = Command::new(self.runtime.binary());
+        if let Some(dir) = path.parent() {
+            command.current_dir(dir);
+        }
+        command
+            .arg("compose")
+            .arg("--project-name")
+            .arg(proxy::project_name(&self.network))
+            .arg("-f")
+            .arg(path);
+        command
+    }
+
+    /// Points `<name>.<domain>` in the hosts file at the routed service of every
+    /// running project, removing the entries of the others.
+    pub fn sync_hosts(&self, configs: &BTreeMap<String, Configuration>, domain: &str) {
+        let output = self.runner.output;
+        let mut entries = Vec::new();
+        for (config_name, config) in configs {
+            let Some(project) = config.project(config_name) else {
+                continue;
+            };
+            match self.host_address(&project, config) {
+                Ok(Some(address)) => {
+                    entries.push((hosts::hostname(config_name, domain), address));
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    let mut report = Report::new(config_name);
+                    report.skipped(format!(
+                        "Couldn't find the address of '{}' for the hosts file: {}",
+                        config_name, err
+                    ));
+                    output.report(report);
+                }
+            }
+        }
+
+        let path = hosts::path();
+        let mut report = Report::global();
+        let written = fs::read_to_string(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|content| {
+                let updated = hosts::update(&content, &self.network, &entries);
+                if updated == content {
+                    return Ok(false);
+                }
+                hosts::write(self.runner, &path, &updated).map(|()| true)
+            });
+        match written {
+            Ok(true) if entries.is_empty() => report.ok(format!(
+                "Removed the entries of {} network from '{}'",
+                self.network,
+                path.display()
+            )),
+            Ok(true) => report.ok(format!(
+                "Updated '{}' for {} running configuration(s)",
+                path.display(),
+                entries.len()
+            )),
+            Ok(false) => {}
+            Err(err) => report.failed(
+                format!("Failed to update '{}'", path.display()),
+                err.as_bytes(),
+            ),
+        }
+        output.report(report);
+    }
+
+    /// The address of the routed service of a project on the shared network,
+    /// `None` when it isn't running.
+    fn host_address(
+        &self,
+        project: &Project,
+        config: &Configuration,
+    ) -> Result<Option<String>, docker::Error> {
+        let containers = self.containers(project)?;
+        let Some(first) = containers.first() else {
+            return Ok(None);
+        };
+        let published = self.published_ports(project)?;
+        let service = proxy::route(&published, config.proxy_service.as_deref())
+            .map_or(first.service.clone(), |route| route.service);
+        let Some(container) = containers
+            .iter()
+            .find(|container| container.service == service)
+        else {
+            return Ok(None);
+        };
+        self.docker
+            .container_address(self.runner, &self.network, &container.id)
+    }
+
+    /// Whether any container of a project is running, `None` when compose
+    /// can't tell.
+    pub fn is_running(&self, project: &Project) -> Option<bool> {
+        let output = self
+            .runner
+            .query(
+                project
+                    .command(self.runtime)
+                    .args(["ps", "--quiet", "--status", "running"]),
+            )
+            .ok()
+            .filter(|output| output.status.success())?;
+        Some(output.stdout.iter().any(|b| !b.is_ascii_whitespace()))
+    }
+
+    /// Whether every service of a project runs with the configuration it
+    /// would be started with, `overrides` included, so `compose up` would
+    /// leave it alone. `None` when compose can't tell.
+    pub fn is_up_to_date(&self, project: &Project, overrides: &[&Path]) -> Option<bool> {
+        let query = |command: &mut Command| {
+            self.runner
+                .query(command)
+                .ok()
+                .filter(|output| output.status.success())
+                .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+        };
+        // `<service> <hash>` lines, the hashes compose labels containers with
+        let expected: BTreeMap<String, String> = query(
+            project
+                .command_with_overrides(self.runtime, overrides)
+                .args(["config", "--hash", "*"]),
+        )?
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(service, hash)| (service.to_string(), hash.trim().to_string()))
+        .collect();
+        let running = query(
+            project
+                .command(self.runtime)
+                .args(["ps", "--status", "running", "--format", "json"]),
+        )?;
+        let running = parse_config_hashes(&running).ok()?;
+        Some(
+            !expected.is_empty()
+                && expected
+                    .iter()
+                    .all(|(service, hash)| running.get(service) == Some(hash)),
+        )
+    }
+}
+
+/// A compose override joining the services of a project to the shared
+/// `network`, where they can also be reached as `<service>.<name>` since
+/// service names alone may clash between projects. Services using the host's
+/// or another container's network stack are left alone.
+fn network_override(
+    network: &str,
+    config_name: &str,
+    compose_config: &str,
+) -> Result<Option<String>, String> {
+    let config: serde_json::Value =
+        serde_json::from_str(compose_config).map_err(|err| err.to_string())?;
+    let key = ports::yaml_string(network);
+    let mut services = String::new();
+    for (service, definition) in config["services"].as_object().into_iter().flatten() {
+        if definition.get("network_mode").is_some() {
+            continue;
+        }
+        services.push_str(&format!(
+            "  {}:\n    networks:\n",
+            ports::yaml_string(service)
+        ));
+        // Naming a network drops the implicit default one
+        if definition.get("networks").is_none() {
+            services.push_str("      default: {}\n");
+        }
+        let alias = format!("{}.{}", service, hosts::host_label(config_name));
+        services.push_str(&format!(
+            "      {}:\n        aliases:\n          - {}\n",
+            key,
+            ports::yaml_string(&alias)
+        ));
+    }
+    if services.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(format!(
+        "services:\n{}networks:\n  {}:\n    name: {}\n    external: true\n",
+        services, key, key
+    )))
+}
+
+/// Orders `selected` configurations into waves where every configuration only
+/// depends on configurations of earlier waves. Dependencies outside `selected`
+/// are ignored. Returns the configurations forming a cycle if there is one.
+pub fn dependency_waves(
+    configs: &BTreeMap<String, Configuration>,
+    selected: &[String],
+) -> Result<Vec<Vec<String>>, Vec<String>> {
+    let dependencies = |config_name: &String| {
+        configs[config_name]
+            .depends_on
+            .iter()
+            .filter(|dependency| selected.contains(dependency))
+    };
+
+    let mut waves: Vec<Vec<String>> = Vec::new();
+    let mut placed = BTreeSet::new();
+    let mut remaining: Vec<&String> = selected.iter().collect();
+    while !remaining.is_empty() {
+        let (wave, rest): (Vec<&String>, Vec<&String>) = remaining
+            .into_iter()
+            .partition(|config_name| dependencies(config_name).all(|dep| placed.contains(dep)));
+
+        if wave.is_empty() {
+            // Every remaining configuration waits on another remaining one, so
+            // following dependencies from any of them must loop
+            let mut path = vec![rest[0]];
+            loop {
+                let last = path[path.len() - 1];
+                let next = dependencies(last)
+                    .find(|dep| rest.contains(dep))
+                    .expect("Remaining configurations have a pending dependency");
+                if let Some(start) = path.iter().position(|config_name| *config_name == next) {
+                    let mut cycle: Vec<String> =
+                        path[start..].iter().map(|n| n.to_string()).collect();
+                    cycle.push(next.clone());
+                    return Err(cycle);
+                }
+                path.push(next);
+            }
+        }
+
+        placed.extend(wave.iter().cloned());
+        waves.push(wave.into_iter().cloned().collect());
+        remaining = rest;
+    }
+    Ok(waves)
+}
+
+/// The config hash label of each service in `compose ps --format json`.
+fn parse_config_hashes(stdout: &str) -> Result<BTreeMap<String, String>, serde_json::Error> {
+    Ok(parse_ps_values(stdout)?
+        .iter()
+        .filter_map(|value| {
+            let service = value["Service"].as_str()?;
+            // A comma-separated `key=value` list
+            let hash = value["Labels"]
+                .as_str()?
+                .split(',')
+                .find_map(|label| label.strip_prefix("com.docker.compose.config-hash="))?;
+            Some((service.to_string(), hash.to_string()))
+        })
+        .collect())
+}
+
+/// Reads `compose ps --format json`, which is a JSON array in older compose
+/// releases and one object per line in newer ones.
+fn parse_ps_values(stdout: &str) -> Result<Vec<serde_json::Value>, serde_json::Error> {
+    if stdout.trim_start().starts_with('[') {
+        serde_json::from_str(stdout)
+    } else {
+        stdout
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect()
+    }
+}
+
+/// The containers of `compose ps --format json`.
+fn parse_compose_ps(stdout: &str) -> Result<Vec<ContainerEntry>, serde_json::Error> {
+    Ok(parse_ps_values(stdout)?
+        .iter()
+        .map(|value| {
+            let text = |key: &str| value[key].as_str().unwrap_or_default().to_string();
+            let ports = value["Publishers"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter(|publisher| publisher["PublishedPort"].as_u64().unwrap_or(0) != 0)
+                .map(|publisher| {
+                    format!(
+                        "{}:{}->{}/{}",
+                        publisher["URL"].as_str().unwrap_or_default(),
+                        publisher["PublishedPort"],
+                        publisher["TargetPort"],
+                        publisher["Protocol"].as_str().unwrap_or("tcp")
+                    )
+                })
+                .collect();
+            ContainerEntry {
+                service: text("Service"),
+                id: text("ID"),
+                status: text("Status"),
+                ports,
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn configs(dependencies: &[(&str, &[&str])]) -> BTreeMap<String, Configuration> {
+        dependencies
+            .iter()
+            .map(|(name, depends_on)| {
+                let config = Configuration {
+                    depends_on: depends_on.iter().map(|name| name.to_string()).collect(),
+                    ..Configuration::new(String::new())
+                };
+                (name.to_string(), config)
+            })
+            .collect()
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn waves_follow_dependencies() {
+        let configs = configs(&[
+            ("db", &[]),
+            ("cache", &[]),
+            ("api", &["db", "cache"]),
+            ("web", &["api"]),
+        ]);
+        // Each wave keeps the order of the selection
+        let waves = dependency_waves(&configs, &names(&["web", "api", "db", "cache"])).unwrap();
+        assert_eq!(
+            waves,
+            [names(&["db", "cache"]), names(&["api"]), names(&["web"])]
+        );
+    }
+
+    #[test]
+    fn unselected_dependencies_are_ignored() {
+        let configs = configs(&[("db", &[]), ("api", &["db"])]);
+        let waves = dependency_waves(&configs, &names(&["api"])).unwrap();
+        assert_eq!(waves, [names(&["api"])]);
+    }
+
+    #[test]
+    fn cycles_are_detected() {
+        let configs = configs(&[("a", &["c"]), ("b", &["a"]), ("c", &["b"]), ("d", &[])]);
+        let cycle = dependency_waves(&configs, &names(&["a", "b", "c", "d"])).unwrap_err();
+        let mut members = cycle.clone();
+        members.sort();
+        members.dedup();
+        assert_eq!(members, names(&["a", "b", "c"]));
+    }
+
+    #[test]
+    fn compose_ps_is_read_as_array_and_as_lines() {
+        let array = r#"[{"Service": "web", "ID": "abc", "Status": "Up 2 minutes",
+            "Publishers": [{"URL": "0.0.0.0", "PublishedPort": 8080, "TargetPort": 80, "Protocol": "tcp"},
+                           {"URL": "", "PublishedPort": 0, "TargetPort": 443, "Protocol": "tcp"}]}]"#;
+        let containers = parse_compose_ps(array).unwrap();
+        assert_eq!(containers.len(), 1);
+        assert_eq!(containers[0].service, "web");
+        assert_eq!(containers[0].ports, ["0.0.0.0:8080->80/tcp"]);
+
+        let lines =
+            "{\"Service\": \"web\", \"ID\": \"abc\"}\n\n{\"Service\": \"db\", \"ID\": \"def\"}\n";
+        let services: Vec<String> = parse_compose_ps(lines)
+            .unwrap()
+            .into_iter()
+            .map(|container| container.service)
+            .collect();
+        assert_eq!(services, ["web", "db"]);
+        assert!(parse_compose_ps("not json").is_err());
+    }
+
+    #[test]
+    fn config_hashes_are_read_from_labels() {
+        let ps = r#"{"Service": "web", "Labels": "com.docker.compose.project=demo,com.docker.compose.config-hash=1234abcd"}
+{"Service": "db", "Labels": "com.docker.compose.project=demo"}"#;
+        let hashes = parse_config_hashes(ps).unwrap();
+        assert_eq!(
+            hashes,
+            BTreeMap::from([("web".to_string(), "1234abcd".to_string())])
+        );
+    }
+}
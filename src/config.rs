@@ -0,0 +1,1221 @@
+//! The configurations in `config.toml`, and reading and writing that file
+//! without losing it to a crash mid-write or to another comphost running at the
+//! same time, upgrading files written by older versions.
+
+use crate::docker::{self, NetworkOptions, Project, Runtime};
+use crate::encryption;
+use crate::error::Error;
+use crate::git;
+use crate::output::Retry;
+use crate::ports::PortRemap;
+use crate::proxy::Proxy;
+use crate::time::Utc;
+use crate::workspace;
+use age::secrecy::SecretString;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs::{self, File, TryLockError};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+use toml::Table;
+
+/// Schema version written to the `version` key of new and migrated files.
+pub const VERSION: u32 = 1;
+
+/// How many changes `comphost undo` can go back.
+const HISTORY_LEN: usize = 10;
+
+/// How many backups of a config are kept when `keep_backups` is unset.
+const KEEP_BACKUPS: usize = 20;
+
+/// How many repositories or projects are worked on concurrently when neither
+/// `--jobs` nor the `jobs` setting is given.
+pub const JOBS: usize = 4;
+
+/// Seconds before the first retry when `retry_backoff` is unset.
+const RETRY_BACKOFF: u64 = 2;
+
+/// Upgrades a parsed file by one version, or explains why it can't.
+type Migration = fn(&mut Table) -> Result<(), String>;
+
+/// Upgrades from the version at their index to the next one.
+const MIGRATIONS: [Migration; VERSION as usize] = [nest_configs];
+
+/// Brings a parsed config file up to [`VERSION`], returning the version it had
+/// if anything was migrated. Files without a `version` key are version 0.
+pub fn migrate(document: &mut Table) -> Result<Option<u32>, String> {
+    if document.is_empty() {
+        document.insert("version".to_string(), VERSION.into());
+        return Ok(None);
+    }
+
+    let version = match document.get("version") {
+        None => 0,
+        // A version 0 configuration named `version`, refused by the migration
+        Some(version) if is_version_0_config(version) => 0,
+        Some(version) => version
+            .as_integer()
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or_else(|| format!("Invalid config version '{}'", version))?,
+    };
+    if version > VERSION {
+        return Err(format!(
+            "The config is version {}, but this comphost only understands up to version {}",
+            version, VERSION
+        ));
+    }
+    if version == VERSION {
+        return Ok(None);
+    }
+
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(document)?;
+    }
+    document.insert("version".to_string(), VERSION.into());
+    Ok(Some(version))
+}
+
+/// Keys of version 1 files that aren't configurations.
+const RESERVED_KEYS: [&str; 4] = ["version", "settings", "profiles", "configs"];
+
+/// Whether `value` looks like a configuration of a version 0 file, which
+/// always had a `url`, rather than the settings or profiles.
+fn is_version_0_config(value: &toml::Value) -> bool {
+    value
+        .as_table()
+        .is_some_and(|table| table.contains_key("url"))
+}
+
+/// Version 0 kept every configuration as a top-level table next to
+/// `[settings]` and `[profiles]`; version 1 moves them under `[configs]`.
+/// Configurations named like the tables of version 1 are refused rather than
+/// taken for them.
+fn nest_configs(document: &mut Table) -> Result<(), String> {
+    let reserved: Vec<String> = document
+        .iter()
+        .filter(|(key, value)| RESERVED_KEYS.contains(&key.as_str()) && is_version_0_config(value))
+        .map(|(key, _)| format!("'{}'", key))
+        .collect();
+    if !reserved.is_empty() {
+        return Err(format!(
+            "Can't migrate the configurations named {}, version {} uses these names itself; rename them in the file first",
+            reserved.join(", "),
+            VERSION
+        ));
+    }
+
+    let names: Vec<String> = document
+        .keys()
+        .filter(|key| !RESERVED_KEYS.contains(&key.as_str()))
+        .cloned()
+        .collect();
+    let mut configs = Table::new();
+    for name in names {
+        if let Some(config) = document.remove(&name) {
+            configs.insert(name, config);
+        }
+    }
+    document.insert("configs".to_string(), configs.into());
+    Ok(())
+}
+
+/// A repository of the workspace and how its compose project is run.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Configuration {
+    pub active: bool,
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clone_path: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Branch to clone and to expect checked out, the remote's default when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    /// History depth to clone, overriding the `clone_depth` setting
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub depth: Option<u32>,
+    /// Clone and update the submodules along with the repository
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub submodules: bool,
+    /// Private key for ssh remotes, for hosts needing another identity
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssh_key: Option<String>,
+    /// Local repository, such as a mirror of `url`, to add the clone to as a
+    /// worktree instead of cloning, so several branches share its objects
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub worktree_of: Option<String>,
+    /// Directories of the repository to check out, such as those the compose
+    /// project of a monorepo needs, everything when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sparse_paths: Vec<String>,
+    /// Stash uncommitted changes before pulling and reapply them afterwards,
+    /// instead of leaving a clone with changes alone
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub autostash: bool,
+    /// Compose project name, the configuration name when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project_name: Option<String>,
+    /// Directory in the clone compose runs in, such as `ops/local` of a
+    /// monorepo, the root of the clone when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subdir: Option<String>,
+    /// Compose file relative to `subdir`, when not the default one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compose_file: Option<String>,
+    /// Compose files relative to `subdir`, merged in order after `compose_file`,
+    /// such as a local override of the project's own file
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub compose_files: Vec<String>,
+    /// Compose profiles to enable
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub profiles: Vec<String>,
+    /// Environment variables set for compose invocations
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// Variables rendered into a managed `.env` file on start
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub dotenv: BTreeMap<String, String>,
+    /// Configurations that must be started before this one
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<String>,
+    /// Seconds configurations depending on this one wait for its services to
+    /// be running and healthy before starting, 120 when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ready_timeout: Option<u64>,
+    /// Services outside comphost that must be up before starting, as
+    /// `host:port` or `http(s)://` URLs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub wait_for: Vec<String>,
+    /// Shell commands run in the clone directory before `compose up`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pre_start: Vec<String>,
+    /// Shell commands run once the project is up, such as migrations
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_start: Vec<String>,
+    /// Shell commands run before `compose down`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pre_stop: Vec<String>,
+    /// Shell commands run once the project is down
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_stop: Vec<String>,
+    /// Template of a compose override merged over the project's files, see
+    /// [`template`](crate::template)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub override_template: Option<String>,
+    /// Service the `proxy` routes to, the first one publishing a TCP port when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy_service: Option<String>,
+    /// CPUs each service may use, such as `1.5`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_limit: Option<f64>,
+    /// Memory each service may use, such as `2g` or `512m`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_limit: Option<String>,
+    /// Host ports moved to avoid conflicts with other configurations
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub remapped_ports: Vec<PortRemap>,
+}
+
+/// Global options stored in the `[settings]` table.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Implementation used to clone repositories
+    pub git_backend: git::BackendKind,
+    /// Container runtime, detected on first use when unset
+    pub runtime: Option<Runtime>,
+    /// Network shared by all started projects, `comphost` when unset
+    pub network: Option<String>,
+    /// How the shared network is created
+    #[serde(skip_serializing_if = "NetworkOptions::is_default")]
+    pub network_options: NetworkOptions,
+    /// Directory new repositories are cloned into, asked for when unset
+    pub workspace_dir: Option<String>,
+    /// History depth of new clones, complete when unset
+    pub clone_depth: Option<u32>,
+    /// Move conflicting host ports to free ones on start instead of failing
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub remap_ports: bool,
+    /// Reverse proxy serving started configurations at `<name>.localhost`
+    pub proxy: Option<Proxy>,
+    /// Override template of configurations without their own
+    pub override_template: Option<String>,
+    /// Domain of the hosts file entries of running configurations, such as
+    /// `comphost.local`; the hosts file is left alone when unset
+    pub hosts_domain: Option<String>,
+    /// Show a desktop notification when `start` or `update` has failures
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub notify_failures: bool,
+    /// Repository `sync-config` reads the shared workspace from
+    pub sync_from: Option<String>,
+    /// Timestamped copies of the config kept in `backups/` of the config
+    /// directory, 20 when unset and none when 0
+    pub keep_backups: Option<usize>,
+    /// Seconds `start` waits for the `wait_for` endpoints of a configuration,
+    /// 60 when unset
+    pub wait_for_timeout: Option<u64>,
+    /// Seconds a clone may take before it is killed, no limit when unset
+    pub clone_timeout: Option<u64>,
+    /// Seconds `compose up` may take before it is killed, no limit when unset
+    pub up_timeout: Option<u64>,
+    /// Seconds `compose down` may take before it is killed, no limit when unset
+    pub down_timeout: Option<u64>,
+    /// Times a clone, pull, image pull or network creation is retried after
+    /// failing, none when unset
+    pub retries: Option<u32>,
+    /// Seconds before the first retry, doubled before each next one, 2 when
+    /// unset
+    pub retry_backoff: Option<u64>,
+    /// Repositories or projects cloned, pulled, started or stopped
+    /// concurrently, [`JOBS`] when unset
+    pub jobs: Option<usize>,
+}
+
+impl Settings {
+    pub fn is_default(&self) -> bool {
+        *self == Settings::default()
+    }
+
+    /// How operations that fail when the network does are retried.
+    pub fn retry(&self) -> Retry {
+        Retry {
+            count: self.retries.unwrap_or(0),
+            backoff: Duration::from_secs(self.retry_backoff.unwrap_or(RETRY_BACKOFF)),
+        }
+    }
+}
+
+/// The part of the config shared between machines: the configurations without
+/// their clone paths and remapped ports, and the profiles.
+#[derive(Serialize, Deserialize)]
+pub struct SharedConfig {
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    pub configs: BTreeMap<String, Configuration>,
+}
+
+/// Files `sync-config` looks for at the root of the shared repository.
+pub const WORKSPACE_FILES: [&str; 4] = [
+    "comphost.toml",
+    "comphost.yaml",
+    "comphost.yml",
+    "comphost.json",
+];
+
+/// File formats a [`SharedConfig`] is exported to and imported from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum FileFormat {
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl FileFormat {
+    /// Guesses the format from the extension of `path`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "json" => Some(FileFormat::Json),
+            "yaml" | "yml" => Some(FileFormat::Yaml),
+            "toml" => Some(FileFormat::Toml),
+            _ => None,
+        }
+    }
+
+    pub fn serialize(self, workspace: &SharedConfig) -> Result<String, String> {
+        match self {
+            FileFormat::Json => serde_json::to_string_pretty(workspace)
+                .map(|json| json + "\n")
+                .map_err(|err| err.to_string()),
+            FileFormat::Yaml => serde_yaml::to_string(workspace).map_err(|err| err.to_string()),
+            FileFormat::Toml => toml::to_string(workspace).map_err(|err| err.to_string()),
+        }
+    }
+
+    pub fn deserialize(self, content: &str) -> Result<SharedConfig, String> {
+        match self {
+            FileFormat::Json => serde_json::from_str(content).map_err(|err| err.to_string()),
+            FileFormat::Yaml => serde_yaml::from_str(content).map_err(|err| err.to_string()),
+            FileFormat::Toml => toml::from_str(content).map_err(|err| err.to_string()),
+        }
+    }
+}
+
+/// What [`ConfigStore::import`] does with names that already exist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Merge {
+    /// Leave them alone and report them as conflicts
+    Refuse,
+    /// Replace them, keeping the local clone path and remapped ports
+    Overwrite,
+    /// Replace them, keeping the local clone path, remapped ports and active flag
+    Update,
+    /// Leave them alone
+    SkipExisting,
+}
+
+/// The outcome of importing one configuration or profile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Imported {
+    Added,
+    Replaced,
+    /// Already there with the same content
+    Unchanged,
+    Skipped,
+    Conflict,
+    /// Its URL or worktree repository starts with `-`, which git would take
+    /// for an option
+    Refused,
+}
+
+/// What [`ConfigStore::import`] did with each name.
+pub struct ImportOutcome {
+    pub configs: Vec<(String, Imported)>,
+    pub profiles: Vec<(String, Imported)>,
+}
+
+/// Layout of `config.toml`: the schema version, the settings and profiles
+/// tables and one `[configs.NAME]` table per configuration.
+#[derive(Deserialize)]
+struct ConfigFile {
+    /// Schema version, see [`migrate`]
+    version: u32,
+    #[serde(default)]
+    settings: Settings,
+    #[serde(default)]
+    profiles: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    configs: BTreeMap<String, Configuration>,
+}
+
+impl Configuration {
+    /// An active configuration of the repository at `url`, not cloned yet.
+    pub fn new(url: String) -> Self {
+        Configuration {
+            active: true,
+            url,
+            ..Configuration::default()
+        }
+    }
+
+    /// Whether a command should act on this configuration: the active ones by
+    /// default, or those carrying one of `tags` when tags are given.
+    pub fn is_selected(&self, tags: &[String]) -> bool {
+        if tags.is_empty() {
+            self.active
+        } else {
+            self.tags.iter().any(|tag| tags.contains(tag))
+        }
+    }
+
+    /// How the repository is cloned and pulled.
+    pub fn clone_options(&self) -> git::CloneOptions {
+        git::CloneOptions {
+            branch: self.branch.clone(),
+            depth: self.depth,
+            submodules: self.submodules,
+            ssh_key: self.ssh_key.clone(),
+            autostash: self.autostash,
+            worktree_of: self.worktree_of.clone(),
+            sparse_paths: self.sparse_paths.clone(),
+            timeout: None,
+        }
+    }
+
+    /// The compose project of a cloned configuration named `name`.
+    pub fn project<'a>(&'a self, name: &str) -> Option<Project<'a>> {
+        let clone_path = self.clone_path.as_deref()?;
+        let project_name = match self.project_name {
+            Some(ref project_name) => Cow::Borrowed(project_name.as_str()),
+            None => Cow::Owned(docker::project_name(name)),
+        };
+        let dir = match self.subdir {
+            Some(ref subdir) => {
+                Cow::Owned(Path::new(clone_path).join(subdir).display().to_string())
+            }
+            None => Cow::Borrowed(clone_path),
+        };
+        Some(Project {
+            name: project_name,
+            dir,
+            clone_path,
+            compose_files: self
+                .compose_file
+                .iter()
+                .chain(&self.compose_files)
+                .map(String::as_str)
+                .collect(),
+            profiles: &self.profiles,
+            env: &self.env,
+        })
+    }
+
+    pub fn clone_project(&mut self, clone_path: String) {
+        self.clone_path = Some(clone_path);
+    }
+}
+
+/// The contents of `config.toml`, loaded while holding the config lock.
+///
+/// Changes to the public fields are written back by [`ConfigStore::save`].
+/// It serializes to the layout of the file.
+#[derive(Serialize)]
+pub struct ConfigStore {
+    version: u32,
+    #[serde(skip_serializing_if = "Settings::is_default")]
+    pub settings: Settings,
+    /// Named sets of active configurations
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Vec<String>>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub configs: BTreeMap<String, Configuration>,
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(skip)]
+    lock: Option<Lock>,
+    #[serde(skip)]
+    loaded: String,
+    /// What was loaded as it would be written, so comments and layout of a
+    /// hand-edited file don't count as changes
+    #[serde(skip)]
+    loaded_state: String,
+    #[serde(skip)]
+    migrated_from: Option<u32>,
+    /// Passphrase the file is saved with, unencrypted when unset
+    #[serde(skip)]
+    passphrase: Option<SecretString>,
+    #[serde(skip)]
+    loaded_encrypted: bool,
+    /// The command whose changes [`ConfigStore::save`] adds to the history
+    #[serde(skip)]
+    history_command: Option<String>,
+}
+
+/// A saved change to the config that `comphost undo` reverts, with the file
+/// content before and after it as written, so encrypted configs stay encrypted.
+#[derive(Serialize, Deserialize)]
+pub struct Change {
+    pub command: String,
+    before: String,
+    after: String,
+}
+
+impl ConfigStore {
+    /// `config.toml` in the [config directory](workspace::config_dir), the
+    /// config of the default workspace.
+    pub fn default_path() -> Result<PathBuf, Error> {
+        workspace::path(workspace::DEFAULT)
+    }
+
+    /// Locks and loads the config at `path`, upgrading older layouts. A missing
+    /// file is an empty config. `waiting` is called once if another invocation
+    /// holds the lock.
+    pub fn open(path: &Path, waiting: impl FnOnce()) -> Result<Self, Error> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|source| Error::ConfigDir {
+                path: dir.display().to_string(),
+                source,
+            })?;
+        }
+        let lock = lock(path, waiting).map_err(Error::Lock)?;
+        Self::load(path, Some(lock))
+    }
+
+    /// Loads the config at `path` without locking it, for reading only, as
+    /// [`ConfigStore::open`] followed by [`ConfigStore::unlock`] would.
+    pub fn read(path: &Path) -> Result<Self, Error> {
+        Self::load(path, None)
+    }
+
+    fn load(path: &Path, lock: Option<Lock>) -> Result<Self, Error> {
+        let loaded = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(source) => {
+                return Err(Error::Read {
+                    path: path.display().to_string(),
+                    source,
+                })
+            }
+        };
+
+        // An encrypted config is handled as its plain text from here on
+        let mut passphrase = None;
+        let loaded_encrypted = encryption::is_encrypted(&loaded);
+        let loaded = if loaded_encrypted {
+            let path = path.display().to_string();
+            let key = encryption::passphrase(&path, false)?;
+            let plaintext = encryption::decrypt(&loaded, &key)
+                .map_err(|message| Error::Decrypt { path, message })?;
+            passphrase = Some(key);
+            plaintext
+        } else {
+            loaded
+        };
+
+        let (
+            ConfigFile {
+                version,
+                settings,
+                profiles,
+                configs,
+            },
+            migrated_from,
+        ) = parse(path, &loaded)?;
+
+        let mut store = ConfigStore {
+            settings,
+            profiles,
+            configs,
+            version,
+            path: path.to_path_buf(),
+            lock,
+            loaded,
+            loaded_state: String::new(),
+            migrated_from,
+            passphrase,
+            loaded_encrypted,
+            history_command: None,
+        };
+        store.loaded_state = toml::to_string(&store)?;
+        Ok(store)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// The version the file had if it was migrated while loading.
+    pub fn migrated_from(&self) -> Option<u32> {
+        self.migrated_from
+    }
+
+    /// Whether the file is saved encrypted.
+    pub fn is_encrypted(&self) -> bool {
+        self.passphrase.is_some()
+    }
+
+    /// Saves the file encrypted with `passphrase` from now on, or in plain
+    /// text when `None`.
+    pub fn set_passphrase(&mut self, passphrase: Option<SecretString>) {
+        self.passphrase = passphrase;
+    }
+
+    /// Lets other invocations use the config. Nothing is saved afterwards, as
+    /// they may have changed the file in the meantime.
+    pub fn unlock(&mut self) {
+        self.lock = None;
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Configuration> {
+        self.configs.get(name)
+    }
+
+    /// Names of the configurations selected by `tags`, see
+    /// [`Configuration::is_selected`].
+    pub fn selected(&self, tags: &[String]) -> Vec<String> {
+        self.configs
+            .iter()
+            .filter(|(_, config)| config.is_selected(tags))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Renames a configuration along with the profiles and dependencies that
+    /// refer to it. Returns `false` if `old` doesn't exist or `new` does.
+    pub fn rename(&mut self, old: &str, new: &str) -> bool {
+        if self.configs.contains_key(new) {
+            return false;
+        }
+        let Some(config) = self.configs.remove(old) else {
+            return false;
+        };
+        self.configs.insert(new.to_string(), config);
+
+        let references = self.profiles.values_mut().chain(
+            self.configs
+                .values_mut()
+                .map(|config| &mut config.depends_on),
+        );
+        for names in references {
+            for name in names.iter_mut().filter(|name| *name == old) {
+                *name = new.to_string();
+            }
+        }
+        true
+    }
+
+    /// The configurations and profiles to share, without local clone paths.
+    pub fn export(&self) -> SharedConfig {
+        let configs = self
+            .configs
+            .iter()
+            .map(|(name, config)| {
+                let mut config = config.clone();
+                config.clone_path = None;
+                config.remapped_ports = Vec::new();
+                (name.clone(), config)
+            })
+            .collect();
+        SharedConfig {
+            profiles: self.profiles.clone(),
+            configs,
+        }
+    }
+
+    /// Merges the configurations and profiles of `workspace` into the config,
+    /// returning what happened to each of them.
+    pub fn import(&mut self, workspace: SharedConfig, merge: Merge) -> ImportOutcome {
+        let configs = workspace
+            .configs
+            .into_iter()
+            .map(|(name, mut config)| {
+                let git_arguments = [Some(&config.url), config.worktree_of.as_ref()];
+                if git_arguments
+                    .into_iter()
+                    .flatten()
+                    .any(|argument| argument.starts_with('-'))
+                {
+                    return (name, Imported::Refused);
+                }
+                let outcome = match self.configs.get(&name) {
+                    None => {
+                        config.clone_path = None;
+                        config.remapped_ports = Vec::new();
+                        Imported::Added
+                    }
+                    Some(existing) => {
+                        config.clone_path = existing.clone_path.clone();
+                        config.remapped_ports = existing.remapped_ports.clone();
+                        if merge == Merge::Update {
+                            config.active = existing.active;
+                        }
+                        match merge {
+                            _ if *existing == config => return (name, Imported::Unchanged),
+                            Merge::Overwrite | Merge::Update => Imported::Replaced,
+                            Merge::SkipExisting => return (name, Imported::Skipped),
+                            Merge::Refuse => return (name, Imported::Conflict),
+                        }
+                    }
+                };
+                self.configs.insert(name.clone(), config);
+                (name, outcome)
+            })
+            .collect();
+        let profiles = workspace
+            .profiles
+            .into_iter()
+            .map(|(name, members)| {
+                let outcome = match self.profiles.get(&name) {
+                    None => Imported::Added,
+                    Some(existing) if *existing == members => return (name, Imported::Unchanged),
+                    Some(_) => match merge {
+                        Merge::Overwrite | Merge::Update => Imported::Replaced,
+                        Merge::SkipExisting => return (name, Imported::Skipped),
+                        Merge::Refuse => return (name, Imported::Conflict),
+                    },
+                };
+                self.profiles.insert(name.clone(), members);
+                (name, outcome)
+            })
+            .collect();
+        ImportOutcome { configs, profiles }
+    }
+
+    /// The text of the file, or what it will contain when it is new or was
+    /// migrated.
+    pub fn text(&self) -> Result<String, Error> {
+        if self.loaded.is_empty() || self.migrated_from.is_some() {
+            return Ok(toml::to_string(self)?);
+        }
+        Ok(self.loaded.clone())
+    }
+
+    /// Checks that `content` is a config this comphost can load.
+    pub fn validate(&self, content: &str) -> Result<(), Error> {
+        parse(&self.path, content).map(|_| ())
+    }
+
+    /// Writes `content` as is after validating it, keeping the comments and
+    /// layout of a hand-edited file. The store is unlocked afterwards since its
+    /// fields no longer match the file.
+    pub fn replace(&mut self, content: &str) -> Result<(), Error> {
+        if self.lock.is_none() {
+            return Ok(());
+        }
+        self.validate(content)?;
+        self.back_up()?;
+        write(&self.path, &self.encode(content)?).map_err(|source| Error::Write {
+            path: self.path.display().to_string(),
+            source,
+        })?;
+        self.unlock();
+        Ok(())
+    }
+
+    /// The file content, in plain text, if the file is new or was migrated,
+    /// its data differs from what was loaded or it is to be encrypted or
+    /// decrypted, and the config is still locked.
+    pub fn changes(&self) -> Result<Option<String>, Error> {
+        if self.lock.is_none() {
+            return Ok(None);
+        }
+        let content = toml::to_string(self)?;
+        let changed = self.loaded.is_empty()
+            || self.migrated_from.is_some()
+            || content != self.loaded_state
+            || self.is_encrypted() != self.loaded_encrypted;
+        Ok(changed.then_some(content))
+    }
+
+    /// The backups of this config in its directory under `backups/` of the
+    /// config directory, the oldest first.
+    pub fn backups(&self) -> Result<Vec<PathBuf>, Error> {
+        let dir = self.backups_dir()?;
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(source) => {
+                return Err(Error::Read {
+                    path: dir.display().to_string(),
+                    source,
+                })
+            }
+        };
+        let prefix = format!("{}-", self.backup_prefix());
+        let mut backups: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                let name = path.file_name().and_then(|name| name.to_str());
+                let timestamp = name
+                    .and_then(|name| name.strip_prefix(&prefix))
+                    .and_then(|rest| rest.strip_suffix(".toml"));
+                timestamp.is_some_and(is_timestamp)
+            })
+            .collect();
+        backups.sort();
+        Ok(backups)
+    }
+
+    /// The directory of the backups of this config in `backups/` of the
+    /// config directory, named after the file and a hash of its canonical
+    /// path, so files with the same name don't share their backups.
+    fn backups_dir(&self) -> Result<PathBuf, Error> {
+        // The file itself may not exist yet
+        let path = fs::canonicalize(&self.path)
+            .or_else(|_| {
+                let dir = self.path.parent().filter(|dir| !dir.as_os_str().is_empty());
+                Ok::<_, io::Error>(
+                    fs::canonicalize(dir.unwrap_or(Path::new(".")))?
+                        .join(self.path.file_name().unwrap_or_default()),
+                )
+            })
+            .unwrap_or_else(|_| self.path.clone());
+        let hash = format!("{:x}", Sha256::digest(path.to_string_lossy().as_bytes()));
+        Ok(workspace::config_dir()?.join("backups").join(format!(
+            "{}-{}",
+            self.backup_prefix(),
+            &hash[..16]
+        )))
+    }
+
+    /// Backups are named after the config file, so they can be told apart
+    /// when copied elsewhere.
+    fn backup_prefix(&self) -> String {
+        self.path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    /// Copies the file as it is to a new backup, removing the oldest ones
+    /// beyond `keep_backups`.
+    fn back_up(&self) -> Result<(), Error> {
+        let keep = self.settings.keep_backups.unwrap_or(KEEP_BACKUPS);
+        if keep == 0 || !self.path.exists() {
+            return Ok(());
+        }
+        let dir = self.backups_dir()?;
+        let write_error = |path: &Path, source| Error::Write {
+            path: path.display().to_string(),
+            source,
+        };
+        fs::create_dir_all(&dir).map_err(|source| write_error(&dir, source))?;
+        let backup = dir.join(format!(
+            "{}-{}.toml",
+            self.backup_prefix(),
+            timestamp(Utc::now())
+        ));
+        fs::copy(&self.path, &backup).map_err(|source| write_error(&backup, source))?;
+
+        let backups = self.backups()?;
+        for old in &backups[..backups.len().saturating_sub(keep)] {
+            fs::remove_file(old).map_err(|source| write_error(old, source))?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the config with the backup named `name`, keeping the current
+    /// encryption, see [`ConfigStore::replace`].
+    pub fn restore(&mut self, name: &str) -> Result<(), Error> {
+        let backup = self
+            .backups()?
+            .into_iter()
+            .find(|backup| {
+                backup
+                    .file_name()
+                    .is_some_and(|file_name| file_name == name)
+            })
+            .ok_or_else(|| Error::UnknownBackup(name.to_string()))?;
+        let content = fs::read_to_string(&backup).map_err(|source| Error::Read {
+            path: backup.display().to_string(),
+            source,
+        })?;
+        let content = if encryption::is_encrypted(&content) {
+            let path = backup.display().to_string();
+            let passphrase = encryption::passphrase(&path, false)?;
+            encryption::decrypt(&content, &passphrase)
+                .map_err(|message| Error::Decrypt { path, message })?
+        } else {
+            content
+        };
+        self.replace(&content)
+    }
+
+    /// Has [`ConfigStore::save`] remember the changes, made by `command`, so
+    /// `comphost undo` can revert them.
+    pub fn keep_history(&mut self, command: String) {
+        self.history_command = Some(command);
+    }
+
+    /// The changes `comphost undo` can revert, the most recent last.
+    pub fn history(&self) -> Vec<Change> {
+        fs::read_to_string(self.history_path())
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    fn history_path(&self) -> PathBuf {
+        with_suffix(&self.path, ".history")
+    }
+
+    fn save_history(&self, history: &[Change]) -> Result<(), Error> {
+        let path = self.history_path();
+        let content = serde_json::to_string(history).expect("Changes serialize to JSON");
+        fs::write(&path, content).map_err(|source| Error::Write {
+            path: path.display().to_string(),
+            source,
+        })
+    }
+
+    /// Reverts the most recent change in the history, returning the command
+    /// that made it, and lets go of the config like [`ConfigStore::unlock`].
+    /// Only the file is restored, not what the command did elsewhere.
+    pub fn undo(&mut self) -> Result<Option<String>, Error> {
+        if self.lock.is_none() {
+            return Ok(None);
+        }
+        let mut history = self.history();
+        let Some(change) = history.pop() else {
+            return Ok(None);
+        };
+        let current = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(source) => {
+                return Err(Error::Read {
+                    path: self.path.display().to_string(),
+                    source,
+                })
+            }
+        };
+        if current != change.after {
+            return Err(Error::Undo(change.command));
+        }
+        self.back_up()?;
+        write(&self.path, &change.before).map_err(|source| Error::Write {
+            path: self.path.display().to_string(),
+            source,
+        })?;
+        self.save_history(&history)?;
+        self.unlock();
+        Ok(Some(change.command))
+    }
+
+    /// Writes the changes back to the file, returning whether there were any.
+    pub fn save(self) -> Result<bool, Error> {
+        let Some(content) = self.changes()? else {
+            return Ok(false);
+        };
+        let write_error = |source| Error::Write {
+            path: self.path.display().to_string(),
+            source,
+        };
+        let before = match self.history_command {
+            Some(_) => match fs::read_to_string(&self.path) {
+                Ok(content) => content,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+                Err(source) => {
+                    return Err(Error::Read {
+                        path: self.path.display().to_string(),
+                        source,
+                    })
+                }
+            },
+            None => String::new(),
+        };
+        let after = self.encode(&content)?;
+        self.back_up()?;
+        write(&self.path, &after).map_err(write_error)?;
+        // The backups and the history of a file that was just encrypted are
+        // still plain text
+        if self.is_encrypted() && !self.loaded_encrypted {
+            let mut plain_text = self.backups()?;
+            plain_text.extend([with_suffix(&self.path, ".bak"), self.history_path()]);
+            for path in plain_text {
+                match fs::remove_file(path) {
+                    Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                        return Err(write_error(err))
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        if let Some(ref command) = self.history_command {
+            let mut history = self.history();
+            history.push(Change {
+                command: command.clone(),
+                before,
+                after,
+            });
+            let excess = history.len().saturating_sub(HISTORY_LEN);
+            history.drain(..excess);
+            self.save_history(&history)?;
+        }
+        Ok(true)
+    }
+
+    /// The content as written to the file, encrypted if it should be.
+    fn encode(&self, content: &str) -> Result<String, Error> {
+        match self.passphrase {
+            Some(ref passphrase) => {
+                encryption::encrypt(content, passphrase).map_err(|message| Error::Encrypt {
+                    path: self.path.display().to_string(),
+                    message,
+                })
+            }
+            None => Ok(content.to_string()),
+        }
+    }
+}
+
+/// Parses the content of the config at `path`, upgrading older layouts.
+fn parse(path: &Path, content: &str) -> Result<(ConfigFile, Option<u32>), Error> {
+    let parse_error = |source| Error::Parse {
+        path: path.display().to_string(),
+        source,
+    };
+    let mut document: Table = toml::from_str(content).map_err(parse_error)?;
+    let migrated_from = migrate(&mut document).map_err(|message| Error::Migrate {
+        path: path.display().to_string(),
+        message,
+    })?;
+    let config_file = toml::Value::Table(document)
+        .try_into()
+        .map_err(parse_error)?;
+    Ok((config_file, migrated_from))
+}
+
+/// How long to wait for another comphost to release the config.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Exclusive access to the config file, released when dropped.
+pub struct Lock {
+    _file: File,
+}
+
+/// Takes an advisory lock on `<path>.lock`, waiting for other invocations to
+/// finish with the config. `waiting` is called once if the lock is busy.
+///
+/// A separate file is locked because [`write()`] replaces `path` itself.
+pub fn lock(path: &Path, waiting: impl FnOnce()) -> io::Result<Lock> {
+    let file = File::create(with_suffix(path, ".lock"))?;
+    let deadline = Instant::now() + LOCK_TIMEOUT;
+    let mut waiting = Some(waiting);
+    loop {
+        match file.try_lock() {
+            Ok(()) => return Ok(Lock { _file: file }),
+            Err(TryLockError::Error(err)) => return Err(err),
+            Err(TryLockError::WouldBlock) if Instant::now() >= deadline => {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!(
+                        "another comphost is still using the config after {} seconds",
+                        LOCK_TIMEOUT.as_secs()
+                    ),
+                ));
+            }
+            Err(TryLockError::WouldBlock) => {
+                if let Some(waiting) = waiting.take() {
+                    waiting();
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
+        }
+    }
+}
+
+/// `time` as `YYYYMMDD-HHMMSS-mmm`, so backups sort by their names.
+fn timestamp(time: Utc) -> String {
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}-{:03}",
+        time.year, time.month, time.day, time.hour, time.minute, time.second, time.millisecond
+    )
+}
+
+/// Whether `text` has the shape of a [`timestamp`].
+fn is_timestamp(text: &str) -> bool {
+    text.len() == 19
+        && text.char_indices().all(|(i, c)| match i {
+            8 | 15 => c == '-',
+            _ => c.is_ascii_digit(),
+        })
+}
+
+/// Appends `suffix` to the file name of `path`.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Replaces the file at `path` with `content`, keeping the previous version
+/// next to it as `<path>.bak`.
+///
+/// The content goes to a temporary file in the same directory first, which is
+/// then renamed over `path`, so readers see either the old or the new file.
+pub fn write(path: &Path, content: &str) -> io::Result<()> {
+    let temp_path = with_suffix(path, ".tmp");
+    let mut temp = File::create(&temp_path)?;
+    temp.write_all(content.as_bytes())?;
+    temp.sync_all()?;
+
+    if path.exists() {
+        fs::copy(path, with_suffix(path, ".bak"))?;
+    }
+    fs::rename(&temp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(content: &str) -> Table {
+        toml::from_str(content).unwrap()
+    }
+
+    #[test]
+    fn version_0_configs_move_under_configs() {
+        let mut version_0 = document(
+            r#"
+            [settings]
+            network = "dev"
+
+            [api]
+            active = true
+            url = "https://example.com/api.git"
+            "#,
+        );
+        assert_eq!(migrate(&mut version_0), Ok(Some(0)));
+        assert_eq!(version_0["version"].as_integer(), Some(VERSION.into()));
+        assert_eq!(version_0["settings"]["network"].as_str(), Some("dev"));
+        assert!(version_0.get("api").is_none());
+        assert_eq!(
+            version_0["configs"]["api"]["url"].as_str(),
+            Some("https://example.com/api.git")
+        );
+
+        let (file, migrated_from) = parse(
+            Path::new("config.toml"),
+            &toml::to_string(&version_0).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(migrated_from, None);
+        assert!(file.configs["api"].active);
+    }
+
+    #[test]
+    fn version_0_configs_with_reserved_names_are_refused() {
+        for name in RESERVED_KEYS {
+            let mut version_0 = document(&format!(
+                "[{}]\nactive = true\nurl = \"https://example.com/repo.git\"\n",
+                name
+            ));
+            let err = migrate(&mut version_0).unwrap_err();
+            assert!(err.contains(&format!("'{}'", name)), "{}", err);
+        }
+
+        // Settings and profiles are still told apart from configurations
+        let mut version_0 = document(
+            r#"
+            [settings]
+            network = "dev"
+
+            [profiles]
+            backend = ["api"]
+
+            [api]
+            active = true
+            url = "https://example.com/api.git"
+            "#,
+        );
+        assert_eq!(migrate(&mut version_0), Ok(Some(0)));
+        assert_eq!(version_0["profiles"]["backend"][0].as_str(), Some("api"));
+        assert!(version_0["configs"].get("settings").is_none());
+    }
+
+    #[test]
+    fn current_and_empty_files_are_left_alone() {
+        let mut current = document("version = 1\n[configs]\n");
+        assert_eq!(migrate(&mut current), Ok(None));
+
+        let mut empty = Table::new();
+        assert_eq!(migrate(&mut empty), Ok(None));
+        assert_eq!(empty["version"].as_integer(), Some(VERSION.into()));
+    }
+
+    #[test]
+    fn newer_and_invalid_versions_are_refused() {
+        assert!(migrate(&mut document("version = 99")).is_err());
+        assert!(migrate(&mut document("version = \"one\"")).is_err());
+        assert!(migrate(&mut document("version = -1")).is_err());
+    }
+
+    #[test]
+    fn backup_timestamps_sort_by_name() {
+        let time = crate::time::Utc {
+            year: 2024,
+            month: 6,
+            day: 1,
+            hour: 9,
+            minute: 5,
+            second: 7,
+            millisecond: 42,
+        };
+        assert_eq!(timestamp(time), "20240601-090507-042");
+        assert!(is_timestamp(&timestamp(time)));
+        assert!(!is_timestamp("2024-06-01"));
+    }
+}
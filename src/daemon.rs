@@ -0,0 +1,215 @@
+//! `comphost daemon`: keeps the status of the configurations of one config
+//! file in memory and answers for `list` over a unix socket next to the config,
+//! so repeated calls neither parse the config nor query the runtime.
+//!
+//! Container events of the runtime and changes to the config file make the
+//! status stale, it's then gathered again on the next request. The protocol is
+//! one JSON request and one JSON response per line. Without a daemon, or when
+//! it doesn't answer in time, the CLI does the work itself.
+
+use crate::compose::{ComposeOps, ConfigStatus, Orphan};
+use crate::config::ConfigStore;
+use crate::error::Error;
+use crate::output::Output;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// How long the CLI waits for an answer before doing the work itself.
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long to wait before following the runtime's events again.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Container events that change what `list` shows.
+const EVENTS: [&str; 4] = ["create", "start", "die", "destroy"];
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "request", rename_all = "snake_case")]
+enum Request {
+    Status,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Response {
+    Status(Status),
+    Error(String),
+}
+
+/// Everything `list` shows.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Status {
+    pub network: String,
+    pub configs: Vec<ConfigStatus>,
+    pub orphans: Vec<Orphan>,
+}
+
+/// The last status, and the modification time of the config it was read from.
+#[derive(Default)]
+struct Cache {
+    status: Option<Status>,
+    modified: Option<SystemTime>,
+}
+
+/// The socket of the daemon serving the config at `config_path`.
+pub fn socket_path(config_path: &Path) -> PathBuf {
+    config_path.with_extension("sock")
+}
+
+/// Asks the daemon serving the config at `config_path` for the status, `None`
+/// when no daemon answers.
+pub fn status(config_path: &Path) -> Option<Status> {
+    let mut stream = UnixStream::connect(socket_path(config_path)).ok()?;
+    stream.set_read_timeout(Some(TIMEOUT)).ok()?;
+    let request = serde_json::to_string(&Request::Status).expect("Requests serialize to JSON");
+    writeln!(stream, "{}", request).ok()?;
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response).ok()?;
+    match serde_json::from_str(&response).ok()? {
+        Response::Status(status) => Some(status),
+        Response::Error(_) => None,
+    }
+}
+
+/// Answers requests for the config at `config_path` until the process is
+/// stopped.
+pub fn serve(config_path: &Path, compose: &ComposeOps, output: &Output) -> Result<(), Error> {
+    let socket = socket_path(config_path);
+    let listen_error = |source| Error::Listen {
+        path: socket.display().to_string(),
+        source,
+    };
+    if UnixStream::connect(&socket).is_ok() {
+        return Err(Error::DaemonRunning(socket.display().to_string()));
+    }
+    // Left behind by a daemon that didn't get to clean up
+    let _ = fs::remove_file(&socket);
+    let listener = UnixListener::bind(&socket).map_err(listen_error)?;
+    output.notice(format!(
+        "Listening on '{}', press Ctrl-C to stop",
+        socket.display()
+    ));
+
+    let cache = Mutex::new(Cache::default());
+    let watching = AtomicBool::new(false);
+    thread::scope(|scope| {
+        scope.spawn(|| watch_events(compose, &cache, &watching));
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            let (cache, watching) = (&cache, &watching);
+            scope.spawn(move || answer(stream, config_path, compose, cache, watching));
+        }
+    });
+    Ok(())
+}
+
+/// Answers the requests of one connection.
+fn answer(
+    stream: UnixStream,
+    config_path: &Path,
+    compose: &ComposeOps,
+    cache: &Mutex<Cache>,
+    watching: &AtomicBool,
+) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        let response = match serde_json::from_str(&line) {
+            Ok(Request::Status) => match current_status(config_path, compose, cache, watching) {
+                Ok(status) => Response::Status(status),
+                Err(err) => Response::Error(err.to_string()),
+            },
+            Err(err) => Response::Error(format!("Invalid request: {}", err)),
+        };
+        let response = serde_json::to_string(&response).expect("Responses serialize to JSON");
+        if writeln!(writer, "{}", response).is_err() {
+            return;
+        }
+    }
+}
+
+/// The cached status, gathered again when it may be stale.
+fn current_status(
+    config_path: &Path,
+    compose: &ComposeOps,
+    cache: &Mutex<Cache>,
+    watching: &AtomicBool,
+) -> Result<Status, Error> {
+    let modified = fs::metadata(config_path)
+        .and_then(|metadata| metadata.modified())
+        .ok();
+    // Held while gathering, so events arriving meanwhile invalidate the result
+    let mut cache = cache.lock().unwrap();
+    if let (Some(status), true, true) = (
+        &cache.status,
+        cache.modified == modified,
+        watching.load(Ordering::SeqCst),
+    ) {
+        return Ok(status.clone());
+    }
+
+    let mut store = ConfigStore::open(config_path, || {})?;
+    store.unlock();
+    let status = Status {
+        network: compose.network().to_string(),
+        configs: compose.status(&store.configs),
+        orphans: compose.orphans(&store.configs).unwrap_or_default(),
+    };
+    *cache = Cache {
+        status: Some(status.clone()),
+        modified,
+    };
+    Ok(status)
+}
+
+/// Follows the container events of the runtime, dropping the cached status on
+/// each one. While events can't be followed nothing is cached.
+fn watch_events(compose: &ComposeOps, cache: &Mutex<Cache>, watching: &AtomicBool) {
+    let mut events_command = Command::new(compose.runtime().binary());
+    events_command.args([
+        "events",
+        "--format",
+        "{{json .}}",
+        "--filter",
+        "type=container",
+    ]);
+    for event in EVENTS {
+        events_command
+            .arg("--filter")
+            .arg(format!("event={}", event));
+    }
+    events_command.stdout(Stdio::piped()).stderr(Stdio::null());
+
+    loop {
+        if let Ok(mut child) = events_command.spawn() {
+            let stdout = child.stdout.take().expect("stdout is piped");
+            watching.store(true, Ordering::SeqCst);
+            // Whatever happened before the events were followed
+            cache.lock().unwrap().status = None;
+            for line in BufReader::new(stdout).lines() {
+                if line.is_err() {
+                    break;
+                }
+                cache.lock().unwrap().status = None;
+            }
+            watching.store(false, Ordering::SeqCst);
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        thread::sleep(RETRY_INTERVAL);
+    }
+}
@@ -0,0 +1,903 @@
+//! Container runtime operations used around `compose`: the shared network and
+//! the containers of a compose project.
+//!
+//! With the `docker-api` feature the Docker Engine HTTP API is used over the local
+//! unix socket, falling back to the runtime's CLI when the socket can't be reached.
+
+use crate::output::Runner;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The container engine whose CLI runs compose and network commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Runtime {
+    Docker,
+    Podman,
+    Nerdctl,
+}
+
+impl Runtime {
+    pub fn binary(self) -> &'static str {
+        match self {
+            Runtime::Docker => "docker",
+            Runtime::Podman => "podman",
+            Runtime::Nerdctl => "nerdctl",
+        }
+    }
+
+    /// Finds the first runtime whose binary is on `PATH`.
+    pub fn detect() -> Option<Runtime> {
+        let path = env::var_os("PATH")?;
+        [Runtime::Docker, Runtime::Podman, Runtime::Nerdctl]
+            .into_iter()
+            .find(|runtime| {
+                // `docker.exe` on Windows
+                let binary = format!("{}{}", runtime.binary(), env::consts::EXE_SUFFIX);
+                env::split_paths(&path).any(|dir| dir.join(&binary).is_file())
+            })
+    }
+}
+
+/// The project name compose would derive from `name`: lowercase letters,
+/// digits, `-` and `_`, starting with a letter or digit.
+pub fn project_name(name: &str) -> String {
+    name.to_lowercase()
+        .chars()
+        .filter(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || ['-', '_'].contains(c))
+        .collect::<String>()
+        .trim_start_matches(['-', '_'])
+        .to_string()
+}
+
+/// A compose project on disk, with what every compose invocation needs to find it.
+pub struct Project<'a> {
+    /// Name compose labels everything of the project with
+    pub name: Cow<'a, str>,
+    /// Directory compose runs in
+    pub dir: Cow<'a, str>,
+    /// Root of the clone, `dir` or a directory above it
+    pub clone_path: &'a str,
+    /// Compose files relative to `dir` in the order they are merged, the
+    /// default ones when empty
+    pub compose_files: Vec<&'a str>,
+    /// Compose profiles to enable
+    pub profiles: &'a [String],
+    /// Extra environment variables for compose
+    pub env: &'a BTreeMap<String, String>,
+}
+
+impl Project<'_> {
+    /// Builds `<runtime> compose -p NAME [-f FILE]... [--profile PROFILE]...`
+    /// running in the project directory.
+    pub fn command(&self, runtime: Runtime) -> Command {
+        self.command_with_overrides(runtime, &[])
+    }
+
+    /// Like [`command`](Self::command), with `overrides` merged over the
+    /// project's own compose files.
+    pub fn command_with_overrides(&self, runtime: Runtime, overrides: &[&Path]) -> Command {
+        let mut command = Command::new(runtime.binary());
+        command
+            .args(["compose", "-p", &self.name])
+            .current_dir(&*self.dir)
+            .envs(self.env);
+        if !self.compose_files.is_empty() {
+            for file in &self.compose_files {
+                command.arg("-f").arg(file);
+            }
+        } else if !overrides.is_empty() {
+            // Naming any file stops compose from looking for its default ones
+            let dir = Path::new(&*self.dir);
+            let defaults = [
+                ["compose.yaml", "compose.override.yaml"],
+                ["compose.yml", "compose.override.yml"],
+                ["docker-compose.yaml", "docker-compose.override.yaml"],
+                ["docker-compose.yml", "docker-compose.override.yml"],
+            ];
+            if let Some(files) = defaults.iter().find(|[file, _]| dir.join(file).is_file()) {
+                for file in files.iter().filter(|file| dir.join(file).is_file()) {
+                    command.arg("-f").arg(file);
+                }
+            }
+        }
+        for file in overrides {
+            command.arg("-f").arg(file);
+        }
+        for profile in self.profiles {
+            command.arg("--profile").arg(profile);
+        }
+        command
+    }
+
+    /// The working directory compose records for the project, which is the
+    /// directory of the first compose file.
+    pub fn working_dir(&self) -> PathBuf {
+        let dir = Path::new(&*self.dir);
+        self.compose_files
+            .first()
+            .and_then(|compose_file| dir.join(compose_file).parent().map(Path::to_path_buf))
+            .unwrap_or_else(|| dir.to_path_buf())
+    }
+}
+
+/// How the shared network is created, from `[settings.network_options]`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkOptions {
+    /// Network driver, the runtime's default when unset
+    pub driver: Option<String>,
+    /// Subnet in CIDR notation
+    pub subnet: Option<String>,
+    /// Range to allocate container addresses from
+    pub ip_range: Option<String>,
+    /// Gateway address of the subnet
+    pub gateway: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+}
+
+impl NetworkOptions {
+    pub fn is_default(&self) -> bool {
+        *self == NetworkOptions::default()
+    }
+}
+
+/// Error message of a failed Docker operation.
+pub type Error = String;
+
+/// What [`DockerBackend::prune`] removes from a compose project.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Leftover {
+    StoppedContainers,
+    DanglingImages,
+    UnusedVolumes,
+}
+
+impl Leftover {
+    pub const ALL: [Leftover; 3] = [
+        Leftover::StoppedContainers,
+        Leftover::DanglingImages,
+        Leftover::UnusedVolumes,
+    ];
+
+    /// The kind of object the runtime's `prune` subcommand belongs to.
+    fn object(self) -> &'static str {
+        match self {
+            Leftover::StoppedContainers => "container",
+            Leftover::DanglingImages => "image",
+            Leftover::UnusedVolumes => "volume",
+        }
+    }
+
+    pub fn describe(self) -> &'static str {
+        match self {
+            Leftover::StoppedContainers => "stopped containers",
+            Leftover::DanglingImages => "dangling images",
+            Leftover::UnusedVolumes => "unused volumes",
+        }
+    }
+}
+
+/// A container as [`DockerBackend::inspect_container`] sees it.
+#[derive(Debug, Clone)]
+pub struct ContainerInfo {
+    /// Name without the leading slash Docker reports
+    pub name: String,
+    pub labels: BTreeMap<String, String>,
+}
+
+impl ContainerInfo {
+    /// Reads the output of `container inspect` or the Engine API, which share
+    /// the same layout.
+    fn parse(container: &serde_json::Value) -> Self {
+        ContainerInfo {
+            name: container["Name"]
+                .as_str()
+                .unwrap_or_default()
+                .trim_start_matches('/')
+                .to_string(),
+            labels: serde_json::from_value(container["Config"]["Labels"].clone())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// The label compose puts on the containers, images and volumes of a project.
+fn project_label(project_name: &str) -> String {
+    format!("com.docker.compose.project={}", project_name)
+}
+
+pub trait DockerBackend: Sync {
+    /// Checks that the runtime, and its daemon if it has one, answers.
+    fn ping(&self, runner: &Runner) -> Result<(), Error>;
+
+    /// Checks whether a network with this name exists.
+    fn network_exists(&self, runner: &Runner, network: &str) -> Result<bool, Error>;
+
+    /// Creates a network.
+    fn create_network(
+        &self,
+        runner: &Runner,
+        network: &str,
+        options: &NetworkOptions,
+    ) -> Result<(), Error>;
+
+    /// Lists the IDs of the running containers of a compose project.
+    fn project_containers(&self, runner: &Runner, project: &Project) -> Result<Vec<String>, Error>;
+
+    /// Lists the IDs of all containers attached to a network, running or not.
+    fn network_containers(&self, runner: &Runner, network: &str) -> Result<Vec<String>, Error>;
+
+    /// Detaches a container from a network.
+    fn disconnect_network(
+        &self,
+        runner: &Runner,
+        network: &str,
+        container: &str,
+    ) -> Result<(), Error>;
+
+    /// Removes a network without containers.
+    fn remove_network(&self, runner: &Runner, network: &str) -> Result<(), Error>;
+
+    /// The address of a container on a network, `None` when it isn't attached.
+    fn container_address(
+        &self,
+        runner: &Runner,
+        network: &str,
+        container: &str,
+    ) -> Result<Option<String>, Error>;
+
+    /// The name and labels of a container.
+    fn inspect_container(&self, runner: &Runner, container: &str) -> Result<ContainerInfo, Error>;
+
+    /// Stops and removes a container.
+    fn remove_container(&self, runner: &Runner, container: &str) -> Result<(), Error>;
+
+    /// Removes the leftovers labelled with a compose project name and returns
+    /// how many were removed.
+    fn prune(
+        &self,
+        runner: &Runner,
+        leftover: Leftover,
+        project_name: &str,
+    ) -> Result<usize, Error>;
+}
+
+/// Picks the Engine API for Docker when available and the runtime's CLI otherwise.
+pub fn backend(runtime: Runtime) -> Box<dyn DockerBackend> {
+    #[cfg(all(unix, feature = "docker-api"))]
+    if runtime == Runtime::Docker {
+        if let Some(api) = api::ApiBackend::connect() {
+            return Box::new(api);
+        }
+    }
+    Box::new(CliBackend { runtime })
+}
+
+/// Shells out to the runtime's binary.
+pub struct CliBackend {
+    runtime: Runtime,
+}
+
+fn stderr_of(output: &std::process::Output) -> Error {
+    String::from_utf8_lossy(&output.stderr)
+        .trim_end()
+        .to_string()
+}
+
+impl DockerBackend for CliBackend {
+    fn ping(&self, runner: &Runner) -> Result<(), Error> {
+        let version_command = runner
+            .query(Command::new(self.runtime.binary()).arg("version"))
+            .map_err(|err| {
+                format!(
+                    "Failed to execute {} version command: {}",
+                    self.runtime.binary(),
+                    err
+                )
+            })?;
+        if !version_command.status.success() {
+            return Err(String::from_utf8_lossy(&version_command.stderr)
+                .trim_end()
+                .to_string());
+        }
+        Ok(())
+    }
+
+    fn network_exists(&self, runner: &Runner, network: &str) -> Result<bool, Error> {
+        let inspect_command = runner
+            .query(Command::new(self.runtime.binary()).args(["network", "inspect", network]))
+            .map_err(|err| {
+                format!(
+                    "Failed to execute {} network inspect command: {}",
+                    self.runtime.binary(),
+                    err
+                )
+            })?;
+        Ok(inspect_command.status.success())
+    }
+
+    fn create_network(
+        &self,
+        runner: &Runner,
+        network: &str,
+        options: &NetworkOptions,
+    ) -> Result<(), Error> {
+        let mut create_command = Command::new(self.runtime.binary());
+        create_command.args(["network", "create"]);
+        let flags = [
+            ("--driver", &options.driver),
+            ("--subnet", &options.subnet),
+            ("--ip-range", &options.ip_range),
+            ("--gateway", &options.gateway),
+        ];
+        for (flag, value) in flags {
+            if let Some(value) = value {
+                create_command.arg(flag).arg(value);
+            }
+        }
+        for (key, value) in &options.labels {
+            create_command
+                .arg("--label")
+                .arg(format!("{}={}", key, value));
+        }
+        create_command.arg(network);
+
+        let create_command = runner.run(&mut create_command).map_err(|err| {
+            format!(
+                "Failed to execute {} network create command: {}",
+                self.runtime.binary(),
+                err
+            )
+        })?;
+        if create_command.status.success() {
+            Ok(())
+        } else {
+            Err(stderr_of(&create_command))
+        }
+    }
+
+    fn project_containers(&self, runner: &Runner, project: &Project) -> Result<Vec<String>, Error> {
+        let ps_command = runner
+            .query(
+                project
+                    .command(self.runtime)
+                    .args(["ps", "--format", "{{.ID}}"]),
+            )
+            .map_err(|err| {
+                format!(
+                    "Failed to execute {} compose ps command: {}",
+                    self.runtime.binary(),
+                    err
+                )
+            })?;
+        if !ps_command.status.success() {
+            return Err(stderr_of(&ps_command));
+        }
+        Ok(String::from_utf8_lossy(&ps_command.stdout)
+            .split_whitespace()
+            .map(String::from)
+            .collect())
+    }
+
+    fn network_containers(&self, runner: &Runner, network: &str) -> Result<Vec<String>, Error> {
+        let ps_command = runner
+            .query(Command::new(self.runtime.binary()).args([
+                "ps",
+                "--all",
+                "--quiet",
+                "--filter",
+                &format!("network={}", network),
+            ]))
+            .map_err(|err| {
+                format!(
+                    "Failed to execute {} ps command: {}",
+                    self.runtime.binary(),
+                    err
+                )
+            })?;
+        if !ps_command.status.success() {
+            return Err(stderr_of(&ps_command));
+        }
+        Ok(String::from_utf8_lossy(&ps_command.stdout)
+            .split_whitespace()
+            .map(String::from)
+            .collect())
+    }
+
+    fn disconnect_network(
+        &self,
+        runner: &Runner,
+        network: &str,
+        container: &str,
+    ) -> Result<(), Error> {
+        let disconnect_command = runner
+            .run(Command::new(self.runtime.binary()).args([
+                "network",
+                "disconnect",
+                "--force",
+                network,
+                container,
+            ]))
+            .map_err(|err| {
+                format!(
+                    "Failed to execute {} network disconnect command: {}",
+                    self.runtime.binary(),
+                    err
+                )
+            })?;
+        if disconnect_command.status.success() {
+            Ok(())
+        } else {
+            Err(stderr_of(&disconnect_command))
+        }
+    }
+
+    fn remove_network(&self, runner: &Runner, network: &str) -> Result<(), Error> {
+        let remove_command = runner
+            .run(Command::new(self.runtime.binary()).args(["network", "rm", network]))
+            .map_err(|err| {
+                format!(
+                    "Failed to execute {} network rm command: {}",
+                    self.runtime.binary(),
+                    err
+                )
+            })?;
+        if remove_command.status.success() {
+            Ok(())
+        } else {
+            Err(stderr_of(&remove_command))
+        }
+    }
+
+    fn container_address(
+        &self,
+        runner: &Runner,
+        network: &str,
+        container: &str,
+    ) -> Result<Option<String>, Error> {
+        let inspect_command = runner
+            .query(Command::new(self.runtime.binary()).args([
+                "inspect",
+                "--format",
+                "{{json .NetworkSettings.Networks}}",
+                container,
+            ]))
+            .map_err(|err| {
+                format!(
+                    "Failed to execute {} inspect command: {}",
+                    self.runtime.binary(),
+                    err
+                )
+            })?;
+        if !inspect_command.status.success() {
+            return Err(stderr_of(&inspect_command));
+        }
+        let networks: serde_json::Value =
+            serde_json::from_slice(&inspect_command.stdout).map_err(|err| {
+                format!(
+                    "Unexpected {} inspect output: {}",
+                    self.runtime.binary(),
+                    err
+                )
+            })?;
+        Ok(networks[network]["IPAddress"]
+            .as_str()
+            .filter(|address| !address.is_empty())
+            .map(String::from))
+    }
+
+    fn inspect_container(&self, runner: &Runner, container: &str) -> Result<ContainerInfo, Error> {
+        let inspect_command = runner
+            .query(Command::new(self.runtime.binary()).args(["container", "inspect", container]))
+            .map_err(|err| {
+                format!(
+                    "Failed to execute {} container inspect command: {}",
+                    self.runtime.binary(),
+                    err
+                )
+            })?;
+        if !inspect_command.status.success() {
+            return Err(stderr_of(&inspect_command));
+        }
+        let containers: serde_json::Value = serde_json::from_slice(&inspect_command.stdout)
+            .map_err(|err| {
+                format!(
+                    "Unexpected {} container inspect output: {}",
+                    self.runtime.binary(),
+                    err
+                )
+            })?;
+        Ok(ContainerInfo::parse(&containers[0]))
+    }
+
+    fn remove_container(&self, runner: &Runner, container: &str) -> Result<(), Error> {
+        let remove_command = runner
+            .run(Command::new(self.runtime.binary()).args(["rm", "--force", container]))
+            .map_err(|err| {
+                format!(
+                    "Failed to execute {} rm command: {}",
+                    self.runtime.binary(),
+                    err
+                )
+            })?;
+        if remove_command.status.success() {
+            Ok(())
+        } else {
+            Err(stderr_of(&remove_command))
+        }
+    }
+
+    fn prune(
+        &self,
+        runner: &Runner,
+        leftover: Leftover,
+        project_name: &str,
+    ) -> Result<usize, Error> {
+        let object = leftover.object();
+        let mut command = Command::new(self.runtime.binary());
+        command.args([
+            object,
+            "prune",
+            "--force",
+            "--filter",
+            &format!("label={}", project_label(project_name)),
+        ]);
+        // Docker 23 and later only prune anonymous volumes without it, while
+        // the project's volumes are named
+        if leftover == Leftover::UnusedVolumes && self.runtime == Runtime::Docker {
+            command.arg("--all");
+        }
+        let prune_command = runner.run(&mut command).map_err(|err| {
+            format!(
+                "Failed to execute {} {} prune command: {}",
+                self.runtime.binary(),
+                object,
+                err
+            )
+        })?;
+        if !prune_command.status.success() {
+            return Err(stderr_of(&prune_command));
+        }
+        // One ID per line under a "Deleted ...:" heading, untagged image names aside
+        Ok(String::from_utf8_lossy(&prune_command.stdout)
+            .lines()
+            .map(str::trim)
+            .filter(|line| {
+                !line.is_empty()
+                    && !line.ends_with(':')
+                    && !line.starts_with("untagged:")
+                    && !line.starts_with("Total reclaimed space")
+            })
+            .count())
+    }
+}
+
+#[cfg(all(unix, feature = "docker-api"))]
+mod api {
+    use super::{
+        project_label, ContainerInfo, DockerBackend, Error, Leftover, NetworkOptions, Project,
+    };
+    use crate::output::Runner;
+    use serde_json::{json, Value};
+    use std::io::{self, Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::path::PathBuf;
+    use std::time::Instant;
+
+    const DEFAULT_SOCKET: &str = "/var/run/docker.sock";
+
+    /// Talks to the Docker Engine HTTP API over its unix socket.
+    pub struct ApiBackend {
+        socket: PathBuf,
+    }
+
+    impl ApiBackend {
+        /// Returns a backend if the daemon socket accepts connections.
+        pub fn connect() -> Option<Self> {
+            let socket = match std::env::var("DOCKER_HOST") {
+                Ok(host) => PathBuf::from(host.strip_prefix("unix://")?),
+                Err(_) => PathBuf::from(DEFAULT_SOCKET),
+            };
+            UnixStream::connect(&socket).ok()?;
+            Some(ApiBackend { socket })
+        }
+
+        /// Sends one request and returns the status code and body.
+        fn request(
+            &self,
+            method: &str,
+            path: &str,
+            body: Option<&Value>,
+        ) -> io::Result<(u16, String)> {
+            let body = body.map(Value::to_string).unwrap_or_default();
+            let started = Instant::now();
+            let mut stream = UnixStream::connect(&self.socket)?;
+            // HTTP/1.0 makes the daemon close the connection without chunked encoding
+            write!(
+                stream,
+                "{} {} HTTP/1.0\r\nHost: docker\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                method,
+                path,
+                body.len(),
+                body
+            )?;
+
+            let mut response = String::new();
+            stream.read_to_string(&mut response)?;
+            let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+            let status = head
+                .split_whitespace()
+                .nth(1)
+                .and_then(|code| code.parse().ok())
+                .ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "Malformed HTTP response")
+                })?;
+            tracing::debug!(method, path, status, duration = ?started.elapsed(), "Docker API request");
+            Ok((status, body.to_string()))
+        }
+
+        /// Sends a request that changes state and records it for the audit log.
+        fn send(
+            &self,
+            runner: &Runner,
+            method: &str,
+            path: &str,
+            body: Option<&Value>,
+        ) -> io::Result<(u16, String)> {
+            let response = self.request(method, path, body);
+            let status = response.as_ref().ok().map(|(status, _)| *status);
+            runner
+                .output
+                .record_request(format!("{} {}", method, path), status);
+            response
+        }
+
+        /// Sends a request that changes state; in dry-run mode it is only printed.
+        fn mutate(
+            &self,
+            runner: &Runner,
+            method: &str,
+            path: &str,
+            body: Option<&Value>,
+        ) -> Result<(), Error> {
+            if runner.dry_run {
+                let body = body.map(|body| format!(" {}", body)).unwrap_or_default();
+                runner
+                    .output
+                    .notice(format!("Would call: {} {}{}", method, path, body));
+                return Ok(());
+            }
+            match self.send(runner, method, path, body) {
+                Ok((200..=299, _)) => Ok(()),
+                Ok((_, body)) => Err(api_message(&body)),
+                Err(err) => Err(format!("Failed to reach the Docker API: {}", err)),
+            }
+        }
+    }
+
+    /// Extracts the `message` the Engine API returns on errors.
+    fn api_message(body: &str) -> Error {
+        serde_json::from_str::<Value>(body)
+            .ok()
+            .and_then(|value| value["message"].as_str().map(String::from))
+            .unwrap_or_else(|| body.trim().to_string())
+    }
+
+    /// Percent-encodes a query string value.
+    fn encode(value: &str) -> String {
+        value
+            .bytes()
+            .map(|byte| match byte {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                    (byte as char).to_string()
+                }
+                _ => format!("%{:02X}", byte),
+            })
+            .collect()
+    }
+
+    impl DockerBackend for ApiBackend {
+        fn ping(&self, _runner: &Runner) -> Result<(), Error> {
+            match self.request("GET", "/_ping", None) {
+                Ok((200, _)) => Ok(()),
+                Ok((_, body)) => Err(api_message(&body)),
+                Err(err) => Err(format!("Failed to reach the Docker API: {}", err)),
+            }
+        }
+
+        fn network_exists(&self, _runner: &Runner, network: &str) -> Result<bool, Error> {
+            match self.request("GET", &format!("/networks/{}", encode(network)), None) {
+                Ok((200, _)) => Ok(true),
+                Ok((404, _)) => Ok(false),
+                Ok((_, body)) => Err(api_message(&body)),
+                Err(err) => Err(format!("Failed to reach the Docker API: {}", err)),
+            }
+        }
+
+        fn create_network(
+            &self,
+            runner: &Runner,
+            network: &str,
+            options: &NetworkOptions,
+        ) -> Result<(), Error> {
+            let mut body = json!({ "Name": network, "Labels": options.labels });
+            if let Some(ref driver) = options.driver {
+                body["Driver"] = json!(driver);
+            }
+            if options.subnet.is_some() || options.ip_range.is_some() || options.gateway.is_some() {
+                let mut config = json!({});
+                if let Some(ref subnet) = options.subnet {
+                    config["Subnet"] = json!(subnet);
+                }
+                if let Some(ref ip_range) = options.ip_range {
+                    config["IPRange"] = json!(ip_range);
+                }
+                if let Some(ref gateway) = options.gateway {
+                    config["Gateway"] = json!(gateway);
+                }
+                body["IPAM"] = json!({ "Config": [config] });
+            }
+            self.mutate(runner, "POST", "/networks/create", Some(&body))
+        }
+
+        fn project_containers(
+            &self,
+            _runner: &Runner,
+            project: &Project,
+        ) -> Result<Vec<String>, Error> {
+            let filters = json!({ "label": [project_label(&project.name)] });
+
+            let path = format!("/containers/json?filters={}", encode(&filters.to_string()));
+            let body = match self.request("GET", &path, None) {
+                Ok((200, body)) => body,
+                Ok((_, body)) => return Err(api_message(&body)),
+                Err(err) => return Err(format!("Failed to reach the Docker API: {}", err)),
+            };
+            let containers: Vec<Value> = serde_json::from_str(&body)
+                .map_err(|err| format!("Unexpected Docker API response: {}", err))?;
+            Ok(containers
+                .iter()
+                .filter_map(|container| container["Id"].as_str().map(String::from))
+                .collect())
+        }
+
+        fn network_containers(
+            &self,
+            _runner: &Runner,
+            network: &str,
+        ) -> Result<Vec<String>, Error> {
+            let body = match self.request("GET", &format!("/networks/{}", encode(network)), None) {
+                Ok((200, body)) => body,
+                Ok((_, body)) => return Err(api_message(&body)),
+                Err(err) => return Err(format!("Failed to reach the Docker API: {}", err)),
+            };
+            let network: Value = serde_json::from_str(&body)
+                .map_err(|err| format!("Unexpected Docker API response: {}", err))?;
+            Ok(network["Containers"]
+                .as_object()
+                .map(|containers| containers.keys().cloned().collect())
+                .unwrap_or_default())
+        }
+
+        fn disconnect_network(
+            &self,
+            runner: &Runner,
+            network: &str,
+            container: &str,
+        ) -> Result<(), Error> {
+            self.mutate(
+                runner,
+                "POST",
+                &format!("/networks/{}/disconnect", encode(network)),
+                Some(&json!({ "Container": container, "Force": true })),
+            )
+        }
+
+        fn remove_network(&self, runner: &Runner, network: &str) -> Result<(), Error> {
+            self.mutate(
+                runner,
+                "DELETE",
+                &format!("/networks/{}", encode(network)),
+                None,
+            )
+        }
+
+        fn container_address(
+            &self,
+            _runner: &Runner,
+            network: &str,
+            container: &str,
+        ) -> Result<Option<String>, Error> {
+            let path = format!("/containers/{}/json", encode(container));
+            let body = match self.request("GET", &path, None) {
+                Ok((200, body)) => body,
+                Ok((_, body)) => return Err(api_message(&body)),
+                Err(err) => return Err(format!("Failed to reach the Docker API: {}", err)),
+            };
+            let container: Value = serde_json::from_str(&body)
+                .map_err(|err| format!("Unexpected Docker API response: {}", err))?;
+            Ok(
+                container["NetworkSettings"]["Networks"][network]["IPAddress"]
+                    .as_str()
+                    .filter(|address| !address.is_empty())
+                    .map(String::from),
+            )
+        }
+
+        fn inspect_container(
+            &self,
+            _runner: &Runner,
+            container: &str,
+        ) -> Result<ContainerInfo, Error> {
+            let path = format!("/containers/{}/json", encode(container));
+            let body = match self.request("GET", &path, None) {
+                Ok((200, body)) => body,
+                Ok((_, body)) => return Err(api_message(&body)),
+                Err(err) => return Err(format!("Failed to reach the Docker API: {}", err)),
+            };
+            let container: Value = serde_json::from_str(&body)
+                .map_err(|err| format!("Unexpected Docker API response: {}", err))?;
+            Ok(ContainerInfo::parse(&container))
+        }
+
+        fn remove_container(&self, runner: &Runner, container: &str) -> Result<(), Error> {
+            self.mutate(
+                runner,
+                "DELETE",
+                &format!("/containers/{}?force=true", encode(container)),
+                None,
+            )
+        }
+
+        fn prune(
+            &self,
+            runner: &Runner,
+            leftover: Leftover,
+            project_name: &str,
+        ) -> Result<usize, Error> {
+            let mut filters = json!({ "label": [project_label(project_name)] });
+            match leftover {
+                Leftover::DanglingImages => filters["dangling"] = json!(["true"]),
+                // Like `volume prune --all`, named volumes too
+                Leftover::UnusedVolumes => filters["all"] = json!(["true"]),
+                Leftover::StoppedContainers => {}
+            }
+            let path = format!(
+                "/{}s/prune?filters={}",
+                leftover.object(),
+                encode(&filters.to_string())
+            );
+            if runner.dry_run {
+                self.mutate(runner, "POST", &path, None)?;
+                return Ok(0);
+            }
+            let body = match self.send(runner, "POST", &path, None) {
+                Ok((200, body)) => body,
+                Ok((_, body)) => return Err(api_message(&body)),
+                Err(err) => return Err(format!("Failed to reach the Docker API: {}", err)),
+            };
+            let pruned: Value = serde_json::from_str(&body)
+                .map_err(|err| format!("Unexpected Docker API response: {}", err))?;
+            let deleted = |key: &str| pruned[key].as_array().map_or(0, Vec::len);
+            Ok(match leftover {
+                Leftover::StoppedContainers => deleted("ContainersDeleted"),
+                // Untagging a name is listed next to deleting the image
+                Leftover::DanglingImages => {
+                    pruned["ImagesDeleted"].as_array().map_or(0, |images| {
+                        images
+                            .iter()
+                            .filter(|image| image.get("Deleted").is_some())
+                            .count()
+                    })
+                }
+                Leftover::UnusedVolumes => deleted("VolumesDeleted"),
+            })
+        }
+    }
+}
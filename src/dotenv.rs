@@ -0,0 +1,199 @@
+//! Managed `.env` files rendered from the `dotenv` table of a configuration.
+//!
+//! Values may reference other variables as `$NAME` or `${NAME}`, resolved from
+//! the other keys of the same table, the built-in `COMPHOST_*` variables and the
+//! environment comphost runs in. `$$` is a literal dollar sign.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// First line of every generated file, used to recognize files comphost owns.
+const HEADER: &str = "# Generated by comphost from config.toml, edits will be overwritten";
+
+/// Renders the variables of a configuration into the contents of a `.env` file.
+///
+/// `builtins` are available for interpolation but not written to the file.
+pub fn render(
+    values: &BTreeMap<String, String>,
+    builtins: &BTreeMap<&str, String>,
+) -> Result<String, String> {
+    let mut resolved = BTreeMap::new();
+    let mut content = format!("{}\n", HEADER);
+    for key in values.keys() {
+        let value = resolve(key, values, builtins, &mut resolved, &mut Vec::new())?;
+        content.push_str(&format!("{}={}\n", key, quote(&value)));
+    }
+    Ok(content)
+}
+
+/// Interpolates the value of `key`, resolving referenced keys first.
+fn resolve<'a>(
+    key: &'a str,
+    values: &'a BTreeMap<String, String>,
+    builtins: &BTreeMap<&str, String>,
+    resolved: &mut BTreeMap<&'a str, String>,
+    stack: &mut Vec<&'a str>,
+) -> Result<String, String> {
+    if let Some(value) = resolved.get(key) {
+        return Ok(value.clone());
+    }
+    if stack.contains(&key) {
+        return Err(format!("Variable '{}' refers to itself", key));
+    }
+
+    stack.push(key);
+    let value = interpolate(&values[key], |name| {
+        if let Some((name, _)) = values.get_key_value(name) {
+            return resolve(name, values, builtins, resolved, stack);
+        }
+        builtins
+            .get(name)
+            .cloned()
+            .or_else(|| std::env::var(name).ok())
+            .ok_or_else(|| format!("Unknown variable '{}' in '{}'", name, key))
+    })?;
+    stack.pop();
+
+    resolved.insert(key, value.clone());
+    Ok(value)
+}
+
+/// Writes `content` to `.env` in `dir`, refusing to replace a file comphost
+/// didn't generate.
+pub fn write(dir: &Path, content: &str) -> io::Result<()> {
+    let path = dir.join(".env");
+    if let Ok(existing) = fs::read_to_string(&path) {
+        if existing == content {
+            return Ok(());
+        }
+        if !existing.starts_with(HEADER) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("'{}' exists and is not managed by comphost", path.display()),
+            ));
+        }
+    }
+    fs::write(path, content)
+}
+
+/// Replaces `$NAME` and `${NAME}` using `lookup`, stopping at its first error.
+fn interpolate(
+    value: &str,
+    mut lookup: impl FnMut(&str) -> Result<String, String>,
+) -> Result<String, String> {
+    let mut result = String::new();
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            result.push(c);
+            continue;
+        }
+
+        let name: String = match chars.peek() {
+            Some('$') => {
+                chars.next();
+                result.push('$');
+                continue;
+            }
+            Some('{') => {
+                chars.next();
+                let name: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                name
+            }
+            _ => {
+                let mut name = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                    name.push(c);
+                }
+                if name.is_empty() {
+                    result.push('$');
+                    continue;
+                }
+                name
+            }
+        };
+        result.push_str(&lookup(&name)?);
+    }
+    Ok(result)
+}
+
+/// Quotes a value so compose reads it back verbatim.
+fn quote(value: &str) -> String {
+    let plain = value
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "-_./:@,+".contains(c));
+    if plain {
+        value.to_string()
+    } else if !value.contains('\'') {
+        // Single quotes keep compose from interpolating the value again
+        format!("'{}'", value)
+    } else {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(values: &[(&str, &str)]) -> BTreeMap<String, String> {
+        values
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn values_are_interpolated_and_quoted() {
+        let builtins = BTreeMap::from([("COMPHOST_NAME", "api".to_string())]);
+        let rendered = render(
+            &values(&[
+                ("DATABASE_URL", "postgres://${DB_HOST}:5432/$COMPHOST_NAME"),
+                ("DB_HOST", "db"),
+                ("GREETING", "hello world"),
+                ("PRICE", "$$5 and $"),
+                ("QUOTE", "it's"),
+            ]),
+            &builtins,
+        )
+        .unwrap();
+        assert_eq!(
+            rendered,
+            format!(
+                "{}\nDATABASE_URL=postgres://db:5432/api\nDB_HOST=db\nGREETING='hello world'\nPRICE='$5 and $'\nQUOTE=\"it's\"\n",
+                HEADER
+            )
+        );
+    }
+
+    #[test]
+    fn unknown_and_circular_variables_are_errors() {
+        let builtins = BTreeMap::new();
+        let unknown = render(
+            &values(&[("A", "${COMPHOST_TEST_SURELY_UNSET}")]),
+            &builtins,
+        );
+        assert!(unknown.unwrap_err().contains("COMPHOST_TEST_SURELY_UNSET"));
+        let circular = render(&values(&[("A", "$B"), ("B", "${A}")]), &builtins);
+        assert!(circular.unwrap_err().contains("refers to itself"));
+    }
+
+    #[test]
+    fn only_generated_files_are_replaced() {
+        let temp = crate::temp::TempDir::create("dotenv-test").unwrap();
+        let dir = temp.path();
+        let content = format!("{}\nA=1\n", HEADER);
+        write(dir, &content).unwrap();
+        write(dir, &format!("{}\nA=2\n", HEADER)).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join(".env")).unwrap(),
+            format!("{}\nA=2\n", HEADER)
+        );
+
+        fs::write(dir.join(".env"), "A=mine\n").unwrap();
+        assert!(write(dir, &content).is_err());
+        assert_eq!(fs::read_to_string(dir.join(".env")).unwrap(), "A=mine\n");
+    }
+}
@@ -0,0 +1,111 @@
+//! Encryption of the config file at rest with a passphrase, turned on with
+//! `comphost encrypt` and off with `comphost decrypt`.
+//!
+//! An encrypted config is an ASCII-armored age file, decrypted when it is
+//! loaded and encrypted again when it is saved. The passphrase comes from
+//! `COMPHOST_PASSPHRASE`, from the output of `COMPHOST_PASSPHRASE_COMMAND`,
+//! which can read it from the OS keychain, or is asked for once per run.
+
+use crate::error::Error;
+use age::secrecy::SecretString;
+use std::env;
+use std::io::{self, IsTerminal};
+use std::process::Command;
+use std::sync::Mutex;
+
+/// How every encrypted config starts.
+const ARMOR_BEGIN: &str = "-----BEGIN AGE ENCRYPTED FILE-----";
+
+/// The passphrase of this run, so it is only asked for once.
+static PASSPHRASE: Mutex<Option<SecretString>> = Mutex::new(None);
+
+/// Whether `content` is an encrypted config.
+pub fn is_encrypted(content: &str) -> bool {
+    content.trim_start().starts_with(ARMOR_BEGIN)
+}
+
+/// The passphrase for the config at `path`. A new one is asked for twice.
+pub fn passphrase(path: &str, new: bool) -> Result<SecretString, Error> {
+    let mut cached = PASSPHRASE.lock().unwrap();
+    if let (false, Some(passphrase)) = (new, cached.as_ref()) {
+        return Ok(passphrase.clone());
+    }
+    let error = |message: String| Error::Passphrase {
+        path: path.to_string(),
+        message,
+    };
+
+    let passphrase = if let Ok(passphrase) = env::var("COMPHOST_PASSPHRASE") {
+        passphrase
+    } else if let Ok(command) = env::var("COMPHOST_PASSPHRASE_COMMAND") {
+        #[cfg(windows)]
+        let output = Command::new("cmd").arg("/C").arg(&command).output();
+        #[cfg(not(windows))]
+        let output = Command::new("sh").arg("-c").arg(&command).output();
+        let output =
+            output.map_err(|err| error(format!("Failed to run '{}': {}", command, err)))?;
+        if !output.status.success() {
+            return Err(error(format!(
+                "'{}' failed: {}",
+                command,
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            )));
+        }
+        String::from_utf8_lossy(&output.stdout)
+            .trim_end_matches(['\r', '\n'])
+            .to_string()
+    } else if io::stdin().is_terminal() {
+        let prompt = |question: &str| {
+            rpassword::prompt_password(question)
+                .map_err(|err| error(format!("Failed to read the passphrase: {}", err)))
+        };
+        let passphrase = prompt(&format!("Passphrase for '{}': ", path))?;
+        if new && prompt("Repeat the passphrase: ")? != passphrase {
+            return Err(error("the passphrases don't match".to_string()));
+        }
+        passphrase
+    } else {
+        return Err(error(
+            "set COMPHOST_PASSPHRASE or COMPHOST_PASSPHRASE_COMMAND".to_string(),
+        ));
+    };
+    if passphrase.is_empty() {
+        return Err(error("the passphrase is empty".to_string()));
+    }
+
+    let passphrase = SecretString::from(passphrase);
+    *cached = Some(passphrase.clone());
+    Ok(passphrase)
+}
+
+/// Encrypts a config with `passphrase`.
+pub fn encrypt(content: &str, passphrase: &SecretString) -> Result<String, String> {
+    let recipient = age::scrypt::Recipient::new(passphrase.clone());
+    age::encrypt_and_armor(&recipient, content.as_bytes()).map_err(|err| err.to_string())
+}
+
+/// Decrypts a config encrypted with `passphrase`.
+pub fn decrypt(content: &str, passphrase: &SecretString) -> Result<String, String> {
+    let identity = age::scrypt::Identity::new(passphrase.clone());
+    let plaintext = age::decrypt(&identity, content.as_bytes()).map_err(|err| err.to_string())?;
+    String::from_utf8(plaintext).map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configs_decrypt_with_their_passphrase_only() {
+        let passphrase = SecretString::from("correct horse".to_string());
+        let content = "version = 1\n\n[configs.api]\nurl = \"https://example.com/api.git\"\n";
+        let encrypted = encrypt(content, &passphrase).unwrap();
+        assert!(is_encrypted(&encrypted));
+        assert!(!is_encrypted(content));
+        assert!(!encrypted.contains("example.com"));
+        assert_eq!(decrypt(&encrypted, &passphrase).unwrap(), content);
+
+        let wrong = SecretString::from("battery staple".to_string());
+        assert!(decrypt(&encrypted, &wrong).is_err());
+    }
+}
@@ -0,0 +1,98 @@
+//! Errors that keep a command from running at all, and the exit codes comphost
+//! finishes with.
+//!
+//! Failures of individual operations, like one project that doesn't start, are
+//! reported as failed events instead and only change the exit code, which
+//! tells scripts what kind of failure happened.
+
+use std::io;
+use thiserror::Error;
+
+/// Every operation succeeded or was skipped.
+pub const EXIT_OK: u8 = 0;
+/// At least one operation failed.
+pub const EXIT_FAILED: u8 = 1;
+/// comphost couldn't load or save its config.
+pub const EXIT_ERROR: u8 = 2;
+/// The config file isn't valid TOML or doesn't have the expected layout.
+pub const EXIT_INVALID_CONFIG: u8 = 3;
+/// A named configuration, profile or secret doesn't exist.
+pub const EXIT_NOT_FOUND: u8 = 4;
+/// The container runtime isn't installed or its daemon can't be reached.
+pub const EXIT_RUNTIME_UNAVAILABLE: u8 = 5;
+/// Cloning or pulling a repository failed.
+pub const EXIT_GIT_FAILED: u8 = 6;
+
+/// What kind of operation failed, which picks the exit code. When several
+/// kinds fail in one run, the one declared last wins.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Failure {
+    #[default]
+    Operation,
+    Git,
+    RuntimeUnavailable,
+    NotFound,
+}
+
+impl Failure {
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Failure::Operation => EXIT_FAILED,
+            Failure::Git => EXIT_GIT_FAILED,
+            Failure::RuntimeUnavailable => EXIT_RUNTIME_UNAVAILABLE,
+            Failure::NotFound => EXIT_NOT_FOUND,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Failed to get user's home directory")]
+    NoHome,
+    #[error("Failed to create config directory '{path}': {source}")]
+    ConfigDir { path: String, source: io::Error },
+    #[error("Failed to lock the config file: {0}")]
+    Lock(io::Error),
+    #[error("Failed to read '{path}': {source}")]
+    Read { path: String, source: io::Error },
+    #[error("Failed to parse '{path}': {source}")]
+    Parse {
+        path: String,
+        source: toml::de::Error,
+    },
+    #[error("Failed to load '{path}': {message}")]
+    Migrate { path: String, message: String },
+    #[error("Failed to serialize the config: {0}")]
+    Serialize(#[from] toml::ser::Error),
+    #[error("Failed to write '{path}': {source}")]
+    Write { path: String, source: io::Error },
+    #[error("Failed to get the passphrase of '{path}': {message}")]
+    Passphrase { path: String, message: String },
+    #[error("Failed to decrypt '{path}': {message}")]
+    Decrypt { path: String, message: String },
+    #[error("Failed to encrypt '{path}': {message}")]
+    Encrypt { path: String, message: String },
+    #[error("Invalid workspace name '{0}', use letters, digits, '-' and '_'")]
+    InvalidWorkspace(String),
+    #[error("Workspace '{0}' does not exist, create it with `comphost workspace use {0}`")]
+    UnknownWorkspace(String),
+    #[error("Failed to listen on '{path}': {source}")]
+    Listen { path: String, source: io::Error },
+    #[error("A daemon is already listening on '{0}'")]
+    DaemonRunning(String),
+    #[error("Backup '{0}' not found, see `comphost restore`")]
+    UnknownBackup(String),
+    #[error("Can't undo '{0}', the config was changed since")]
+    Undo(String),
+    #[error("Terminal error: {0}")]
+    Terminal(io::Error),
+}
+
+impl Error {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Parse { .. } | Error::Migrate { .. } => EXIT_INVALID_CONFIG,
+            _ => EXIT_ERROR,
+        }
+    }
+}
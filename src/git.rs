@@ -0,0 +1,629 @@
+//! Git operations, either through the `git` binary or through libgit2 when the
+//! `libgit2` feature is enabled and selected with `git_backend = "libgit2"`.
+
+use crate::error::Failure;
+use crate::output::{Output, Report, Runner};
+use crate::temp::TempDir;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Which implementation clones repositories.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    /// Shell out to `git`
+    #[default]
+    Cli,
+    /// Use the bundled libgit2
+    Libgit2,
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The `git` binary could not be executed.
+    #[error("Failed to execute git: {0}")]
+    Spawn(io::Error),
+    /// `git` ran for longer than its timeout and was killed.
+    #[error("{0}")]
+    TimedOut(io::Error),
+    /// `git` ran but failed, with its stderr.
+    #[error("{0}")]
+    Command(String),
+    /// libgit2 reported an error.
+    #[cfg(feature = "libgit2")]
+    #[error("{}", .0.message())]
+    Libgit2(git2::Error),
+}
+
+/// How a repository is cloned by [`GitOps::clone_repo`] and updated by
+/// [`GitOps::pull`].
+#[derive(Debug, Default, Clone)]
+pub struct CloneOptions {
+    /// Branch to check out instead of the remote's default one
+    pub branch: Option<String>,
+    /// Number of commits of history to fetch, all of them when unset
+    pub depth: Option<u32>,
+    /// Also clone the submodules, recursively
+    pub submodules: bool,
+    /// Private key to authenticate with over ssh instead of the default ones
+    pub ssh_key: Option<String>,
+    /// Stash uncommitted changes around pulls
+    pub autostash: bool,
+    /// Local repository to add a worktree of instead of cloning
+    pub worktree_of: Option<String>,
+    /// Directories to check out, everything when empty
+    pub sparse_paths: Vec<String>,
+    /// Seconds a `git clone` may run before it is killed, no limit when unset.
+    /// libgit2 clones in-process and can't be killed, so it ignores this.
+    pub timeout: Option<u64>,
+}
+
+impl CloneOptions {
+    /// The private key with a leading `~` expanded to the home directory.
+    fn ssh_key_path(&self) -> Option<PathBuf> {
+        self.ssh_key.as_deref().map(expand_home)
+    }
+
+    /// Makes `git` use the private key, if any, for ssh remotes.
+    fn apply(&self, command: &mut Command) {
+        if let Some(key) = self.ssh_key_path() {
+            let key = key.display().to_string().replace('\'', "'\\''");
+            command.env(
+                "GIT_SSH_COMMAND",
+                format!("ssh -i '{}' -o IdentitiesOnly=yes", key),
+            );
+        }
+    }
+}
+
+pub trait GitBackend: Sync {
+    /// Clones `url` into the directory `name` inside `parent_dir`.
+    fn clone_repo(
+        &self,
+        runner: &Runner,
+        url: &str,
+        parent_dir: &str,
+        name: &str,
+        options: &CloneOptions,
+    ) -> Result<(), Error>;
+}
+
+/// A shallow clone in a temporary directory, deleted when dropped.
+pub struct TempClone {
+    path: PathBuf,
+    /// Deletes the clone when dropped
+    _dir: TempDir,
+}
+
+impl TempClone {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Clones and updates the repositories of configurations.
+pub struct GitOps<'a> {
+    runner: &'a Runner<'a>,
+    backend: Box<dyn GitBackend>,
+}
+
+impl<'a> GitOps<'a> {
+    /// Uses the backend selected in the settings, see [`backend`].
+    pub fn new(runner: &'a Runner<'a>, kind: BackendKind) -> Self {
+        GitOps {
+            runner,
+            backend: backend(kind, runner.output),
+        }
+    }
+
+    /// Clones `url` into the directory `name` inside `parent_dir`, or adds a
+    /// worktree of the repository `options` name there, then narrows it down
+    /// to the sparse paths of `options`.
+    pub fn clone_repo(
+        &self,
+        url: &str,
+        parent_dir: &str,
+        name: &str,
+        options: &CloneOptions,
+    ) -> Result<(), Error> {
+        match options.worktree_of {
+            Some(ref repo) => self.add_worktree(repo, parent_dir, name, options)?,
+            None => self.runner.retry(&format!("Cloning '{}'", name), || {
+                self.backend
+                    .clone_repo(self.runner, url, parent_dir, name, options)
+            })?,
+        }
+        if options.sparse_paths.is_empty() {
+            return Ok(());
+        }
+        run_git(
+            self.runner,
+            Command::new("git")
+                .args(["sparse-checkout", "set", "--"])
+                .args(&options.sparse_paths)
+                .current_dir(Path::new(parent_dir).join(name)),
+        )
+    }
+
+    /// Adds a worktree of `repo` in the directory `name` inside `parent_dir`,
+    /// sharing its objects. Git creates a branch named `name` when `options`
+    /// has none, since a branch is only checked out in one worktree at a time.
+    fn add_worktree(
+        &self,
+        repo: &str,
+        parent_dir: &str,
+        name: &str,
+        options: &CloneOptions,
+    ) -> Result<(), Error> {
+        let path = Path::new(parent_dir).join(name);
+        let mut command = Command::new("git");
+        command
+            .arg("-C")
+            .arg(expand_home(repo))
+            .args(["worktree", "add", "--"])
+            .arg(&path);
+        command.args(&options.branch);
+        options.apply(&mut command);
+        run_git(self.runner, &mut command)?;
+        if options.submodules {
+            run_git(
+                self.runner,
+                Command::new("git")
+                    .args(["submodule", "update", "--init", "--recursive"])
+                    .current_dir(&path),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Drops what `repo` still records about worktrees deleted from disk, so
+    /// their branches can be checked out again.
+    pub fn prune_worktrees(&self, repo: &str) -> Result<(), Error> {
+        run_git(
+            self.runner,
+            Command::new("git")
+                .arg("-C")
+                .arg(expand_home(repo))
+                .args(["worktree", "prune"]),
+        )
+    }
+
+    /// Checks that a clone has `branch` checked out, reporting it when it
+    /// doesn't. Clones without an expected branch always pass.
+    pub fn verify_branch(&self, config_name: &str, clone_path: &str, branch: Option<&str>) -> bool {
+        let Some(branch) = branch else {
+            return true;
+        };
+        let head = self.runner.query(
+            Command::new("git")
+                .args(["symbolic-ref", "--quiet", "--short", "HEAD"])
+                .current_dir(clone_path),
+        );
+        let message = match head {
+            Ok(head) if head.status.success() => {
+                let current = String::from_utf8_lossy(&head.stdout).trim().to_string();
+                if current == branch {
+                    return true;
+                }
+                format!(
+                    "'{}' is on branch '{}' instead of '{}'",
+                    config_name, current, branch
+                )
+            }
+            Ok(_) => format!(
+                "'{}' is not on a branch, expected '{}'",
+                config_name, branch
+            ),
+            Err(err) => format!("Failed to execute git: {}", err),
+        };
+        let mut report = Report::new(config_name);
+        report.failed_as(Failure::Git, message, &[]);
+        self.runner.output.report(report);
+        false
+    }
+
+    /// Whether a clone has uncommitted changes to tracked files, reporting it
+    /// as skipped when it does. Untracked files don't get in the way of a pull.
+    pub fn has_changes(&self, config_name: &str, clone_path: &str) -> bool {
+        let status = self.runner.query(
+            Command::new("git")
+                .args(["status", "--porcelain", "--untracked-files=no"])
+                .current_dir(clone_path),
+        );
+        match status {
+            Ok(status) if status.status.success() && !status.stdout.trim_ascii().is_empty() => {
+                let mut report = Report::new(config_name);
+                report.skipped(format!(
+                    "'{}' has uncommitted changes, not pulling it",
+                    config_name
+                ));
+                self.runner.output.report(report);
+                true
+            }
+            // Left to the pull to report
+            _ => false,
+        }
+    }
+
+    /// Makes a shallow clone of `url` to read files from. It only touches a
+    /// temporary directory, so it also happens in dry-run mode.
+    pub fn clone_temp(&self, url: &str) -> Result<TempClone, Error> {
+        let dir = TempDir::create("clone").map_err(|err| {
+            Error::Command(format!("Failed to create a temporary directory: {}", err))
+        })?;
+        let clone = TempClone {
+            path: dir.path().join("repo"),
+            _dir: dir,
+        };
+        let output = self
+            .runner
+            .query(
+                Command::new("git")
+                    .args(["clone", "--quiet", "--depth", "1", "--", url])
+                    .arg(clone.path()),
+            )
+            .map_err(Error::Spawn)?;
+        if output.status.success() {
+            Ok(clone)
+        } else {
+            Err(Error::Command(
+                String::from_utf8_lossy(&output.stderr)
+                    .trim_end()
+                    .to_string(),
+            ))
+        }
+    }
+
+    /// The URL of the `origin` remote of an existing clone.
+    pub fn origin_url(&self, clone_path: &str) -> Result<String, Error> {
+        let output = self
+            .runner
+            .query(
+                Command::new("git")
+                    .args(["remote", "get-url", "origin"])
+                    .current_dir(clone_path),
+            )
+            .map_err(Error::Spawn)?;
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        } else {
+            Err(Error::Command(
+                String::from_utf8_lossy(&output.stderr)
+                    .trim_end()
+                    .to_string(),
+            ))
+        }
+    }
+
+    /// Pulls the clone of one configuration and reports how many commits arrived,
+    /// then brings its submodules up to date when `options` asks for them.
+    /// Returns the number of new commits, `None` if anything failed. Nothing is
+    /// pulled in dry-run mode, so there are never any new commits.
+    pub fn pull(
+        &self,
+        config_name: &str,
+        clone_path: &str,
+        options: &CloneOptions,
+    ) -> Option<usize> {
+        let runner = self.runner;
+        let mut report = Report::new(config_name);
+        let git = |args: &[&str]| {
+            let mut command = Command::new("git");
+            command.args(args).current_dir(clone_path);
+            options.apply(&mut command);
+            command
+        };
+
+        // Remember where HEAD was to count the new commits
+        let pulled = runner
+            .query(&mut git(&["rev-parse", "HEAD"]))
+            .and_then(|head_before| {
+                let head_before = String::from_utf8_lossy(&head_before.stdout)
+                    .trim()
+                    .to_string();
+                let pull_args: &[&str] = match options.autostash {
+                    true => &["pull", "--autostash"],
+                    false => &["pull"],
+                };
+                let pull_command = runner
+                    .run_retrying(&format!("Pulling '{}'", config_name), &mut git(pull_args))?;
+                Ok((head_before, pull_command))
+            });
+        let (head_before, pull_command) = match pulled {
+            Ok(pulled) => pulled,
+            Err(err) => {
+                report.failed_as(
+                    Failure::Git,
+                    format!("Failed to pull '{}' in '{}'", config_name, clone_path),
+                    err.to_string().as_bytes(),
+                );
+                runner.output.report(report);
+                return None;
+            }
+        };
+
+        if !pull_command.status.success() {
+            report.failed_as(
+                Failure::Git,
+                format!("Failed to pull '{}' in '{}'", config_name, clone_path),
+                &pull_command.stderr,
+            );
+            runner.output.report(report);
+            return None;
+        }
+        let count = if runner.dry_run {
+            0
+        } else {
+            let range = format!("{}..HEAD", head_before);
+            let count = runner
+                .query(&mut git(&["rev-list", "--count", &range]))
+                .ok()
+                .and_then(|count_command| {
+                    String::from_utf8_lossy(&count_command.stdout)
+                        .trim()
+                        .parse::<usize>()
+                        .ok()
+                })
+                .unwrap_or(0);
+            report.ok(format!("Pulled '{}', {} new commit(s)", config_name, count));
+            count
+        };
+
+        if options.submodules {
+            let updated = runner
+                .run(&mut git(&["submodule", "update", "--init", "--recursive"]))
+                .map_err(|err| err.to_string().into_bytes())
+                .and_then(|update_command| match update_command.status.success() {
+                    true => Ok(()),
+                    false => Err(update_command.stderr),
+                });
+            if let Err(stderr) = updated {
+                report.failed_as(
+                    Failure::Git,
+                    format!("Failed to update the submodules of '{}'", config_name),
+                    &stderr,
+                );
+                runner.output.report(report);
+                return None;
+            }
+        }
+        runner.output.report(report);
+        Some(count)
+    }
+}
+
+/// `path` with a leading `~` expanded to the home directory.
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Runs a state-changing `git` command, failing with its stderr.
+fn run_git(runner: &Runner, command: &mut Command) -> Result<(), Error> {
+    run_git_within(runner, command, None)
+}
+
+/// Runs a state-changing `git` command like [`run_git`], killing it after
+/// `timeout` seconds.
+fn run_git_within(
+    runner: &Runner,
+    command: &mut Command,
+    timeout: Option<u64>,
+) -> Result<(), Error> {
+    let output = runner
+        .run_timeout(command, timeout.map(Duration::from_secs))
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::TimedOut => Error::TimedOut(err),
+            _ => Error::Spawn(err),
+        })?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(Error::Command(
+            String::from_utf8_lossy(&output.stderr)
+                .trim_end()
+                .to_string(),
+        ))
+    }
+}
+
+/// Returns the backend selected in the settings, falling back to the CLI when
+/// libgit2 support isn't compiled in.
+#[cfg_attr(feature = "libgit2", allow(unused_variables))]
+pub fn backend(kind: BackendKind, output: &Output) -> Box<dyn GitBackend> {
+    match kind {
+        BackendKind::Cli => Box::new(CliBackend),
+        #[cfg(feature = "libgit2")]
+        BackendKind::Libgit2 => Box::new(libgit2::Libgit2Backend),
+        #[cfg(not(feature = "libgit2"))]
+        BackendKind::Libgit2 => {
+            output.notice(
+                "comphost was built without libgit2 support, using the git CLI".to_string(),
+            );
+            Box::new(CliBackend)
+        }
+    }
+}
+
+/// Shells out to the `git` binary.
+pub struct CliBackend;
+
+impl GitBackend for CliBackend {
+    fn clone_repo(
+        &self,
+        runner: &Runner,
+        url: &str,
+        parent_dir: &str,
+        name: &str,
+        options: &CloneOptions,
+    ) -> Result<(), Error> {
+        let mut command = Command::new("git");
+        command.arg("clone");
+        if let Some(ref branch) = options.branch {
+            command.arg("--branch").arg(branch);
+        }
+        if let Some(depth) = options.depth {
+            command.arg("--depth").arg(depth.to_string());
+        }
+        if options.submodules {
+            command.arg("--recurse-submodules");
+        }
+        if !options.sparse_paths.is_empty() {
+            // Only the top-level files until the sparse paths are set
+            command.arg("--sparse");
+        }
+        command.arg("--").arg(url).arg(name).current_dir(parent_dir);
+        options.apply(&mut command);
+        let cloned = run_git_within(runner, &mut command, options.timeout);
+        if let Err(Error::TimedOut(_)) = cloned {
+            // A killed clone doesn't clean up after itself, and the next one
+            // would find its directory in the way
+            let _ = fs::remove_dir_all(Path::new(parent_dir).join(name));
+        }
+        cloned
+    }
+}
+
+#[cfg(feature = "libgit2")]
+mod libgit2 {
+    use super::{CloneOptions, Error, GitBackend};
+    use crate::output::Runner;
+    use git2::build::RepoBuilder;
+    use git2::{
+        Cred, CredentialType, FetchOptions, RemoteCallbacks, Repository, SubmoduleUpdateOptions,
+    };
+    use std::path::{Path, PathBuf};
+
+    /// Clones in-process with libgit2.
+    pub struct Libgit2Backend;
+
+    /// Private keys tried when the ssh agent has none that work.
+    fn default_ssh_keys() -> Vec<PathBuf> {
+        let Some(home) = dirs::home_dir() else {
+            return Vec::new();
+        };
+        ["id_ed25519", "id_ecdsa", "id_rsa"]
+            .iter()
+            .map(|key| home.join(".ssh").join(key))
+            .filter(|key| key.is_file())
+            .collect()
+    }
+
+    /// Callbacks that offer `ssh_key` when given, or the ssh agent and then the
+    /// default keys, then git's credential helpers.
+    fn authenticated_callbacks<'a>(ssh_key: Option<PathBuf>) -> RemoteCallbacks<'a> {
+        let mut callbacks = RemoteCallbacks::new();
+        // A configured key is the only identity offered, like `IdentitiesOnly`
+        let mut tried_agent = ssh_key.is_some();
+        let mut ssh_keys = match ssh_key {
+            Some(key) => vec![key].into_iter(),
+            None => default_ssh_keys().into_iter(),
+        };
+        let mut tried_helper = false;
+        callbacks.credentials(move |url, username, allowed| {
+            if allowed.contains(CredentialType::SSH_KEY) {
+                let username = username.unwrap_or("git");
+                if !tried_agent {
+                    tried_agent = true;
+                    return Cred::ssh_key_from_agent(username);
+                }
+                if let Some(key) = ssh_keys.next() {
+                    return Cred::ssh_key(username, None, &key, None);
+                }
+            }
+            if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) && !tried_helper {
+                tried_helper = true;
+                let config = git2::Config::open_default()?;
+                return Cred::credential_helper(&config, url, username);
+            }
+            if allowed.contains(CredentialType::DEFAULT) {
+                return Cred::default();
+            }
+            Err(git2::Error::from_str("no more credentials to try"))
+        });
+        callbacks
+    }
+
+    /// Initializes and checks out the submodules of `repo`, and theirs.
+    fn update_submodules(repo: &Repository, options: &CloneOptions) -> Result<(), git2::Error> {
+        for mut submodule in repo.submodules()? {
+            let mut fetch_options = FetchOptions::new();
+            fetch_options.remote_callbacks(authenticated_callbacks(options.ssh_key_path()));
+            let mut update_options = SubmoduleUpdateOptions::new();
+            update_options.fetch(fetch_options);
+            submodule.update(true, Some(&mut update_options))?;
+            update_submodules(&submodule.open()?, options)?;
+        }
+        Ok(())
+    }
+
+    impl GitBackend for Libgit2Backend {
+        fn clone_repo(
+            &self,
+            runner: &Runner,
+            url: &str,
+            parent_dir: &str,
+            name: &str,
+            options: &CloneOptions,
+        ) -> Result<(), Error> {
+            let path = Path::new(parent_dir).join(name);
+            if runner.dry_run {
+                runner.output.notice(format!(
+                    "Would clone '{}' into '{}' with libgit2",
+                    url,
+                    path.display()
+                ));
+                return Ok(());
+            }
+
+            let mut callbacks = authenticated_callbacks(options.ssh_key_path());
+
+            // Report every quarter of the received objects
+            if !runner.output.is_json() {
+                let name = name.to_string();
+                let mut next_step = 25;
+                callbacks.transfer_progress(move |stats| {
+                    let total = stats.total_objects();
+                    if let Some(percent) = (stats.received_objects() * 100).checked_div(total) {
+                        if percent >= next_step {
+                            eprintln!(
+                                "Receiving objects for '{}': {}% ({}/{})",
+                                name,
+                                percent,
+                                stats.received_objects(),
+                                total
+                            );
+                            next_step = percent / 25 * 25 + 25;
+                        }
+                    }
+                    true
+                });
+            }
+
+            let mut fetch_options = FetchOptions::new();
+            fetch_options.remote_callbacks(callbacks);
+            if let Some(depth) = options.depth {
+                fetch_options.depth(i32::try_from(depth).unwrap_or(i32::MAX));
+            }
+            let mut builder = RepoBuilder::new();
+            if let Some(ref branch) = options.branch {
+                builder.branch(branch);
+            }
+            builder
+                .fetch_options(fetch_options)
+                .clone(url, &path)
+                .and_then(|repo| match options.submodules {
+                    true => update_submodules(&repo, options),
+                    false => Ok(()),
+                })
+                .map_err(Error::Libgit2)
+        }
+    }
+}
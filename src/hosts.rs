@@ -0,0 +1,164 @@
+//! Hosts file entries for running configurations, enabled with the
+//! `hosts_domain` setting: `<name>.<domain>` resolves to the container of the
+//! project's routed service on the shared network.
+//!
+//! The entries of each shared network live in their own marked block, so
+//! workspaces don't overwrite each other and the rest of the file is left
+//! alone. Container addresses are only reachable from the machine itself when
+//! the runtime runs natively, as Docker does on Linux.
+
+use crate::output::Runner;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The system hosts file.
+pub fn path() -> PathBuf {
+    #[cfg(windows)]
+    let path = PathBuf::from(r"C:\Windows\System32\drivers\etc\hosts");
+    #[cfg(not(windows))]
+    let path = PathBuf::from("/etc/hosts");
+    path
+}
+
+/// `<name>.<domain>`, with the characters a hostname can't hold replaced.
+pub fn hostname(config_name: &str, domain: &str) -> String {
+    format!("{}.{}", host_label(config_name), domain.trim_matches('.'))
+}
+
+/// A configuration name as a DNS label.
+pub(crate) fn host_label(config_name: &str) -> String {
+    let label: String = config_name
+        .to_ascii_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    label.trim_matches('-').to_string()
+}
+
+/// Replaces the block of `network` in the hosts file `content` with
+/// `entries` of hostname and address, dropping it when there are none.
+pub fn update(content: &str, network: &str, entries: &[(String, String)]) -> String {
+    let begin = format!("# BEGIN comphost {}", network);
+    let end = format!("# END comphost {}", network);
+    let mut lines = Vec::new();
+    let mut position = None;
+    let mut inside = false;
+    for line in content.lines() {
+        if line.trim() == begin {
+            inside = true;
+            position = Some(lines.len());
+        } else if inside {
+            inside = line.trim() != end;
+        } else {
+            lines.push(line.to_string());
+        }
+    }
+
+    if !entries.is_empty() {
+        let mut block = vec![begin];
+        for (hostname, address) in entries {
+            block.push(format!("{}\t{}", address, hostname));
+        }
+        block.push(end);
+        let position = position.unwrap_or(lines.len());
+        lines.splice(position..position, block);
+    }
+
+    // Keep the line endings of the file
+    let newline = if content.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let mut updated = lines.join(newline);
+    if !updated.is_empty() {
+        updated.push_str(newline);
+    }
+    updated
+}
+
+/// Writes the hosts file, through `sudo` when it isn't writable.
+pub fn write(runner: &Runner, path: &Path, content: &str) -> Result<(), String> {
+    if runner.dry_run {
+        runner
+            .output
+            .notice(format!("Would write '{}'", path.display()));
+        return Ok(());
+    }
+    match fs::write(path, content) {
+        Ok(()) => Ok(()),
+        #[cfg(unix)]
+        Err(err) if err.kind() == std::io::ErrorKind::PermissionDenied => {
+            // Copying over the file keeps its owner and mode, sudo may ask for a password
+            let staging = crate::temp::TempDir::create("hosts").map_err(|err| err.to_string())?;
+            let staged = staging
+                .write("hosts", content)
+                .map_err(|err| err.to_string())?;
+            let copied = runner.interactive(
+                std::process::Command::new("sudo")
+                    .arg("cp")
+                    .arg(&staged)
+                    .arg(path),
+            );
+            match copied {
+                Ok(status) if status.success() => Ok(()),
+                Ok(status) => Err(format!("sudo cp exited with {}", status)),
+                Err(err) => Err(format!("Failed to execute sudo: {}", err)),
+            }
+        }
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(entries: &[(&str, &str)]) -> Vec<(String, String)> {
+        entries
+            .iter()
+            .map(|(hostname, address)| (hostname.to_string(), address.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn names_become_hostnames() {
+        assert_eq!(hostname("My_API", ".test."), "my-api.test");
+        assert_eq!(hostname("-web-", "localhost"), "web.localhost");
+    }
+
+    #[test]
+    fn blocks_are_added_replaced_and_dropped() {
+        let original = "127.0.0.1\tlocalhost\n";
+        let added = update(original, "dev", &entries(&[("api.test", "172.18.0.2")]));
+        assert_eq!(
+            added,
+            "127.0.0.1\tlocalhost\n# BEGIN comphost dev\n172.18.0.2\tapi.test\n# END comphost dev\n"
+        );
+
+        // Other lines, before and after, and other networks stay where they are
+        let edited = format!("{}::1\tip6-localhost\n", added);
+        let other = update(&edited, "other", &entries(&[("web.test", "172.19.0.2")]));
+        let replaced = update(&other, "dev", &entries(&[("api.test", "172.18.0.3")]));
+        assert_eq!(
+            replaced,
+            "127.0.0.1\tlocalhost\n# BEGIN comphost dev\n172.18.0.3\tapi.test\n# END comphost dev\n::1\tip6-localhost\n# BEGIN comphost other\n172.19.0.2\tweb.test\n# END comphost other\n"
+        );
+
+        let dropped = update(&update(&replaced, "other", &[]), "dev", &[]);
+        assert_eq!(dropped, "127.0.0.1\tlocalhost\n::1\tip6-localhost\n");
+    }
+
+    #[test]
+    fn windows_line_endings_are_kept() {
+        let updated = update(
+            "127.0.0.1 localhost\r\n",
+            "dev",
+            &entries(&[("api.test", "172.18.0.2")]),
+        );
+        assert_eq!(
+            updated,
+            "127.0.0.1 localhost\r\n# BEGIN comphost dev\r\n172.18.0.2\tapi.test\r\n# END comphost dev\r\n"
+        );
+    }
+}
@@ -0,0 +1,80 @@
+//! comphost manages a workspace of git repositories with compose projects:
+//! cloning them and bringing their projects up and down on a shared network.
+//!
+//! [`ConfigStore`] loads and saves the configurations, [`GitOps`] clones and
+//! pulls their repositories and [`ComposeOps`] drives their compose projects.
+//! [`workflow`] combines them into what the commands do to several
+//! configurations at once, like starting them in dependency order. Results
+//! are reported through an [`Output`], and external commands run through a
+//! [`Runner`] that honors dry-run mode.
+//!
+//! The `comphost` binary parses the command line, resolves the settings and
+//! calls into this crate, printing tables for the commands that list things.
+//! Some commands are still implemented in the binary where they are mostly
+//! argument handling.
+
+pub mod audit;
+pub mod compose;
+pub mod config;
+#[cfg(unix)]
+pub mod daemon;
+pub mod docker;
+pub mod dotenv;
+pub mod encryption;
+pub mod error;
+pub mod git;
+pub mod hosts;
+pub mod limits;
+pub mod notify;
+pub mod output;
+pub mod ports;
+pub mod proxy;
+pub mod secrets;
+pub mod server;
+pub mod temp;
+pub mod template;
+pub mod time;
+#[cfg(feature = "tui")]
+pub mod ui;
+pub mod update;
+pub mod wait;
+pub mod workflow;
+pub mod workspace;
+
+pub use compose::ComposeOps;
+pub use config::{ConfigStore, Configuration, Settings};
+pub use error::Error;
+pub use git::GitOps;
+pub use output::{Output, OutputFormat, Report, Runner};
+
+use std::sync::Mutex;
+use std::thread;
+
+/// Runs `task` over `items` on at most `jobs` threads, returning results in input order.
+pub fn run_parallel<T, R, F>(jobs: usize, items: Vec<T>, task: F) -> Vec<R>
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> R + Sync,
+{
+    let total = items.len();
+    let queue = Mutex::new(items.into_iter().enumerate());
+    let results = Mutex::new(Vec::with_capacity(total));
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, total.max(1)) {
+            scope.spawn(|| loop {
+                let next = queue.lock().unwrap().next();
+                let Some((index, item)) = next else {
+                    break;
+                };
+                let result = task(item);
+                results.lock().unwrap().push((index, result));
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
@@ -0,0 +1,33 @@
+//! CPU and memory caps of configurations, set with `cpu_limit` and
+//! `memory_limit` and applied to each of their services through a compose
+//! override, so heavy projects can't starve the rest of the workspace.
+
+use crate::ports::yaml_string;
+
+/// The services of the `compose config --format json` output.
+pub fn services(compose_config: &str) -> Result<Vec<String>, String> {
+    let config: serde_json::Value =
+        serde_json::from_str(compose_config).map_err(|err| err.to_string())?;
+    Ok(config["services"]
+        .as_object()
+        .map(|services| services.keys().cloned().collect())
+        .unwrap_or_default())
+}
+
+/// A compose override capping every one of `services`.
+pub fn override_file(services: &[String], cpus: Option<f64>, memory: Option<&str>) -> String {
+    let mut content = String::from("services:\n");
+    for service in services {
+        content.push_str(&format!(
+            "  {}:\n    deploy:\n      resources:\n        limits:\n",
+            yaml_string(service)
+        ));
+        if let Some(cpus) = cpus {
+            content.push_str(&format!("          cpus: \"{}\"\n", cpus));
+        }
+        if let Some(memory) = memory {
+            content.push_str(&format!("          memory: {}\n", yaml_string(memory)));
+        }
+    }
+    content
+}
@@ -1,13 +1,101 @@
-use clap::{Parser, Subcommand};
-use std::collections::HashMap;
+use clap::{ArgAction, Args, CommandFactory, Parser, Subcommand};
+use clap_complete::env::Shells;
+use clap_complete::{ArgValueCandidates, CompleteEnv, CompletionCandidate, Shell};
+use comphost::audit;
+use comphost::compose::{
+    dependency_waves, has_compose_file, ConfigStatus, ContainerEntry, Orphan, StartOptions,
+    StopOptions, READY_TIMEOUT,
+};
+use comphost::config::{self, FileFormat, ImportOutcome, Imported, Merge, WORKSPACE_FILES};
+#[cfg(unix)]
+use comphost::daemon;
+use comphost::docker::{self, Runtime};
+use comphost::error::Failure;
+use comphost::notify;
+use comphost::secrets::Secrets;
+use comphost::update;
+use comphost::workflow::{
+    edit, find_repos, pull_images, pull_options, report_dirty, roll_back, running, select_cloned,
+    skip_dirty, start_in_order, with_tagged,
+};
+use comphost::{
+    encryption, error, ports, run_parallel, workspace, ComposeOps, ConfigStore, Configuration,
+    Error, GitOps, Output, OutputFormat, Report, Runner,
+};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
 use std::env;
-use std::fs::{File, OpenOptions};
-use std::io::{self, Read, Write};
-use std::process::Command;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use tracing::Level;
+
+/// Exit codes, listed in `--help`
+const EXIT_CODES: &str = "Exit codes:
+  0  Everything succeeded or was skipped
+  1  Some operations failed
+  2  The config couldn't be loaded or saved
+  3  The config file is invalid
+  4  A named configuration, profile or secret doesn't exist
+  5  The container runtime can't be reached
+  6  Cloning or pulling a repository failed
+
+When several kinds of operations fail, 4 wins over 5, 5 over 6 and 6 over 1.";
 
 #[derive(Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, after_long_help = EXIT_CODES)]
 struct Cli {
+    /// Output format
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Plain)]
+    output: OutputFormat,
+
+    /// Shared network to attach containers to, overriding the settings
+    #[arg(long, global = true, value_name = "NAME")]
+    network: Option<String>,
+
+    /// Print the commands and config changes instead of applying them
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    /// Only print failures and the results of commands like `list`
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// Don't color the output, also disabled by setting `NO_COLOR`
+    #[arg(long, global = true)]
+    no_color: bool,
+
+    /// Workspace to use instead of the one selected with `workspace use`
+    #[arg(long, global = true, value_name = "NAME")]
+    workspace: Option<String>,
+
+    /// Log what comphost does: `-v` the commands it runs, `-vv` also how long
+    /// they took, their exit codes and error output, `-vvv` everything
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// Append the log to this file instead of stderr, at `-vv` unless more
+    /// verbose
+    #[arg(long, global = true, value_name = "PATH", env = "COMPHOST_LOG_FILE")]
+    log_file: Option<PathBuf>,
+
+    /// Number of repositories or projects to work on concurrently, overriding
+    /// the `jobs` setting
+    #[arg(short, long, global = true, value_name = "N")]
+    jobs: Option<usize>,
+
+    /// Config file to use instead of the one of the workspace
+    #[arg(
+        long = "config",
+        global = true,
+        value_name = "PATH",
+        env = "COMPHOST_CONFIG"
+    )]
+    config_file: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -18,280 +106,2351 @@ enum Commands {
     Add {
         #[arg(value_name = "NAME")]
         name: Vec<String>,
+        /// Tag the new configurations
+        #[arg(long = "tag", value_name = "TAG")]
+        tags: Vec<String>,
+    },
+    /// Remove configurations
+    Remove {
+        #[arg(value_name = "NAME", add = ArgValueCandidates::new(all_names))]
+        name: Vec<String>,
+        /// Also delete the cloned directory (asks for confirmation)
+        #[arg(long)]
+        purge: bool,
+    },
+    /// Rename a configuration
+    Rename {
+        #[arg(value_name = "OLD")]
+        old: String,
+        #[arg(value_name = "NEW")]
+        new: String,
+        /// Also rename the cloned directory to the new name
+        #[arg(long = "move")]
+        move_dir: bool,
+    },
+    /// Open the config file in $VISUAL or $EDITOR, keeping the result only if it's valid
+    Edit,
+    /// Encrypt the config file with a passphrase, asked for or taken from
+    /// `COMPHOST_PASSPHRASE` or the output of `COMPHOST_PASSPHRASE_COMMAND`
+    Encrypt,
+    /// Store the config file in plain text again
+    Decrypt,
+    /// Print the configurations and profiles to share them, without clone paths
+    Export {
+        #[arg(long, value_enum, default_value_t = FileFormat::Toml)]
+        format: FileFormat,
+    },
+    /// Add the configurations and profiles of an exported file
+    Import {
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+        /// Format of the file, guessed from its extension when not given
+        #[arg(long, value_enum)]
+        format: Option<FileFormat>,
+        /// Replace existing configurations and profiles, keeping their clone paths
+        #[arg(long, conflicts_with = "skip_existing")]
+        overwrite: bool,
+        /// Leave existing configurations and profiles as they are
+        #[arg(long)]
+        skip_existing: bool,
+    },
+    /// Update the configurations and profiles from a workspace file in a shared repository
+    ///
+    /// The repository is remembered, so later syncs don't need `--from`. Local
+    /// clone paths and active flags are kept.
+    SyncConfig {
+        /// Repository with a comphost.toml, .yaml, .yml or .json at its root
+        #[arg(long, value_name = "GIT_URL")]
+        from: Option<String>,
+        /// Path of the workspace file inside the repository
+        #[arg(long, value_name = "PATH")]
+        file: Option<PathBuf>,
+    },
+    /// Add configurations for the git repositories with compose files found in a directory
+    ImportDir {
+        #[arg(value_name = "PATH")]
+        path: PathBuf,
+        /// How many directory levels to search below PATH
+        #[arg(long, value_name = "N", default_value_t = 3)]
+        depth: usize,
+        /// Tag the new configurations
+        #[arg(long = "tag", value_name = "TAG")]
+        tags: Vec<String>,
     },
     /// Turn on configurations
     On {
-        #[arg(value_name = "NAME")]
+        /// Configurations to turn on, `*` and `?` match any characters
+        #[arg(value_name = "NAME", add = ArgValueCandidates::new(inactive_names))]
         name: Vec<String>,
+        /// Also operate on configurations with this tag
+        #[arg(long = "tag", value_name = "TAG")]
+        tags: Vec<String>,
+        /// Turn on every configuration
+        #[arg(long, conflicts_with_all = ["name", "tags"])]
+        all: bool,
+        /// Turn off all other configurations, changing nothing if any name is
+        /// unknown
+        #[arg(long, conflicts_with = "all")]
+        only: bool,
     },
     /// Turn off configurations
     Off {
-        #[arg(value_name = "NAME")]
+        /// Configurations to turn off, `*` and `?` match any characters
+        #[arg(value_name = "NAME", add = ArgValueCandidates::new(active_names))]
         name: Vec<String>,
+        /// Also operate on configurations with this tag
+        #[arg(long = "tag", value_name = "TAG")]
+        tags: Vec<String>,
+        /// Turn off every configuration
+        #[arg(long, conflicts_with_all = ["name", "tags"])]
+        all: bool,
+    },
+    /// Turn configurations on if they are off and off if they are on
+    Toggle {
+        #[arg(
+            value_name = "NAME",
+            required = true,
+            add = ArgValueCandidates::new(all_names)
+        )]
+        name: Vec<String>,
+    },
+    /// Revert the config to before the last add, remove, on, off, toggle or
+    /// profile use
+    Undo,
+    /// Replace the config with one of its backups, listing them when no backup
+    /// is given
+    Restore {
+        #[arg(value_name = "BACKUP")]
+        backup: Option<String>,
+    },
+    /// Show what recent comphost runs changed and which commands they ran
+    History {
+        /// How many of the most recent runs to show
+        #[arg(short = 'n', long, value_name = "COUNT", default_value_t = 20)]
+        limit: usize,
     },
     /// Clone active configurations
-    Clone,
+    Clone {
+        /// Configurations to clone, defaults to the active ones
+        #[arg(value_name = "NAME")]
+        name: Vec<String>,
+        /// Directory to clone into, defaults to the `workspace_dir` setting
+        #[arg(long, value_name = "DIR")]
+        path: Option<String>,
+        /// Operate on configurations with this tag instead of the active ones
+        #[arg(long = "tag", value_name = "TAG")]
+        tags: Vec<String>,
+        /// Only fetch the last N commits, overriding the configurations and settings
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+        depth: Option<u32>,
+    },
+    /// Pull the latest changes for active configurations
+    ///
+    /// Clones with uncommitted changes to tracked files are left alone.
+    Pull {
+        /// Pull clones with uncommitted changes too
+        #[arg(long)]
+        allow_dirty: bool,
+        /// Stash uncommitted changes before pulling and reapply them afterwards,
+        /// like the `autostash` setting of configurations
+        #[arg(long)]
+        autostash: bool,
+    },
+    /// Pull active configurations and rebuild and restart those with new commits
+    Update {
+        /// Configurations to update, defaults to the active ones
+        #[arg(value_name = "NAME")]
+        name: Vec<String>,
+        /// Operate on configurations with this tag instead of the active ones
+        #[arg(long = "tag", value_name = "TAG")]
+        tags: Vec<String>,
+        /// Pull clones with uncommitted changes too, instead of leaving them alone
+        #[arg(long)]
+        allow_dirty: bool,
+        /// Stash uncommitted changes before pulling and reapply them afterwards,
+        /// like the `autostash` setting of configurations
+        #[arg(long)]
+        autostash: bool,
+    },
     /// Start Docker Compose for active configurations
-    Start,
+    Start {
+        /// Configurations to start, defaults to the active ones. `*` and `?` match
+        /// any characters
+        #[arg(value_name = "NAME", add = ArgValueCandidates::new(all_names))]
+        name: Vec<String>,
+        /// Operate on configurations with this tag instead of the active ones
+        #[arg(long = "tag", value_name = "TAG")]
+        tags: Vec<String>,
+        /// Stop the configurations this start brought up again if any fails,
+        /// leaving those that were running before alone
+        #[arg(long)]
+        atomic: bool,
+        #[command(flatten)]
+        options: StartArgs,
+    },
+    /// Pull the images of active configurations
+    PullImages {
+        /// Configurations to pull images for, defaults to the active ones
+        #[arg(value_name = "NAME")]
+        name: Vec<String>,
+        /// Operate on configurations with this tag instead of the active ones
+        #[arg(long = "tag", value_name = "TAG")]
+        tags: Vec<String>,
+    },
     /// Stop Docker Compose for active configurations
-    Stop,
+    Stop {
+        /// Configurations to stop, defaults to the active ones. `*` and `?` match
+        /// any characters
+        #[arg(value_name = "NAME", add = ArgValueCandidates::new(all_names))]
+        name: Vec<String>,
+        /// Operate on configurations with this tag instead of the active ones
+        #[arg(long = "tag", value_name = "TAG")]
+        tags: Vec<String>,
+        /// Stop every cloned configuration, active or not
+        #[arg(long, conflicts_with_all = ["name", "tags"])]
+        all: bool,
+        /// Leave the shared network in place once no project is running
+        #[arg(long)]
+        keep_network: bool,
+        /// Remove the volumes of the projects too
+        #[arg(long)]
+        volumes: bool,
+        /// Remove the images of the projects too, `local` ones without a
+        /// custom tag unless `all` is given
+        #[arg(
+            long,
+            value_name = "TYPE",
+            value_parser = ["local", "all"],
+            num_args = 0..=1,
+            default_missing_value = "local"
+        )]
+        rmi: Option<String>,
+    },
+    /// Remove stopped containers, dangling images and unused volumes of configurations
+    ///
+    /// Only what compose labelled as belonging to their projects is removed,
+    /// unlike `docker system prune`.
+    Prune {
+        /// Configurations to prune, defaults to the active ones
+        #[arg(value_name = "NAME")]
+        name: Vec<String>,
+        /// Operate on configurations with this tag instead of the active ones
+        #[arg(long = "tag", value_name = "TAG")]
+        tags: Vec<String>,
+        /// Prune every cloned configuration, active or not
+        #[arg(long, conflicts_with_all = ["name", "tags"])]
+        all: bool,
+        /// Also remove containers on the shared network left behind by removed
+        /// or moved configurations
+        #[arg(long)]
+        orphans: bool,
+    },
+    /// Show compose logs of configurations, prefixed with their names
+    Logs {
+        /// Configurations to show, defaults to the active ones
+        #[arg(value_name = "NAME")]
+        name: Vec<String>,
+        /// Keep streaming new log output
+        #[arg(short, long)]
+        follow: bool,
+        /// Number of lines to show from the end of each log
+        #[arg(long, value_name = "N")]
+        tail: Option<usize>,
+    },
+    /// Run a command in a service of a configuration
+    Exec {
+        #[arg(value_name = "CONFIG")]
+        config: String,
+        #[arg(value_name = "SERVICE")]
+        service: String,
+        /// Command to run, defaults to an interactive shell
+        #[arg(last = true, value_name = "CMD")]
+        command: Vec<String>,
+    },
+    /// Print the compose override rendered from the template of a configuration
+    RenderOverride {
+        #[arg(value_name = "CONFIG")]
+        config: String,
+    },
+    /// Store secrets passed to compose as environment variables on start
+    Secret {
+        #[command(subcommand)]
+        command: SecretCommands,
+    },
+    /// Save or restore which configurations are active
+    #[command(visible_alias = "preset")]
+    Profile {
+        #[command(subcommand)]
+        command: ProfileCommands,
+    },
+    /// Switch between workspaces, each with its own configurations and network
+    Workspace {
+        #[command(subcommand)]
+        command: WorkspaceCommands,
+    },
+    /// Show configurations and whether their projects are running
+    #[command(visible_alias = "status")]
+    List {
+        /// Keep refreshing, with the containers of running configurations
+        #[arg(short, long)]
+        watch: bool,
+        /// Seconds between refreshes
+        #[arg(
+            short = 'n',
+            long,
+            value_name = "SECONDS",
+            default_value_t = 2,
+            requires = "watch"
+        )]
+        interval: u64,
+    },
+    /// Show the containers of active configurations
+    Ps,
+    /// Show the host ports active configurations publish, and which were remapped
+    Ports,
+    /// Point `<name>.<hosts_domain>` in the hosts file at running configurations
+    ///
+    /// This also happens on `start` and `stop` once `hosts_domain` is set.
+    Hosts {
+        /// Remove the entries of this workspace instead
+        #[arg(long)]
+        clear: bool,
+    },
+    /// Keep the status of configurations at hand for `list`, until stopped
+    ///
+    /// `list` asks the daemon over a socket next to the config instead of
+    /// reading the config and querying the container runtime itself.
+    #[cfg(unix)]
+    Daemon,
+    /// Serve an HTTP API on localhost to list, toggle, start and stop
+    /// configurations and stream their logs
+    Serve {
+        /// Port to listen on
+        #[arg(long, value_name = "PORT", default_value_t = 8800)]
+        port: u16,
+    },
     /// List configuration names for shell completion
-    ListNames,
+    ListNames {
+        /// Only list active configurations
+        #[arg(long, conflicts_with = "inactive_only")]
+        active_only: bool,
+        /// Only list inactive configurations
+        #[arg(long)]
+        inactive_only: bool,
+        /// Follow each name with a tab and `on` or `off`, the format fish
+        /// completions take descriptions in
+        #[arg(long)]
+        with_state: bool,
+    },
+    /// Open a dashboard to watch, start, stop and toggle configurations
+    #[cfg(feature = "tui")]
+    Ui,
+    /// Replace comphost with the binary of its latest release, verifying its
+    /// checksum first
+    SelfUpdate {
+        /// Only tell whether a newer release exists
+        #[arg(long)]
+        check: bool,
+    },
+    /// Print a shell completion script, which asks comphost for the names of
+    /// configurations as they are at the time of completing
+    ///
+    /// For example `source <(comphost completions bash)` in `~/.bashrc`
+    Completions {
+        #[arg(value_name = "SHELL")]
+        shell: Shell,
+    },
+}
+
+/// Flags of `start` that change how each project is brought up.
+#[derive(Args)]
+struct StartArgs {
+    /// Wait until services are running and healthy
+    #[arg(long)]
+    wait: bool,
+    /// Seconds to wait for services to become ready
+    #[arg(long, value_name = "SECONDS", default_value_t = READY_TIMEOUT, requires = "wait")]
+    wait_timeout: u64,
+    /// Pull the images of all projects first, only starting those that succeed
+    #[arg(long)]
+    pull: bool,
+    /// Rebuild images before starting the containers
+    #[arg(long)]
+    build: bool,
+    /// Recreate containers even if their configuration hasn't changed
+    #[arg(long)]
+    force_recreate: bool,
+    /// Start projects whose services all run with their current configuration
+    /// too, which are skipped otherwise
+    #[arg(long)]
+    force: bool,
+    /// Arguments passed on to `compose up` as they are, such as `--scale worker=3`
+    #[arg(last = true, value_name = "COMPOSE_ARGS")]
+    compose_args: Vec<String>,
+}
+
+impl From<StartArgs> for StartOptions {
+    fn from(args: StartArgs) -> Self {
+        StartOptions {
+            wait: args.wait,
+            wait_timeout: args.wait_timeout,
+            build: args.build,
+            force_recreate: args.force_recreate,
+            // Freshly pulled images are only used once the project is started
+            force: args.force || args.pull,
+            compose_args: args.compose_args,
+            proxy: None,
+            override_template: None,
+            secrets: BTreeMap::new(),
+            wait_for_timeout: None,
+            up_timeout: None,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum ProfileCommands {
+    /// Save the currently active configurations as a profile
+    Save {
+        #[arg(value_name = "NAME")]
+        name: String,
+    },
+    /// Activate exactly the configurations of a profile
+    #[command(visible_alias = "apply")]
+    Use {
+        #[arg(value_name = "NAME")]
+        name: String,
+    },
+    /// Show the saved profiles and their configurations
+    List,
+}
+
+#[derive(Subcommand)]
+enum SecretCommands {
+    /// Set a secret, asking for its value or reading it from stdin
+    Set {
+        #[arg(value_name = "CONFIG")]
+        config: String,
+        #[arg(value_name = "KEY")]
+        key: String,
+    },
+    /// Show the names of the secrets, without their values
+    List {
+        #[arg(value_name = "CONFIG")]
+        config: Option<String>,
+    },
+    /// Remove a secret
+    Remove {
+        #[arg(value_name = "CONFIG")]
+        config: String,
+        #[arg(value_name = "KEY")]
+        key: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum WorkspaceCommands {
+    /// Select the workspace commands use, creating it if it doesn't exist
+    Use {
+        #[arg(value_name = "NAME")]
+        name: String,
+    },
+    /// Show the workspaces and which one is selected
+    List,
+}
+
+/// A row of the `workspace list` output.
+#[derive(Serialize)]
+struct WorkspaceEntry {
+    name: String,
+    current: bool,
+}
+
+/// The containers of one configuration in the `ps` output.
+#[derive(Serialize)]
+struct ProjectContainers<'a> {
+    config: &'a str,
+    containers: Vec<ContainerEntry>,
+}
+
+/// A row of the `ports` output.
+#[derive(Serialize)]
+struct PortEntry<'a> {
+    config: &'a str,
+    service: String,
+    host_ip: Option<String>,
+    host_port: u16,
+    /// Port the service asked for, when it was remapped
+    remapped_from: Option<u16>,
+    target: u16,
+    protocol: String,
+}
+
+/// Prints rows as left-aligned columns under a header.
+fn print_table<const N: usize>(header: [&str; N], rows: &[[String; N]], indent: &str) {
+    let header = header.map(String::from);
+    let mut widths = header.clone().map(|column| column.len());
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    for row in std::iter::once(&header).chain(rows) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        println!("{}{}", indent, line.join("  ").trim_end());
+    }
+}
+
+/// Prints the `list` table, followed by the orphaned containers on `network`
+/// if there are any.
+fn print_list(entries: &[ConfigStatus], orphans: Vec<Orphan>, network: &str) {
+    let rows: Vec<[String; 5]> = entries
+        .iter()
+        .map(|entry| {
+            let running = match (&entry.clone_path, entry.running) {
+                (None, _) => "-",
+                (Some(_), Some(true)) => "yes",
+                (Some(_), Some(false)) => "no",
+                (Some(_), None) => "unknown",
+            };
+            [
+                entry.name.clone(),
+                if entry.active { "yes" } else { "no" }.to_string(),
+                entry.url.clone(),
+                entry.clone_path.as_deref().unwrap_or("-").to_string(),
+                running.to_string(),
+            ]
+        })
+        .collect();
+    print_table(
+        ["NAME", "ACTIVE", "URL", "CLONE PATH", "RUNNING"],
+        &rows,
+        "",
+    );
+
+    if !orphans.is_empty() {
+        println!();
+        println!(
+            "Orphaned containers on {} network, remove them with `comphost prune --orphans`:",
+            network
+        );
+        let rows: Vec<[String; 3]> = orphans
+            .into_iter()
+            .map(|orphan| [orphan.name, orphan.project, orphan.working_dir])
+            .collect();
+        print_table(["NAME", "PROJECT", "WORKING DIR"], &rows, "  ");
+    }
+}
+
+/// Prints the containers of each configuration, as `ps` shows them.
+fn print_containers(projects: &[ProjectContainers]) {
+    for project in projects {
+        println!("{}", project.config);
+        if project.containers.is_empty() {
+            println!("  no containers");
+            continue;
+        }
+        let rows: Vec<[String; 4]> = project
+            .containers
+            .iter()
+            .map(|container| {
+                [
+                    container.service.clone(),
+                    container.id.clone(),
+                    container.status.clone(),
+                    container.ports.join(", "),
+                ]
+            })
+            .collect();
+        print_table(["SERVICE", "CONTAINER ID", "STATUS", "PORTS"], &rows, "  ");
+    }
+}
+
+/// Writes the secrets, or only says so in dry-run mode.
+fn save_secrets(secrets: &Secrets, output: &Output, dry_run: bool) -> Result<(), Error> {
+    if dry_run {
+        output.notice(format!("Would write '{}'", secrets.path().display()));
+        return Ok(());
+    }
+    secrets.save()
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
-struct Configuration {
-    active: bool,
-    url: String,
-    clone_path: Option<String>,
+/// Updates the secrets for configurations that were removed or renamed,
+/// saving them when `change` says it changed any. Secrets that can't be
+/// decrypted are left alone with a notice rather than failing the command,
+/// which most likely touched configurations without any.
+fn update_secrets(
+    store: &ConfigStore,
+    output: &Output,
+    dry_run: bool,
+    change: impl FnOnce(&mut BTreeMap<String, BTreeMap<String, String>>) -> bool,
+) -> Result<(), Error> {
+    let mut secrets = match Secrets::open(store.path()) {
+        Ok(secrets) => secrets,
+        Err(err) => {
+            output.notice(format!("Left the secrets alone: {}", err));
+            return Ok(());
+        }
+    };
+    if change(&mut secrets.configs) {
+        save_secrets(&secrets, output, dry_run)?;
+    }
+    Ok(())
 }
 
-impl Configuration {
-    fn clone_project(&mut self, clone_path: String) {
-        self.clone_path = Some(clone_path);
+fn secret_command(
+    command: SecretCommands,
+    store: &ConfigStore,
+    output: &Output,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let mut secrets = Secrets::open(store.path())?;
+    match command {
+        SecretCommands::Set {
+            config: config_name,
+            key,
+        } => {
+            if !store.configs.contains_key(&config_name) {
+                output.not_found(&config_name);
+                return Ok(());
+            }
+            if key.is_empty() || key.contains('=') {
+                output.failed(&config_name, format!("Invalid secret name '{}'", key));
+                return Ok(());
+            }
+
+            // Don't echo the value, and let scripts pipe it in
+            let value = if io::stdin().is_terminal() {
+                rpassword::prompt_password(format!("Value of {}: ", key))
+            } else {
+                io::read_to_string(io::stdin())
+                    .map(|value| value.trim_end_matches(['\r', '\n']).to_string())
+            };
+            let value = match value {
+                Ok(value) => value,
+                Err(err) => {
+                    output.failed(
+                        &config_name,
+                        format!("Failed to read the value of '{}': {}", key, err),
+                    );
+                    return Ok(());
+                }
+            };
+            secrets
+                .configs
+                .entry(config_name.clone())
+                .or_default()
+                .insert(key.clone(), value);
+            save_secrets(&secrets, output, dry_run)?;
+            output.ok(
+                &config_name,
+                format!("Secret '{}' of '{}' set.", key, config_name),
+            );
+        }
+        SecretCommands::List { config } => {
+            if let Some(ref config_name) = config {
+                secrets.configs.retain(|name, _| name == config_name);
+            }
+            let names: BTreeMap<&String, Vec<&String>> = secrets
+                .configs
+                .iter()
+                .map(|(config_name, values)| (config_name, values.keys().collect()))
+                .collect();
+            if output.is_json() {
+                output.data(&names);
+            } else if names.is_empty() {
+                println!("No secrets");
+            } else {
+                let rows: Vec<[String; 2]> = names
+                    .iter()
+                    .flat_map(|(config_name, keys)| {
+                        keys.iter()
+                            .map(|key| [config_name.to_string(), key.to_string()])
+                    })
+                    .collect();
+                print_table(["CONFIG", "SECRET"], &rows, "");
+            }
+        }
+        SecretCommands::Remove {
+            config: config_name,
+            key,
+        } => {
+            let removed = secrets
+                .configs
+                .get_mut(&config_name)
+                .and_then(|values| values.remove(&key));
+            if removed.is_none() {
+                let mut report = Report::new(&config_name);
+                report.failed_as(
+                    Failure::NotFound,
+                    format!("Secret '{}' of '{}' not found.", key, config_name),
+                    &[],
+                );
+                output.report(report);
+                return Ok(());
+            }
+            secrets.configs.retain(|_, values| !values.is_empty());
+            save_secrets(&secrets, output, dry_run)?;
+            output.ok(
+                &config_name,
+                format!("Secret '{}' of '{}' removed.", key, config_name),
+            );
+        }
     }
+    Ok(())
 }
 
-fn main() {
+/// Reports what happened to each imported configuration and profile.
+fn report_import(output: &Output, imported: ImportOutcome) {
+    let outcomes = imported
+        .configs
+        .into_iter()
+        .map(|(name, outcome)| ("Configuration", name, outcome))
+        .chain(
+            imported
+                .profiles
+                .into_iter()
+                .map(|(name, outcome)| ("Profile", name, outcome)),
+        );
+    for (kind, name, outcome) in outcomes {
+        match outcome {
+            Imported::Added => output.ok(&name, format!("{} '{}' imported.", kind, name)),
+            Imported::Replaced => output.ok(&name, format!("{} '{}' replaced.", kind, name)),
+            Imported::Unchanged => {
+                output.skipped(&name, format!("{} '{}' is unchanged.", kind, name))
+            }
+            Imported::Skipped => output.skipped(
+                &name,
+                format!("{} '{}' already exists, skipped.", kind, name),
+            ),
+            Imported::Conflict => output.failed(
+                &name,
+                format!(
+                    "{} '{}' already exists, use --overwrite or --skip-existing.",
+                    kind, name
+                ),
+            ),
+            Imported::Refused => output.failed(
+                &name,
+                format!(
+                    "{} '{}' has a URL or worktree repository starting with '-', not imported.",
+                    kind, name
+                ),
+            ),
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    // Answers completion scripts calling back with `COMPLETE=<shell>`
+    CompleteEnv::with_factory(Cli::command).complete();
     let args = Cli::parse();
 
-    // Get the user's home directory
-    let home_dir = match env::var("HOME") {
-        Ok(val) => val,
-        Err(_) => {
-            eprintln!("Failed to get user's home directory");
-            return;
+    // Completion scripts don't depend on the config
+    if let Commands::Completions { shell } = args.command {
+        let shells = Shells::builtins();
+        let completer = shells
+            .completer(&shell.to_string())
+            .expect("Every shell has a completer");
+        let registered = completer.write_registration(
+            "COMPLETE",
+            "comphost",
+            "comphost",
+            "comphost",
+            &mut io::stdout(),
+        );
+        if let Err(err) = registered {
+            eprintln!("Failed to print the completion script: {}", err);
+            return ExitCode::from(error::EXIT_ERROR);
+        }
+        return ExitCode::SUCCESS;
+    }
+    match run(args) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::from(err.exit_code())
+        }
+    }
+}
+
+/// The names of configurations with whether they are active, only the active
+/// or inactive ones when `active` is given.
+fn config_states(
+    configs: &BTreeMap<String, Configuration>,
+    active: Option<bool>,
+) -> BTreeMap<&String, bool> {
+    configs
+        .iter()
+        .filter(|(_, config)| active.is_none_or(|active| config.active == active))
+        .map(|(config_name, config)| (config_name, config.active))
+        .collect()
+}
+
+/// Names of configurations for shell completion, read from the config of the
+/// current workspace without waiting for its lock. An encrypted config offers
+/// nothing rather than asking for its passphrase mid-completion.
+fn complete_names(active: Option<bool>) -> Vec<CompletionCandidate> {
+    let path = match env::var_os("COMPHOST_CONFIG") {
+        Some(path) => PathBuf::from(path),
+        None => match workspace::current().and_then(|current| workspace::path(&current)) {
+            Ok(path) => path,
+            Err(_) => return Vec::new(),
+        },
+    };
+    let encrypted =
+        std::fs::read_to_string(&path).is_ok_and(|content| encryption::is_encrypted(&content));
+    let passphrase_set = env::var_os("COMPHOST_PASSPHRASE").is_some()
+        || env::var_os("COMPHOST_PASSPHRASE_COMMAND").is_some();
+    if encrypted && !passphrase_set {
+        return Vec::new();
+    }
+    let Ok(store) = ConfigStore::read(&path) else {
+        return Vec::new();
+    };
+    config_states(&store.configs, active)
+        .into_iter()
+        .map(|(config_name, active)| {
+            CompletionCandidate::new(config_name)
+                .help(Some(if active { "on" } else { "off" }.into()))
+        })
+        .collect()
+}
+
+fn all_names() -> Vec<CompletionCandidate> {
+    complete_names(None)
+}
+
+fn active_names() -> Vec<CompletionCandidate> {
+    complete_names(Some(true))
+}
+
+fn inactive_names() -> Vec<CompletionCandidate> {
+    complete_names(Some(false))
+}
+
+/// Lists or selects workspaces, with `current` being the selected one.
+fn workspace_command(
+    command: WorkspaceCommands,
+    current: &str,
+    output: &Output,
+    dry_run: bool,
+) -> Result<(), Error> {
+    match command {
+        WorkspaceCommands::List => {
+            let entries: Vec<WorkspaceEntry> = workspace::list()?
+                .into_iter()
+                .map(|name| WorkspaceEntry {
+                    current: name == current,
+                    name,
+                })
+                .collect();
+            if output.is_json() {
+                output.data(&entries);
+            } else {
+                for entry in entries {
+                    let marker = if entry.current { "*" } else { " " };
+                    println!("{} {}", marker, entry.name);
+                }
+            }
+        }
+        WorkspaceCommands::Use { name } => {
+            let path = workspace::path(&name)?;
+            if !workspace::exists(&name)? {
+                if dry_run {
+                    output.notice(format!("Would create '{}'", path.display()));
+                } else {
+                    ConfigStore::open(&path, || {})?.save()?;
+                    let mut report = Report::global();
+                    report.ok(format!("Workspace '{}' created.", name));
+                    output.report(report);
+                }
+            }
+            let mut report = Report::global();
+            if name == current {
+                report.skipped(format!("Workspace '{}' is already in use.", name));
+            } else if dry_run {
+                report.skipped(format!("Would switch to workspace '{}'", name));
+            } else {
+                workspace::set_current(&name)?;
+                report.ok(format!("Switched to workspace '{}'.", name));
+            }
+            output.report(report);
+        }
+    }
+    Ok(())
+}
+
+/// Sends the log to stderr at the level `verbose` asks for, or to `log_file`.
+fn init_logging(verbose: u8, log_file: Option<&Path>, color: bool) -> Result<(), Error> {
+    let level = match (verbose, log_file) {
+        (0, None) => return Ok(()),
+        (1, None) => Level::INFO,
+        (0..=2, _) => Level::DEBUG,
+        _ => Level::TRACE,
+    };
+    let logger = tracing_subscriber::fmt().with_max_level(level);
+    match log_file {
+        Some(path) => {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|source| Error::Write {
+                    path: path.display().to_string(),
+                    source,
+                })?;
+            logger.with_ansi(false).with_writer(Mutex::new(file)).init();
         }
+        None => logger
+            .with_ansi(color && io::stderr().is_terminal())
+            .with_writer(io::stderr)
+            .init(),
+    }
+    Ok(())
+}
+
+/// Updates comphost to its latest release, or only looks for one with `check`.
+fn self_update(check: bool, output: &Output, dry_run: bool) {
+    let mut report = Report::global();
+    let release = {
+        let _progress = output.progress("Looking for a newer release".to_string());
+        update::latest()
     };
+    match release {
+        Err(err) => report.failed(err, &[]),
+        Ok(release) if !release.is_newer() => report.skipped(format!(
+            "comphost {} is the latest release",
+            update::CURRENT_VERSION
+        )),
+        Ok(release) if check || dry_run => report.skipped(format!(
+            "comphost {} is available, this is {}",
+            release.version,
+            update::CURRENT_VERSION
+        )),
+        Ok(release) => {
+            let installed = {
+                let _progress =
+                    output.progress(format!("Downloading comphost {}", release.version));
+                update::install(&release)
+            };
+            match installed {
+                Ok(executable) => report.ok(format!(
+                    "Updated '{}' from {} to {}",
+                    executable.display(),
+                    update::CURRENT_VERSION,
+                    release.version
+                )),
+                Err(err) => report.failed(err, &[]),
+            }
+        }
+    }
+    output.report(report);
+}
 
-    // Define the config directory and file path
-    let config_dir = format!("{}/.config/comphost", home_dir);
-    let config_file_path = format!("{}/config.toml", config_dir);
+/// Shows the last `limit` entries of the audit log.
+fn history_command(limit: usize, output: &Output) -> Result<(), Error> {
+    let mut entries = audit::read()?;
+    entries.drain(..entries.len().saturating_sub(limit));
+    if output.is_json() {
+        output.data(&entries);
+        return Ok(());
+    }
+    if entries.is_empty() {
+        println!("No history");
+    }
+    for entry in entries {
+        println!("{}  {}  comphost {}", entry.time, entry.user, entry.command);
+        if entry.config_changed {
+            println!("  Changed '{}'", entry.config);
+        }
+        for event in entry.events {
+            match event.config {
+                Some(config_name) => println!("  [{}] {}", config_name, event.message),
+                None => println!("  {}", event.message),
+            }
+        }
+        for command in entry.commands {
+            match command.http_status {
+                Some(status) => println!("  > {} (HTTP {})", command.command, status),
+                None => {
+                    let exit_code = command
+                        .exit_code
+                        .map_or_else(|| "none".to_string(), |code| code.to_string());
+                    println!("  $ {} (exit code {})", command.command, exit_code);
+                }
+            }
+        }
+    }
+    Ok(())
+}
 
-    // Ensure the config directory exists
-    if let Err(err) = std::fs::create_dir_all(&config_dir) {
-        eprintln!("Failed to create config directory: {}", err);
-        return;
+/// Runs the command against the config file, returning the exit code for the
+/// reported results.
+fn run(args: Cli) -> Result<ExitCode, Error> {
+    // https://no-color.org: any non-empty NO_COLOR disables colors
+    let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    let output = Output::new(args.output)
+        .with_color(!args.no_color && !no_color)
+        .with_quiet(args.quiet);
+    init_logging(
+        args.verbose,
+        args.log_file.as_deref(),
+        !args.no_color && !no_color,
+    )?;
+
+    if let Commands::History { limit } = args.command {
+        history_command(limit, &output)?;
+        return Ok(output.finish());
+    }
+    if let Commands::SelfUpdate { check } = args.command {
+        self_update(check, &output, args.dry_run);
+        return Ok(output.finish());
+    }
+    let current = workspace::current()?;
+    if let Commands::Workspace { command } = args.command {
+        workspace_command(command, &current, &output, args.dry_run)?;
+        return Ok(output.finish());
     }
+    let workspace = match args.workspace {
+        Some(ref name) if !workspace::exists(name)? => {
+            return Err(Error::UnknownWorkspace(name.clone()))
+        }
+        Some(ref name) => name.clone(),
+        // An explicit config file isn't part of any workspace
+        None if args.config_file.is_some() => workspace::DEFAULT.to_string(),
+        None => current,
+    };
+    let path = match args.config_file {
+        Some(ref path) => path.clone(),
+        None => workspace::path(&workspace)?,
+    };
 
-    // Read the existing TOML content if the file exists
-    let mut existing_content = String::new();
-    if let Ok(mut file) = File::open(&config_file_path) {
-        file.read_to_string(&mut existing_content)
-            .expect("Could not read file");
+    // A running daemon knows the status without reading the config
+    #[cfg(unix)]
+    if let (Commands::List { watch: false, .. }, None) = (&args.command, &args.network) {
+        if let Some(status) = daemon::status(&path) {
+            if output.is_json() {
+                output.data(&status.configs);
+            } else {
+                print_list(&status.configs, status.orphans, &status.network);
+            }
+            return Ok(output.finish());
+        }
     }
 
-    // Deserialize existing TOML content into a HashMap
-    let mut toml_content: HashMap<String, Configuration> = if existing_content.is_empty() {
-        HashMap::new()
-    } else {
-        toml::from_str(&existing_content).expect("Could not parse TOML")
+    // Keep other invocations from changing the config until we're done with it
+    let mut store = ConfigStore::open(&path, || {
+        eprintln!("Waiting for another comphost to finish...")
+    })?;
+    // Runs that changed something go to the audit log, except `logs`, which
+    // only runs compose to follow the output
+    let audited = !matches!(args.command, Commands::Logs { .. });
+    let loaded = std::fs::read(store.path()).ok();
+
+    // Long-running commands only read the config, so they let go of it right away
+    match args.command {
+        Commands::Logs { .. } | Commands::Exec { .. } | Commands::List { watch: true, .. } => {
+            store.unlock()
+        }
+        #[cfg(feature = "tui")]
+        Commands::Ui => store.unlock(),
+        #[cfg(unix)]
+        Commands::Daemon => store.unlock(),
+        Commands::Serve { .. } => store.unlock(),
+        Commands::Add { .. }
+        | Commands::Remove { .. }
+        | Commands::On { .. }
+        | Commands::Off { .. }
+        | Commands::Toggle { .. }
+        | Commands::Profile {
+            command: ProfileCommands::Use { .. },
+        } => {
+            let command: Vec<String> = env::args().skip(1).collect();
+            store.keep_history(command.join(" "));
+        }
+        _ => {}
+    }
+
+    // Remember the detected container runtime so it stays stable across runs
+    let runtime = match store.settings.runtime {
+        Some(runtime) => runtime,
+        None => {
+            let runtime = Runtime::detect().unwrap_or(Runtime::Docker);
+            store.settings.runtime = Some(runtime);
+            runtime
+        }
     };
 
+    if let Some(migrated_from) = store.migrated_from() {
+        output.notice(format!(
+            "Migrated '{}' from version {} to {}",
+            store.path().display(),
+            migrated_from,
+            store.version()
+        ));
+    }
+    let runner = Runner {
+        output: &output,
+        dry_run: args.dry_run,
+        retry: store.settings.retry(),
+    };
+    let network = args
+        .network
+        .clone()
+        .or_else(|| store.settings.network.clone())
+        .unwrap_or_else(|| workspace::default_network(&workspace));
+    let compose = ComposeOps::new(
+        &runner,
+        runtime,
+        network,
+        store.settings.network_options.clone(),
+    );
+    let git = GitOps::new(&runner, store.settings.git_backend);
+    let jobs = args.jobs.or(store.settings.jobs).unwrap_or(config::JOBS);
+
+    // Fail early, with an exit code of its own, when compose can't do anything
+    let needs_runtime = matches!(
+        args.command,
+        Commands::Start { .. }
+            | Commands::Stop { .. }
+            | Commands::PullImages { .. }
+            | Commands::Prune { .. }
+            | Commands::Update { .. }
+            | Commands::Logs { .. }
+            | Commands::Exec { .. }
+            | Commands::Ps
+            | Commands::Ports
+    );
+    if needs_runtime && !compose.check_runtime() {
+        return Ok(output.finish());
+    }
+
     match args.command {
-        Commands::Add { name } => {
+        Commands::Add { name, tags } => {
             for config_name in &name {
                 // Prompt the user for a URL
-                println!("Enter URL for '{}':", config_name);
-                let mut url = String::new();
-                io::stdin()
-                    .read_line(&mut url)
-                    .expect("Failed to read line");
+                let url = output.prompt(&format!("Enter URL for '{}':", config_name));
 
                 // Add or update the new configuration
                 let config = Configuration {
-                    active: true,
-                    url: url.trim().to_string(),
-                    clone_path: None,
+                    tags: tags.clone(),
+                    ..Configuration::new(url)
                 };
-                toml_content.insert(config_name.clone(), config);
-                println!("Configuration '{}' added.", config_name);
+                store.configs.insert(config_name.clone(), config);
+                output.ok(
+                    config_name,
+                    format!("Configuration '{}' added.", config_name),
+                );
             }
         }
-        Commands::On { name } => {
+        Commands::Remove { name, purge } => {
             for config_name in &name {
-                if let Some(config) = toml_content.get_mut(config_name) {
-                    config.active = true;
-                    println!("Configuration '{}' turned on.", config_name);
+                let Some(config) = store.configs.remove(config_name) else {
+                    output.not_found(config_name);
+                    continue;
+                };
+                output.ok(
+                    config_name,
+                    format!("Configuration '{}' removed.", config_name),
+                );
+
+                if !purge {
+                    continue;
+                }
+                if let Some(ref clone_path) = config.clone_path {
+                    if !std::path::Path::new(clone_path).is_dir() {
+                        output.skipped(
+                            config_name,
+                            format!("Nothing to purge, '{}' does not exist", clone_path),
+                        );
+                        continue;
+                    }
+
+                    if args.dry_run {
+                        output.notice(format!("Would delete '{}'", clone_path));
+                        continue;
+                    }
+
+                    // Ask before deleting anything from disk
+                    let answer = output.prompt(&format!("Delete '{}'? [y/N]", clone_path));
+                    if !matches!(answer.as_str(), "y" | "Y" | "yes") {
+                        output.skipped(config_name, format!("Kept '{}'", clone_path));
+                        continue;
+                    }
+                    match std::fs::remove_dir_all(clone_path) {
+                        Ok(()) => output.ok(config_name, format!("Deleted '{}'", clone_path)),
+                        Err(err) => {
+                            output.failed(
+                                config_name,
+                                format!("Failed to delete '{}': {}", clone_path, err),
+                            );
+                            continue;
+                        }
+                    }
+                    if let Some(ref repo) = config.worktree_of {
+                        if let Err(err) = git.prune_worktrees(repo) {
+                            let mut report = Report::new(config_name);
+                            report.failed_as(
+                                Failure::Git,
+                                format!("Failed to prune the worktrees of '{}'", repo),
+                                err.to_string().as_bytes(),
+                            );
+                            output.report(report);
+                        }
+                    }
+                }
+            }
+
+            update_secrets(&store, &output, args.dry_run, |configs| {
+                let count = configs.len();
+                configs.retain(|config_name, _| !name.contains(config_name));
+                configs.len() != count
+            })?;
+        }
+        Commands::Rename { old, new, move_dir } => 'rename: {
+            let Some(config) = store.get(&old) else {
+                output.not_found(&old);
+                break 'rename;
+            };
+            if store.configs.contains_key(&new) {
+                output.failed(&new, format!("Configuration '{}' already exists.", new));
+                break 'rename;
+            }
+
+            // Compose projects are named after their configuration unless named
+            // explicitly, and the containers of a running one would be left behind
+            let renames_project = config.project_name.is_none()
+                && docker::project_name(&old) != docker::project_name(&new);
+            if let Some(project) = config.project(&old) {
+                if (move_dir || renames_project) && compose.is_running(&project) == Some(true) {
+                    let reason = if move_dir {
+                        "moving its directory"
+                    } else {
+                        "renaming it"
+                    };
+                    output.failed(&old, format!("Stop '{}' before {}", old, reason));
+                    break 'rename;
+                }
+            }
+
+            // Move the clone first so a failed move leaves the config untouched
+            let mut new_clone_path = None;
+            if let (true, Some(clone_path)) = (move_dir, config.clone_path.as_deref()) {
+                let from = std::path::Path::new(clone_path);
+                let to = from.with_file_name(&new);
+                if to.exists() {
+                    output.failed(&old, format!("'{}' already exists", to.display()));
+                    break 'rename;
+                }
+                if args.dry_run {
+                    output.notice(format!(
+                        "Would move '{}' to '{}'",
+                        from.display(),
+                        to.display()
+                    ));
+                } else if let Err(err) = std::fs::rename(from, &to) {
+                    output.failed(
+                        &old,
+                        format!("Failed to move '{}': {}", from.display(), err),
+                    );
+                    break 'rename;
+                }
+                new_clone_path = Some(to.display().to_string());
+            }
+
+            store.rename(&old, &new);
+            update_secrets(&store, &output, args.dry_run, |configs| {
+                let Some(values) = configs.remove(&old) else {
+                    return false;
+                };
+                configs.insert(new.clone(), values);
+                true
+            })?;
+            if let Some(clone_path) = new_clone_path {
+                if let Some(config) = store.configs.get_mut(&new) {
+                    config.clone_project(clone_path);
+                }
+            }
+            output.ok(
+                &new,
+                format!("Configuration '{}' renamed to '{}'.", old, new),
+            );
+        }
+        Commands::Edit => edit(&mut store, &runner)?,
+        Commands::Encrypt | Commands::Decrypt => {
+            let encrypt = matches!(args.command, Commands::Encrypt);
+            let path = store.path().display().to_string();
+            let mut report = Report::global();
+            if store.is_encrypted() == encrypt {
+                let state = if encrypt { "encrypted" } else { "plain text" };
+                report.skipped(format!("'{}' is already {}", path, state));
+            } else if encrypt {
+                store.set_passphrase(Some(encryption::passphrase(&path, true)?));
+                report.ok(format!("Encrypted '{}'", path));
+            } else {
+                store.set_passphrase(None);
+                report.ok(format!("Decrypted '{}'", path));
+            }
+            output.report(report);
+        }
+        Commands::Export { format } => match format.serialize(&store.export()) {
+            Ok(content) => print!("{}", content),
+            Err(err) => {
+                let mut report = Report::global();
+                report.failed("Failed to export the config".to_string(), err.as_bytes());
+                output.report(report);
+            }
+        },
+        Commands::Import {
+            file,
+            format,
+            overwrite,
+            skip_existing,
+        } => 'import: {
+            let workspace = format
+                .or_else(|| FileFormat::from_path(&file))
+                .ok_or_else(|| "unknown format, use --format".to_string())
+                .and_then(|format| {
+                    let content = std::fs::read_to_string(&file).map_err(|err| err.to_string())?;
+                    format.deserialize(&content)
+                });
+            let workspace = match workspace {
+                Ok(workspace) => workspace,
+                Err(err) => {
+                    let mut report = Report::global();
+                    report.failed(
+                        format!("Failed to import '{}': {}", file.display(), err),
+                        &[],
+                    );
+                    output.report(report);
+                    break 'import;
+                }
+            };
+
+            let merge = if overwrite {
+                Merge::Overwrite
+            } else if skip_existing {
+                Merge::SkipExisting
+            } else {
+                Merge::Refuse
+            };
+            report_import(&output, store.import(workspace, merge));
+        }
+        Commands::SyncConfig { from, file } => 'sync: {
+            let Some(url) = from.or_else(|| store.settings.sync_from.clone()) else {
+                let mut report = Report::global();
+                report.failed("No repository to sync from, use --from".to_string(), &[]);
+                output.report(report);
+                break 'sync;
+            };
+            let workspace = git
+                .clone_temp(&url)
+                .map_err(|err| err.to_string())
+                .and_then(|clone| {
+                    let path = match file {
+                        Some(ref file) => clone.path().join(file),
+                        None => WORKSPACE_FILES
+                            .iter()
+                            .map(|file| clone.path().join(file))
+                            .find(|path| path.is_file())
+                            .ok_or_else(|| {
+                                format!("none of {} found", WORKSPACE_FILES.join(", "))
+                            })?,
+                    };
+                    let format = FileFormat::from_path(&path)
+                        .ok_or_else(|| format!("unknown format of '{}'", path.display()))?;
+                    let content = std::fs::read_to_string(&path).map_err(|err| err.to_string())?;
+                    format.deserialize(&content)
+                });
+            let workspace = match workspace {
+                Ok(workspace) => workspace,
+                Err(err) => {
+                    let mut report = Report::global();
+                    report.failed(format!("Failed to sync from '{}'", url), err.as_bytes());
+                    output.report(report);
+                    break 'sync;
+                }
+            };
+            report_import(&output, store.import(workspace, Merge::Update));
+            store.settings.sync_from = Some(url);
+        }
+        Commands::ImportDir { path, depth, tags } => {
+            let mut repos = Vec::new();
+            match std::fs::canonicalize(&path) {
+                Ok(path) => find_repos(&path, depth, &mut repos),
+                Err(err) => {
+                    let mut report = Report::global();
+                    report.failed(format!("Failed to read '{}': {}", path.display(), err), &[]);
+                    output.report(report);
+                }
+            }
+
+            for repo in repos {
+                let Some(config_name) = repo.file_name().map(|name| name.to_string_lossy()) else {
+                    continue;
+                };
+                let config_name = config_name.to_string();
+                let clone_path = repo.display().to_string();
+                if !has_compose_file(&repo) {
+                    output.skipped(
+                        &config_name,
+                        format!("Skipping '{}', it has no compose file", clone_path),
+                    );
+                    continue;
+                }
+                let existing = store
+                    .configs
+                    .iter()
+                    .find(|(_, config)| config.clone_path.as_deref() == Some(clone_path.as_str()));
+                if let Some((existing, _)) = existing {
+                    output.skipped(
+                        &config_name,
+                        format!("'{}' is already configured as '{}'", clone_path, existing),
+                    );
+                    continue;
+                }
+                if store.configs.contains_key(&config_name) {
+                    output.failed(
+                        &config_name,
+                        format!(
+                            "Configuration '{}' already exists, not adding '{}'",
+                            config_name, clone_path
+                        ),
+                    );
+                    continue;
+                }
+                let url = match git.origin_url(&clone_path) {
+                    Ok(url) => url,
+                    Err(err) => {
+                        let mut report = Report::new(&config_name);
+                        report.failed(
+                            format!("Failed to read the origin of '{}'", clone_path),
+                            err.to_string().as_bytes(),
+                        );
+                        output.report(report);
+                        continue;
+                    }
+                };
+
+                let config = Configuration {
+                    clone_path: Some(clone_path.clone()),
+                    tags: tags.clone(),
+                    ..Configuration::new(url)
+                };
+                store.configs.insert(config_name.clone(), config);
+                output.ok(
+                    &config_name,
+                    format!(
+                        "Configuration '{}' added for '{}'.",
+                        config_name, clone_path
+                    ),
+                );
+            }
+        }
+        Commands::On {
+            name,
+            tags,
+            all,
+            only,
+        } => {
+            let selected = if all {
+                store.configs.keys().cloned().collect()
+            } else {
+                with_tagged(&store.configs, name, &tags, &output)
+            };
+            if only {
+                for config_name in &selected {
+                    if !store.configs.contains_key(config_name) {
+                        output.not_found(config_name);
+                    }
+                }
+                if !output.failures().is_empty() {
+                    output.notice("No configuration changed.".to_string());
                 } else {
-                    eprintln!("Configuration '{}' not found.", config_name);
+                    for (config_name, config) in &mut store.configs {
+                        let active = selected.contains(config_name);
+                        if active {
+                            output.ok(
+                                config_name,
+                                format!("Configuration '{}' turned on.", config_name),
+                            );
+                        } else if config.active {
+                            output.ok(
+                                config_name,
+                                format!("Configuration '{}' turned off.", config_name),
+                            );
+                        }
+                        config.active = active;
+                    }
+                }
+            } else {
+                for config_name in &selected {
+                    if let Some(config) = store.configs.get_mut(config_name) {
+                        config.active = true;
+                        output.ok(
+                            config_name,
+                            format!("Configuration '{}' turned on.", config_name),
+                        );
+                    } else {
+                        output.not_found(config_name);
+                    }
                 }
             }
         }
-        Commands::Off { name } => {
-            for config_name in &name {
-                if let Some(config) = toml_content.get_mut(config_name) {
+        Commands::Off { name, tags, all } => {
+            let selected = if all {
+                store.configs.keys().cloned().collect()
+            } else {
+                with_tagged(&store.configs, name, &tags, &output)
+            };
+            for config_name in &selected {
+                if let Some(config) = store.configs.get_mut(config_name) {
                     config.active = false;
-                    println!("Configuration '{}' turned off.", config_name);
+                    output.ok(
+                        config_name,
+                        format!("Configuration '{}' turned off.", config_name),
+                    );
                 } else {
-                    eprintln!("Configuration '{}' not found.", config_name);
+                    output.not_found(config_name);
                 }
             }
         }
-        Commands::Clone => {
-            println!("Enter the path where you want to clone:");
-            let mut clone_dir = String::new();
-            io::stdin()
-                .read_line(&mut clone_dir)
-                .expect("Failed to read line");
+        Commands::Toggle { name } => {
+            for config_name in &name {
+                if let Some(config) = store.configs.get_mut(config_name) {
+                    config.active = !config.active;
+                    output.ok(
+                        config_name,
+                        format!(
+                            "Configuration '{}' turned {}.",
+                            config_name,
+                            if config.active { "on" } else { "off" }
+                        ),
+                    );
+                } else {
+                    output.not_found(config_name);
+                }
+            }
+        }
+        Commands::Undo => {
+            if args.dry_run {
+                match store.history().last() {
+                    Some(change) => output.notice(format!("Would undo '{}'", change.command)),
+                    None => output.notice("Nothing to undo.".to_string()),
+                }
+            } else {
+                match store.undo()? {
+                    Some(command) => {
+                        let mut report = Report::global();
+                        report.ok(format!("Undid '{}'.", command));
+                        output.report(report);
+                    }
+                    None => output.notice("Nothing to undo.".to_string()),
+                }
+            }
+        }
+        Commands::Restore { backup: None } => {
+            let names: Vec<String> = store
+                .backups()?
+                .iter()
+                .filter_map(|backup| backup.file_name())
+                .map(|name| name.to_string_lossy().to_string())
+                .collect();
+            if output.is_json() {
+                output.data(&names);
+            } else if names.is_empty() {
+                println!("No backups");
+            } else {
+                for name in names {
+                    println!("{}", name);
+                }
+            }
+        }
+        Commands::Restore {
+            backup: Some(backup),
+        } => {
+            if args.dry_run {
+                output.notice(format!(
+                    "Would restore '{}' from '{}'",
+                    store.path().display(),
+                    backup
+                ));
+            } else {
+                store.restore(&backup)?;
+                let mut report = Report::global();
+                report.ok(format!(
+                    "Restored '{}' from '{}'.",
+                    store.path().display(),
+                    backup
+                ));
+                output.report(report);
+            }
+        }
+        Commands::Clone {
+            name,
+            path,
+            tags,
+            depth,
+        } => {
+            let selected: Vec<String> = if name.is_empty() {
+                store
+                    .configs
+                    .iter()
+                    .filter(|(_, config)| config.is_selected(&tags))
+                    .map(|(config_name, _)| config_name.clone())
+                    .collect()
+            } else {
+                let mut selected = with_tagged(&store.configs, name, &tags, &output);
+                selected.retain(|config_name| {
+                    let found = store.configs.contains_key(config_name);
+                    if !found {
+                        output.not_found(config_name);
+                    }
+                    found
+                });
+                selected
+            };
 
-            let clone_dir = clone_dir.trim();
-            for (config_name, config) in &mut toml_content {
-                if config.active {
-                    let clone_path = format!("{}/{}", clone_dir, config_name);
+            let clone_dir = match path.or_else(|| store.settings.workspace_dir.clone()) {
+                Some(clone_dir) => clone_dir,
+                None => output.prompt("Enter the path where you want to clone:"),
+            };
+            let clone_dir = clone_dir.as_str();
+            let mut pending = Vec::new();
+            for (config_name, config) in &mut store.configs {
+                if selected.contains(config_name) {
+                    let clone_path = Path::new(clone_dir).join(config_name).display().to_string();
                     if let Ok(metadata) = std::fs::metadata(&clone_path) {
                         if metadata.is_dir() {
-                            println!(
-                                "Skipping '{}', folder already exists at '{}'",
-                                config_name, clone_path
+                            output.skipped(
+                                config_name,
+                                format!(
+                                    "Skipping '{}', folder already exists at '{}'",
+                                    config_name, clone_path
+                                ),
                             );
                             config.clone_project(clone_path);
                             continue;
                         } else {
-                            eprintln!("Path '{}' exists but is not a directory", clone_path);
+                            output.failed(
+                                config_name,
+                                format!("Path '{}' exists but is not a directory", clone_path),
+                            );
                             continue;
                         }
                     }
+                    let mut options = config.clone_options();
+                    options.depth = depth.or(config.depth).or(store.settings.clone_depth);
+                    options.timeout = store.settings.clone_timeout;
+                    pending.push((config_name.clone(), config.url.clone(), clone_path, options));
+                }
+            }
 
-                    let clone_command = Command::new("git")
-                        .arg("clone")
-                        .arg(&config.url)
-                        .arg(config_name)
-                        .current_dir(clone_dir)
-                        .output()
-                        .expect("Failed to execute git clone command");
-
-                    if clone_command.status.success() {
-                        println!(
+            let cloned = run_parallel(jobs, pending, |(config_name, url, clone_path, options)| {
+                let mut report = Report::new(&config_name);
+                let progress = output.progress(format!("Cloning '{}'", config_name));
+                let result = git.clone_repo(&url, clone_dir, &config_name, &options);
+                drop(progress);
+                let cloned = match result {
+                    Ok(()) => {
+                        report.ok(format!(
                             "Cloned '{}' from '{}' to '{}'",
-                            config_name, config.url, clone_path
-                        );
-                        config.clone_project(clone_path);
-                    } else {
-                        eprintln!(
-                            "Failed to clone '{}' from '{}' to '{}'",
-                            config_name, config.url, clone_path
+                            config_name, url, clone_path
+                        ));
+                        true
+                    }
+                    Err(err) => {
+                        report.failed_as(
+                            Failure::Git,
+                            format!(
+                                "Failed to clone '{}' from '{}' to '{}'",
+                                config_name, url, clone_path
+                            ),
+                            err.to_string().as_bytes(),
                         );
-                        io::stderr().write_all(&clone_command.stderr).unwrap();
+                        false
+                    }
+                };
+                output.report(report);
+                cloned.then_some((config_name, clone_path))
+            });
+
+            for (config_name, clone_path) in cloned.into_iter().flatten() {
+                if let Some(config) = store.configs.get_mut(&config_name) {
+                    config.clone_project(clone_path);
+                }
+            }
+        }
+        Commands::Pull {
+            allow_dirty,
+            autostash,
+        } => {
+            let mut selected: Vec<String> = store
+                .configs
+                .iter()
+                .filter(|(_, config)| config.active && config.clone_path.is_some())
+                .map(|(config_name, _)| config_name.clone())
+                .collect();
+            let dirty = match allow_dirty {
+                true => Vec::new(),
+                false => skip_dirty(&store.configs, &mut selected, autostash, &git),
+            };
+            for config_name in &selected {
+                let config = &store.configs[config_name];
+                let clone_path = config
+                    .clone_path
+                    .as_deref()
+                    .expect("Selected configurations are cloned");
+                if git.verify_branch(config_name, clone_path, config.branch.as_deref()) {
+                    git.pull(config_name, clone_path, &pull_options(config, autostash));
+                }
+            }
+            report_dirty(&dirty, &output);
+        }
+        Commands::Start {
+            name,
+            tags,
+            atomic,
+            options,
+        } => {
+            let mut selected = select_cloned(&store.configs, name, &tags, &output);
+            let failures = output.failures().len();
+            let was_running: BTreeSet<String> = match atomic {
+                true => running(&store.configs, &selected, &compose),
+                false => BTreeSet::new(),
+            };
+            if options.pull {
+                let pulled = pull_images(&store.configs, selected.clone(), jobs, &compose);
+                selected.retain(|config_name| pulled.contains(config_name));
+            }
+            let options = StartOptions {
+                proxy: store.settings.proxy,
+                override_template: store.settings.override_template.clone(),
+                wait_for_timeout: store.settings.wait_for_timeout,
+                up_timeout: store.settings.up_timeout,
+                secrets: Secrets::open(store.path())?.configs,
+                ..StartOptions::from(options)
+            };
+            if !selected.is_empty() && compose.ensure_network() {
+                start_in_order(
+                    &mut store.configs,
+                    &selected,
+                    &options,
+                    store.settings.remap_ports,
+                    jobs,
+                    &compose,
+                    &git,
+                    &output,
+                );
+                if atomic && output.failures().len() > failures {
+                    let options = StopOptions {
+                        down_timeout: store.settings.down_timeout,
+                        ..StopOptions::default()
+                    };
+                    roll_back(
+                        &store.configs,
+                        &selected,
+                        &was_running,
+                        &options,
+                        &compose,
+                        &output,
+                    );
+                }
+                if let Some(ref domain) = store.settings.hosts_domain {
+                    compose.sync_hosts(&store.configs, domain);
+                }
+            }
+            if store.settings.notify_failures {
+                notify::failures(&output, "start", &output.failures());
+            }
+        }
+        Commands::PullImages { name, tags } => {
+            let selected = select_cloned(&store.configs, name, &tags, &output);
+            pull_images(&store.configs, selected, jobs, &compose);
+        }
+        Commands::Update {
+            name,
+            tags,
+            allow_dirty,
+            autostash,
+        } => {
+            let mut selected = select_cloned(&store.configs, name, &tags, &output);
+            let dirty = match allow_dirty {
+                true => Vec::new(),
+                false => skip_dirty(&store.configs, &mut selected, autostash, &git),
+            };
+            let pulled = run_parallel(jobs, selected.clone(), |config_name| {
+                let config = &store.configs[&config_name];
+                let clone_path = config.clone_path.as_deref()?;
+                if !git.verify_branch(&config_name, clone_path, config.branch.as_deref()) {
+                    return None;
+                }
+                git.pull(&config_name, clone_path, &pull_options(config, autostash))
+            });
+
+            let mut changed = Vec::new();
+            for (config_name, pulled) in selected.into_iter().zip(pulled) {
+                match pulled {
+                    // Whether the pull would bring anything is unknown
+                    Some(_) if args.dry_run => output.notice(format!(
+                        "Would pull '{}' and restart it if anything changed",
+                        config_name
+                    )),
+                    Some(0) => output.skipped(
+                        &config_name,
+                        format!("'{}' is up to date, not restarting it", config_name),
+                    ),
+                    Some(_) => changed.push(config_name),
+                    None => {}
+                }
+            }
+            let options = StartOptions {
+                build: true,
+                proxy: store.settings.proxy,
+                override_template: store.settings.override_template.clone(),
+                wait_for_timeout: store.settings.wait_for_timeout,
+                up_timeout: store.settings.up_timeout,
+                secrets: Secrets::open(store.path())?.configs,
+                ..StartOptions::default()
+            };
+            if !changed.is_empty() && compose.ensure_network() {
+                start_in_order(
+                    &mut store.configs,
+                    &changed,
+                    &options,
+                    store.settings.remap_ports,
+                    jobs,
+                    &compose,
+                    &git,
+                    &output,
+                );
+                if let Some(ref domain) = store.settings.hosts_domain {
+                    compose.sync_hosts(&store.configs, domain);
+                }
+            }
+            report_dirty(&dirty, &output);
+            if store.settings.notify_failures {
+                notify::failures(&output, "update", &output.failures());
+            }
+        }
+        Commands::Stop {
+            name,
+            tags,
+            all,
+            keep_network,
+            volumes,
+            rmi,
+        } => {
+            let selected: Vec<String> = if all {
+                store
+                    .configs
+                    .iter()
+                    .filter(|(_, config)| config.clone_path.is_some())
+                    .map(|(config_name, _)| config_name.clone())
+                    .collect()
+            } else {
+                select_cloned(&store.configs, name, &tags, &output)
+            };
+
+            let options = StopOptions {
+                volumes,
+                remove_images: rmi,
+                down_timeout: store.settings.down_timeout,
+            };
+            match dependency_waves(&store.configs, &selected) {
+                Ok(waves) => {
+                    // Tear down dependents before the projects they depend on
+                    let mut all_stopped = true;
+                    for wave in waves.into_iter().rev() {
+                        let stopped = run_parallel(jobs, wave, |config_name| {
+                            compose.stop(&options, &config_name, &store.configs[&config_name])
+                        });
+                        all_stopped &= stopped.into_iter().all(|stopped| stopped);
+                    }
+                    if let Some(ref domain) = store.settings.hosts_domain {
+                        compose.sync_hosts(&store.configs, domain);
                     }
+
+                    if all_stopped && !keep_network {
+                        compose.remove_unused_network(&store.configs, &selected);
+                    }
+                }
+                Err(cycle) => {
+                    let mut report = Report::global();
+                    report.failed(
+                        format!("Dependency cycle detected: {}", cycle.join(" -> ")),
+                        &[],
+                    );
+                    output.report(report);
                 }
             }
         }
-        Commands::Start => {
-            // Check if the comphost network exists
-            let network_check_command = Command::new("docker")
-                .args(&["network", "inspect", "comphost"])
-                .output()
-                .expect("Failed to execute docker network inspect command");
+        Commands::Prune {
+            name,
+            tags,
+            all,
+            orphans,
+        } => {
+            let selected: Vec<String> = if all {
+                store
+                    .configs
+                    .iter()
+                    .filter(|(_, config)| config.clone_path.is_some())
+                    .map(|(config_name, _)| config_name.clone())
+                    .collect()
+            } else {
+                select_cloned(&store.configs, name, &tags, &output)
+            };
+            for config_name in selected {
+                let project = store.configs[&config_name]
+                    .project(&config_name)
+                    .expect("Selected configurations are cloned");
+                compose.prune(&config_name, &project);
+            }
+            if orphans {
+                compose.remove_orphans(&store.configs);
+            }
+        }
+        Commands::Logs { name, follow, tail } => {
+            let mut projects = Vec::new();
+            if name.is_empty() {
+                for (config_name, config) in &store.configs {
+                    if let (true, Some(project)) = (config.active, config.project(config_name)) {
+                        projects.push((config_name, project));
+                    }
+                }
+            } else {
+                for config_name in &name {
+                    match store.configs.get_key_value(config_name) {
+                        Some((config_name, config)) => match config.project(config_name) {
+                            Some(project) => projects.push((config_name, project)),
+                            None => output.skipped(
+                                config_name,
+                                format!("Configuration '{}' is not cloned.", config_name),
+                            ),
+                        },
+                        None => output.not_found(config_name),
+                    }
+                }
+            }
 
-            if !network_check_command.status.success() {
-                // Create the comphost network if it does not exist
-                let create_network_command = Command::new("docker")
-                    .args(&["network", "create", "comphost"])
-                    .output()
-                    .expect("Failed to execute docker network create command");
+            // Pad the prefixes so the log lines stay aligned
+            let width = projects
+                .iter()
+                .map(|(config_name, _)| config_name.len())
+                .max()
+                .unwrap_or(0);
+            thread::scope(|scope| {
+                for (config_name, project) in projects {
+                    let runner = &runner;
+                    let output = &output;
+                    scope.spawn(move || {
+                        let mut logs_command = project.command(runtime);
+                        logs_command.arg("logs");
+                        if follow {
+                            logs_command.arg("--follow");
+                        }
+                        if let Some(tail) = tail {
+                            logs_command.arg("--tail").arg(tail.to_string());
+                        }
 
-                if create_network_command.status.success() {
-                    println!("Created comphost network");
-                } else {
-                    eprintln!("Failed to create comphost network");
-                    io::stderr()
-                        .write_all(&create_network_command.stderr)
-                        .unwrap();
-                    return;
-                }
-            }
-
-            for (config_name, config) in &mut toml_content {
-                if config.active {
-                    if let Some(ref clone_path) = config.clone_path {
-                        let start_command = Command::new("docker")
-                            .arg("compose")
-                            .arg("up")
-                            .arg("--detach")
-                            .current_dir(clone_path)
-                            .output()
-                            .expect("Failed to execute docker compose up command");
-
-                        if start_command.status.success() {
-                            println!("Started Docker Compose for '{}'", config_name);
-
-                            // Retrieve container IDs
-                            let ps_output = Command::new("docker")
-                                .args(&["compose", "ps", "--format", "{{.ID}}"])
-                                .current_dir(clone_path)
-                                .output()
-                                .expect("Failed to execute docker ps command");
-                            let container_ids = String::from_utf8_lossy(&ps_output.stdout);
-
-                            // Attach containers to the comphost network
-                            for container_id in container_ids.split_whitespace() {
-                                let attach_command = Command::new("docker")
-                                    .arg("network")
-                                    .arg("connect")
-                                    .arg("comphost")
-                                    .arg(container_id)
-                                    .output()
-                                    .expect("Failed to execute docker network connect command");
-
-                                if attach_command.status.success() {
-                                    println!(
-                                        "Attached container '{}' to comphost network for '{}'",
-                                        container_id, config_name
-                                    );
-                                } else {
-                                    eprintln!(
-                                        "Failed to attach container '{}' to comphost network for '{}'",
-                                        container_id, config_name
-                                    );
-                                    io::stderr().write_all(&attach_command.stderr).unwrap();
-                                }
-                            }
-                        } else {
-                            eprintln!("Failed to start Docker Compose for '{}'", config_name);
-                            io::stderr().write_all(&start_command.stderr).unwrap();
+                        let prefix = format!("{:<width$}", config_name, width = width);
+                        match runner.stream(&mut logs_command, &prefix) {
+                            Ok(status) if status.success() => {}
+                            Ok(_) => output.failed(
+                                config_name,
+                                format!("Failed to show logs for '{}'", config_name),
+                            ),
+                            Err(err) => output.failed(
+                                config_name,
+                                format!("Failed to execute docker compose logs command: {}", err),
+                            ),
                         }
+                    });
+                }
+            });
+        }
+        Commands::Exec {
+            config: config_name,
+            service,
+            command,
+        } => match store
+            .configs
+            .get(&config_name)
+            .map(|config| config.project(&config_name))
+        {
+            Some(Some(project)) => {
+                let mut exec_command = project.command(runtime);
+                exec_command.args(["exec", &service]);
+                if command.is_empty() {
+                    exec_command.arg("sh");
+                } else {
+                    exec_command.args(&command);
+                }
+
+                match runner.interactive(&mut exec_command) {
+                    Ok(status) if status.success() => {}
+                    Ok(status) => output.failed(
+                        &config_name,
+                        format!(
+                            "Command in '{}' of '{}' failed ({})",
+                            service, config_name, status
+                        ),
+                    ),
+                    Err(err) => output.failed(
+                        &config_name,
+                        format!("Failed to execute docker compose exec command: {}", err),
+                    ),
+                }
+            }
+            Some(_) => output.failed(
+                &config_name,
+                format!("Configuration '{}' is not cloned.", config_name),
+            ),
+            None => output.not_found(&config_name),
+        },
+        Commands::RenderOverride {
+            config: config_name,
+        } => match store.configs.get(&config_name) {
+            Some(config) if config.clone_path.is_some() => {
+                let default_template = store.settings.override_template.as_deref();
+                match compose.rendered_override(&config_name, config, default_template) {
+                    Ok(Some(rendered)) => print!("{}", rendered),
+                    Ok(None) => output.failed(
+                        &config_name,
+                        format!("Configuration '{}' has no override_template.", config_name),
+                    ),
+                    Err(err) => {
+                        let mut report = Report::new(&config_name);
+                        report.failed(
+                            format!("Failed to render the override of '{}'", config_name),
+                            err.as_bytes(),
+                        );
+                        output.report(report);
                     }
                 }
             }
+            Some(_) => output.failed(
+                &config_name,
+                format!("Configuration '{}' is not cloned.", config_name),
+            ),
+            None => output.not_found(&config_name),
+        },
+        Commands::Secret { command } => secret_command(command, &store, &output, args.dry_run)?,
+        Commands::Profile {
+            command: ProfileCommands::Save { name },
+        } => {
+            let active: Vec<String> = store
+                .configs
+                .iter()
+                .filter(|(_, config)| config.active)
+                .map(|(config_name, _)| config_name.clone())
+                .collect();
+            let mut report = Report::global();
+            report.ok(format!(
+                "Profile '{}' saved with {} active configuration(s).",
+                name,
+                active.len()
+            ));
+            output.report(report);
+            store.profiles.insert(name, active);
         }
-        Commands::Stop => {
-            for (config_name, config) in &toml_content {
-                if config.active {
-                    if let Some(ref clone_path) = config.clone_path {
-                        let stop_command = Command::new("docker")
-                            .arg("compose")
-                            .arg("down")
-                            .current_dir(clone_path)
-                            .output()
-                            .expect("Failed to execute docker compose down command");
+        Commands::Profile {
+            command: ProfileCommands::Use { name },
+        } => {
+            if let Some(profile) = store.profiles.get(&name) {
+                for (config_name, config) in &mut store.configs {
+                    config.active = profile.contains(config_name);
+                }
+                for config_name in profile {
+                    if !store.configs.contains_key(config_name) {
+                        output.skipped(
+                            config_name,
+                            format!("Configuration '{}' no longer exists.", config_name),
+                        );
+                    }
+                }
+                let mut report = Report::global();
+                report.ok(format!("Profile '{}' is now active.", name));
+                output.report(report);
+            } else {
+                let mut report = Report::global();
+                report.failed_as(
+                    Failure::NotFound,
+                    format!("Profile '{}' not found.", name),
+                    &[],
+                );
+                output.report(report);
+            }
+        }
+        Commands::Profile {
+            command: ProfileCommands::List,
+        } => {
+            if output.is_json() {
+                output.data(&store.profiles);
+            } else if store.profiles.is_empty() {
+                println!("No profiles");
+            } else {
+                let rows: Vec<[String; 2]> = store
+                    .profiles
+                    .iter()
+                    .map(|(name, config_names)| [name.clone(), config_names.join(", ")])
+                    .collect();
+                print_table(["PROFILE", "CONFIGURATIONS"], &rows, "");
+            }
+        }
+        Commands::List { watch: false, .. } => {
+            let entries = compose.status(&store.configs);
+            if output.is_json() {
+                output.data(&entries);
+            } else {
+                // Best effort like the running column, the table is what was asked for
+                let orphans = compose.orphans(&store.configs).unwrap_or_default();
+                print_list(&entries, orphans, compose.network());
+            }
+        }
+        Commands::List {
+            watch: true,
+            interval,
+        } => loop {
+            // Reload every time, so toggled and added configurations show up
+            let mut store = ConfigStore::open(&path, || {})?;
+            store.unlock();
+            let entries = compose.status(&store.configs);
+            if output.is_json() {
+                // One document per line
+                println!(
+                    "{}",
+                    serde_json::to_string(&entries).expect("Could not serialize to JSON")
+                );
+            } else {
+                if io::stdout().is_terminal() {
+                    // Clear the screen like `watch` does
+                    print!("\x1b[2J\x1b[H");
+                } else {
+                    println!();
+                }
+                println!("Every {}s, press Ctrl-C to stop", interval);
+                println!();
+                let orphans = compose.orphans(&store.configs).unwrap_or_default();
+                print_list(&entries, orphans, compose.network());
 
-                        if stop_command.status.success() {
-                            println!("Stopped Docker Compose for '{}'", config_name);
-                        } else {
-                            eprintln!("Failed to stop Docker Compose for '{}'", config_name);
-                            io::stderr().write_all(&stop_command.stderr).unwrap();
-                        }
+                let mut projects = Vec::new();
+                for entry in entries.iter().filter(|entry| entry.running == Some(true)) {
+                    let project = store.configs[&entry.name]
+                        .project(&entry.name)
+                        .expect("Running configurations are cloned");
+                    if let Ok(containers) = compose.containers(&project) {
+                        projects.push(ProjectContainers {
+                            config: &entry.name,
+                            containers,
+                        });
+                    }
+                }
+                if !projects.is_empty() {
+                    println!();
+                    print_containers(&projects);
+                }
+            }
+            thread::sleep(Duration::from_secs(interval));
+        },
+        Commands::Ps => {
+            let mut projects = Vec::new();
+            for (config_name, config) in &store.configs {
+                let (true, Some(project)) = (config.active, config.project(config_name)) else {
+                    continue;
+                };
+                match compose.containers(&project) {
+                    Ok(containers) => projects.push(ProjectContainers {
+                        config: config_name,
+                        containers,
+                    }),
+                    Err(err) => {
+                        let mut report = Report::new(config_name);
+                        report.failed(
+                            format!("Failed to list containers for '{}'", config_name),
+                            err.as_bytes(),
+                        );
+                        output.report(report);
                     }
                 }
             }
+
+            if output.is_json() {
+                output.data(&projects);
+            } else {
+                print_containers(&projects);
+            }
         }
-        Commands::ListNames => {
-            for config_name in toml_content.keys() {
-                println!("{}", config_name);
+        Commands::Hosts { clear } => match store.settings.hosts_domain {
+            // Without configurations the block is removed
+            _ if clear => compose.sync_hosts(&BTreeMap::new(), ""),
+            Some(ref domain) => compose.sync_hosts(&store.configs, domain),
+            None => {
+                let mut report = Report::global();
+                report.failed(
+                    "Set hosts_domain in [settings] to manage hosts file entries".to_string(),
+                    &[],
+                );
+                output.report(report);
             }
+        },
+        Commands::Ports => {
+            let mut entries = Vec::new();
+            for (config_name, config) in &store.configs {
+                let (true, Some(project)) = (config.active, config.project(config_name)) else {
+                    continue;
+                };
+                match compose.published_ports(&project) {
+                    Ok(ports) => {
+                        entries.extend(ports.iter().map(|port| {
+                            let published = ports::remapped(port, &config.remapped_ports);
+                            PortEntry {
+                                config: config_name,
+                                remapped_from: (published.port != port.port).then_some(port.port),
+                                service: published.service,
+                                host_ip: published.host_ip,
+                                host_port: published.port,
+                                target: published.target,
+                                protocol: published.protocol,
+                            }
+                        }));
+                    }
+                    Err(err) => {
+                        let mut report = Report::new(config_name);
+                        report.failed(
+                            format!("Failed to read the ports of '{}'", config_name),
+                            err.as_bytes(),
+                        );
+                        output.report(report);
+                    }
+                }
+            }
+
+            if output.is_json() {
+                output.data(&entries);
+            } else if entries.is_empty() {
+                println!("No published ports");
+            } else {
+                let rows: Vec<[String; 4]> = entries
+                    .iter()
+                    .map(|entry| {
+                        let host = match entry.host_ip {
+                            Some(ref host_ip) => format!("{}:{}", host_ip, entry.host_port),
+                            None => entry.host_port.to_string(),
+                        };
+                        let host = match entry.remapped_from {
+                            Some(port) => format!("{} (remapped from {})", host, port),
+                            None => host,
+                        };
+                        [
+                            entry.config.to_string(),
+                            entry.service.clone(),
+                            format!("{}/{}", entry.target, entry.protocol),
+                            host,
+                        ]
+                    })
+                    .collect();
+                print_table(["CONFIG", "SERVICE", "PORT", "HOST PORT"], &rows, "");
+            }
+        }
+        Commands::Serve { port } => comphost::server::run(
+            store.path(),
+            runtime,
+            compose.network().to_string(),
+            store.settings.network_options.clone(),
+            store.settings.retry(),
+            args.dry_run,
+            port,
+        )?,
+        #[cfg(unix)]
+        Commands::Daemon => daemon::serve(store.path(), &compose, &output)?,
+        #[cfg(feature = "tui")]
+        Commands::Ui => comphost::ui::run(
+            store.path(),
+            runtime,
+            compose.network().to_string(),
+            store.settings.network_options.clone(),
+            store.settings.retry(),
+            args.dry_run,
+        )?,
+        Commands::Completions { .. }
+        | Commands::Workspace { .. }
+        | Commands::History { .. }
+        | Commands::SelfUpdate { .. } => {
+            unreachable!("handled before loading the config")
+        }
+        Commands::ListNames {
+            active_only,
+            inactive_only,
+            with_state,
+        } => {
+            let active = match (active_only, inactive_only) {
+                (true, _) => Some(true),
+                (_, true) => Some(false),
+                _ => None,
+            };
+            let configs = config_states(&store.configs, active);
+            if output.is_json() {
+                if with_state {
+                    output.data(&configs);
+                } else {
+                    output.data(&configs.keys().collect::<Vec<_>>());
+                }
+            } else {
+                for (config_name, active) in configs {
+                    if with_state {
+                        println!("{}\t{}", config_name, if active { "on" } else { "off" });
+                    } else {
+                        println!("{}", config_name);
+                    }
+                }
+            }
+        }
+    }
+
+    if args.dry_run {
+        if let Some(content) = store.changes()? {
+            output.notice(format!(
+                "Would write '{}':\n{}",
+                store.path().display(),
+                content.trim_end()
+            ));
+        }
+        return Ok(output.finish());
+    }
+
+    // Write the updated config back to the file
+    let config_path = store.path().to_path_buf();
+    let path = config_path.display().to_string();
+    let encrypted = store.is_encrypted();
+    if let Err(err) = store.save() {
+        let mut report = Report::global();
+        report.failed(
+            format!("Failed to write '{}'", path),
+            err.to_string().as_bytes(),
+        );
+        output.report(report);
+    }
+
+    let config_changed = std::fs::read(&config_path).ok() != loaded;
+    let (events, commands) = output.take_log();
+    if audited && (config_changed || !commands.is_empty()) {
+        let entry = audit::Entry::new(path, config_changed, commands, events, encrypted);
+        if let Err(err) = audit::append(&entry) {
+            let mut report = Report::global();
+            report.failed(
+                "Failed to write the audit log".to_string(),
+                err.to_string().as_bytes(),
+            );
+            output.report(report);
         }
     }
 
-    // Serialize the updated HashMap back to TOML
-    let toml_string = toml::to_string(&toml_content).expect("Could not serialize to TOML");
-
-    // Write the updated TOML content back to the file
-    let mut file = OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .create(true)
-        .open(config_file_path)
-        .expect("Could not open file");
-    write!(file, "{}", toml_string).expect("Could not write to file");
+    Ok(output.finish())
 }
@@ -0,0 +1,34 @@
+//! Desktop notifications about failed operations, turned on with the
+//! `notify_failures` setting, since long runs often finish while the terminal
+//! is out of sight.
+
+use crate::output::Output;
+
+/// Notifies that `command` failed for `failures`, the configurations or
+/// messages [`Output::failures`] returns. Nothing is shown without failures.
+pub fn failures(output: &Output, command: &str, failures: &[String]) {
+    if failures.is_empty() {
+        return;
+    }
+    let summary = format!("comphost {} failed", command);
+    let body = format!("Failed: {}", failures.join(", "));
+    if let Err(err) = show(&summary, &body) {
+        output.notice(format!("Failed to show a desktop notification: {}", err));
+    }
+}
+
+#[cfg(feature = "notifications")]
+fn show(summary: &str, body: &str) -> Result<(), String> {
+    notify_rust::Notification::new()
+        .appname("comphost")
+        .summary(summary)
+        .body(body)
+        .show()
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+#[cfg(not(feature = "notifications"))]
+fn show(_summary: &str, _body: &str) -> Result<(), String> {
+    Err("comphost was built without the notifications feature".to_string())
+}
@@ -0,0 +1,704 @@
+//! Reporting results, as plain text or as one JSON document, and running
+//! external commands with dry-run support.
+
+use crate::error::{self, Failure};
+use clap::ValueEnum;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, IsTerminal, Write};
+use std::process::{self, Command, ExitCode, ExitStatus, Stdio};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, trace};
+
+/// How results are written to the terminal.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human readable messages
+    Plain,
+    /// A single JSON document on stdout
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Skipped,
+    Failed,
+}
+
+impl Status {
+    /// ANSI color of messages with this status.
+    fn color(self) -> &'static str {
+        match self {
+            Status::Ok => "\x1b[32m",
+            Status::Skipped => "\x1b[33m",
+            Status::Failed => "\x1b[31m",
+        }
+    }
+}
+
+const RESET: &str = "\x1b[0m";
+
+/// The result of one step of an operation.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Event {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config: Option<String>,
+    pub status: Status,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stderr: Option<String>,
+    /// What kind of operation failed, for the exit code
+    #[serde(skip)]
+    pub failure: Failure,
+}
+
+/// Events of a single task, buffered so parallel tasks don't interleave their lines.
+pub struct Report {
+    config: Option<String>,
+    events: Vec<Event>,
+}
+
+impl Report {
+    pub fn new(config_name: &str) -> Self {
+        Report {
+            config: Some(config_name.to_string()),
+            events: Vec::new(),
+        }
+    }
+
+    pub fn global() -> Self {
+        Report {
+            config: None,
+            events: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, status: Status, message: String, stderr: &[u8], failure: Failure) {
+        let stderr = String::from_utf8_lossy(stderr).trim_end().to_string();
+        self.events.push(Event {
+            config: self.config.clone(),
+            status,
+            message,
+            stderr: (!stderr.is_empty()).then_some(stderr),
+            failure,
+        });
+    }
+
+    pub fn ok(&mut self, message: String) {
+        self.push(Status::Ok, message, &[], Failure::default());
+    }
+
+    pub fn skipped(&mut self, message: String) {
+        self.push(Status::Skipped, message, &[], Failure::default());
+    }
+
+    pub fn failed(&mut self, message: String, stderr: &[u8]) {
+        self.failed_as(Failure::Operation, message, stderr);
+    }
+
+    /// Reports a failure of a specific kind, with its own exit code.
+    pub fn failed_as(&mut self, failure: Failure, message: String, stderr: &[u8]) {
+        self.push(Status::Failed, message, stderr, failure);
+    }
+}
+
+/// A state-changing command run through a [`Runner`], or request sent to the
+/// Docker Engine API, for the audit log.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RanCommand {
+    /// The command as typed in a shell, without its environment, which may hold
+    /// secrets, or the method and path of the request
+    pub command: String,
+    /// `None` when it couldn't be started or was ended by a signal
+    pub exit_code: Option<i32>,
+    /// HTTP status of a request, `None` when the API couldn't be reached
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_status: Option<u16>,
+}
+
+/// Outcome counts of a run, for the closing summary and the exit code.
+#[derive(Default)]
+struct Summary {
+    succeeded: usize,
+    /// Configurations, or messages of global events, that failed
+    failed: Vec<String>,
+    /// The kind of failure that picks the exit code
+    failure: Option<Failure>,
+}
+
+/// Renders reports either as they arrive (plain) or as one JSON document at the end.
+pub struct Output {
+    format: OutputFormat,
+    /// Whether plain output may be colored when going to a terminal
+    color: bool,
+    /// Whether plain output leaves out everything but failures
+    quiet: bool,
+    events: Mutex<Vec<Event>>,
+    data: Mutex<Option<serde_json::Value>>,
+    summary: Mutex<Summary>,
+    /// Spinners of running tasks, when plain output goes to a terminal
+    progress: Option<MultiProgress>,
+    /// Every event and state-changing command so far, for the audit log
+    logged_events: Mutex<Vec<Event>>,
+    ran: Mutex<Vec<RanCommand>>,
+}
+
+/// A spinner with the elapsed time, shown while a long task runs and removed
+/// when dropped.
+pub struct Progress(Option<ProgressBar>);
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if let Some(ref bar) = self.0 {
+            bar.finish_and_clear();
+        }
+    }
+}
+
+impl Output {
+    pub fn new(format: OutputFormat) -> Self {
+        Output {
+            format,
+            color: false,
+            quiet: false,
+            events: Mutex::new(Vec::new()),
+            data: Mutex::new(None),
+            summary: Mutex::new(Summary::default()),
+            progress: (format == OutputFormat::Plain && io::stderr().is_terminal())
+                .then(MultiProgress::new),
+            logged_events: Mutex::new(Vec::new()),
+            ran: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Colors messages by status when the stream they go to is a terminal.
+    pub fn with_color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Prints only failures in plain mode, and no spinners.
+    pub fn with_quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        if quiet {
+            self.progress = None;
+        }
+        self
+    }
+
+    pub fn is_json(&self) -> bool {
+        self.format == OutputFormat::Json
+    }
+
+    pub fn report(&self, report: Report) {
+        {
+            let mut summary = self.summary.lock().unwrap();
+            for event in &report.events {
+                match event.status {
+                    Status::Ok => summary.succeeded += 1,
+                    Status::Skipped => {}
+                    Status::Failed => {
+                        summary.failed.push(
+                            event
+                                .config
+                                .clone()
+                                .unwrap_or_else(|| event.message.clone()),
+                        );
+                        summary.failure = summary.failure.max(Some(event.failure));
+                    }
+                }
+            }
+        }
+        self.logged_events
+            .lock()
+            .unwrap()
+            .extend(report.events.iter().cloned());
+
+        if self.is_json() {
+            self.events.lock().unwrap().extend(report.events);
+            return;
+        }
+
+        // Keep spinners from drawing over the messages
+        match self.progress {
+            Some(ref progress) => progress.suspend(|| self.print(report)),
+            None => self.print(report),
+        }
+    }
+
+    fn print(&self, report: Report) {
+        let mut stdout = io::stdout().lock();
+        let mut stderr = io::stderr().lock();
+        let stdout_color = self.color && stdout.is_terminal();
+        let stderr_color = self.color && stderr.is_terminal();
+        for event in report.events {
+            if self.quiet && event.status != Status::Failed {
+                continue;
+            }
+            let (stream, color): (&mut dyn Write, bool) = if event.status == Status::Failed {
+                (&mut stderr, stderr_color)
+            } else {
+                (&mut stdout, stdout_color)
+            };
+            if color {
+                writeln!(stream, "{}{}{}", event.status.color(), event.message, RESET).unwrap();
+            } else {
+                writeln!(stream, "{}", event.message).unwrap();
+            }
+            if let Some(ref output) = event.stderr {
+                writeln!(stderr, "{}", output).unwrap();
+            }
+        }
+    }
+
+    /// Shows a spinner for a task until the returned guard is dropped.
+    pub fn progress(&self, message: String) -> Progress {
+        let Some(ref progress) = self.progress else {
+            return Progress(None);
+        };
+        let style = ProgressStyle::with_template("{spinner} {msg} ({elapsed})")
+            .expect("Spinner template is valid");
+        let bar = progress.add(
+            ProgressBar::new_spinner()
+                .with_style(style)
+                .with_message(message),
+        );
+        bar.enable_steady_tick(Duration::from_millis(100));
+        Progress(Some(bar))
+    }
+
+    pub fn notice(&self, message: String) {
+        let mut report = Report::global();
+        report.skipped(message);
+        self.report(report);
+    }
+
+    pub fn ok(&self, config_name: &str, message: String) {
+        let mut report = Report::new(config_name);
+        report.ok(message);
+        self.report(report);
+    }
+
+    pub fn skipped(&self, config_name: &str, message: String) {
+        let mut report = Report::new(config_name);
+        report.skipped(message);
+        self.report(report);
+    }
+
+    pub fn failed(&self, config_name: &str, message: String) {
+        let mut report = Report::new(config_name);
+        report.failed(message, &[]);
+        self.report(report);
+    }
+
+    /// Reports that no configuration is named `config_name`.
+    pub fn not_found(&self, config_name: &str) {
+        let mut report = Report::new(config_name);
+        report.failed_as(
+            Failure::NotFound,
+            format!("Configuration '{}' not found.", config_name),
+            &[],
+        );
+        self.report(report);
+    }
+
+    /// Configurations, or messages of global events, that failed so far.
+    pub fn failures(&self) -> Vec<String> {
+        let mut failed = self.summary.lock().unwrap().failed.clone();
+        failed.sort();
+        failed.dedup();
+        failed
+    }
+
+    /// Removes the events collected so far in JSON mode, for callers that
+    /// present them on their own.
+    pub fn take_events(&self) -> Vec<Event> {
+        std::mem::take(&mut *self.events.lock().unwrap())
+    }
+
+    /// Every event reported and state-changing command run so far, for the
+    /// audit log.
+    pub fn take_log(&self) -> (Vec<Event>, Vec<RanCommand>) {
+        (
+            std::mem::take(&mut *self.logged_events.lock().unwrap()),
+            std::mem::take(&mut *self.ran.lock().unwrap()),
+        )
+    }
+
+    fn record(&self, command: &Command, exit_code: Option<i32>) {
+        self.ran.lock().unwrap().push(RanCommand {
+            command: describe_program(command),
+            exit_code,
+            http_status: None,
+        });
+    }
+
+    /// Records a state-changing request to the Docker Engine API, like
+    /// `POST /networks/create`, with the status it was answered with.
+    pub fn record_request(&self, request: String, http_status: Option<u16>) {
+        self.ran.lock().unwrap().push(RanCommand {
+            command: request,
+            exit_code: None,
+            http_status,
+        });
+    }
+
+    /// Sets the document printed instead of the event list in JSON mode.
+    pub fn data<T: Serialize>(&self, data: &T) {
+        let data = serde_json::to_value(data).expect("Could not serialize to JSON");
+        *self.data.lock().unwrap() = Some(data);
+    }
+
+    /// Asks the user for a line of input, keeping stdout clean in JSON mode.
+    pub fn prompt(&self, question: &str) -> String {
+        if self.is_json() {
+            eprintln!("{}", question);
+        } else {
+            println!("{}", question);
+        }
+        let mut answer = String::new();
+        io::stdin()
+            .read_line(&mut answer)
+            .expect("Failed to read line");
+        answer.trim().to_string()
+    }
+
+    /// Prints the JSON document or, when only some operations failed, a summary
+    /// of the failures, and returns the exit code.
+    pub fn finish(self) -> ExitCode {
+        let is_json = self.is_json();
+        let mut summary = self.summary.into_inner().unwrap();
+        if is_json {
+            let document = match self.data.into_inner().unwrap() {
+                Some(data) => data,
+                None => serde_json::to_value(self.events.into_inner().unwrap())
+                    .expect("Could not serialize to JSON"),
+            };
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&document).expect("Could not serialize to JSON")
+            );
+        } else if !summary.failed.is_empty() && summary.succeeded > 0 {
+            summary.failed.sort();
+            summary.failed.dedup();
+            let message = format!(
+                "Finished with {} failure(s): {}",
+                summary.failed.len(),
+                summary.failed.join(", ")
+            );
+            if self.color && io::stderr().is_terminal() {
+                eprintln!("{}{}{}", Status::Failed.color(), message, RESET);
+            } else {
+                eprintln!("{}", message);
+            }
+        }
+
+        match summary.failure {
+            None => ExitCode::from(error::EXIT_OK),
+            Some(failure) => ExitCode::from(failure.exit_code()),
+        }
+    }
+}
+
+/// How [`Runner::retry`] retries operations that fail when the network does.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Retry {
+    /// Attempts after the first one
+    pub count: u32,
+    /// Wait before the first retry, doubled before each next one
+    pub backoff: Duration,
+}
+
+/// Executes external commands, or only announces them in dry-run mode.
+pub struct Runner<'a> {
+    pub output: &'a Output,
+    pub dry_run: bool,
+    pub retry: Retry,
+}
+
+impl Runner<'_> {
+    /// Runs a command that changes state; in dry-run mode it is only printed.
+    pub fn run(&self, command: &mut Command) -> io::Result<process::Output> {
+        self.run_timeout(command, None)
+    }
+
+    /// Like [`Runner::run`], but kills the command once it has run for
+    /// `timeout`, failing with [`io::ErrorKind::TimedOut`].
+    pub fn run_timeout(
+        &self,
+        command: &mut Command,
+        timeout: Option<Duration>,
+    ) -> io::Result<process::Output> {
+        if self.dry_run {
+            self.output
+                .notice(format!("Would run: {}", describe_command(command)));
+            return Ok(process::Output {
+                status: ExitStatus::default(),
+                stdout: Vec::new(),
+                stderr: Vec::new(),
+            });
+        }
+        let output = match timeout {
+            Some(timeout) => output_within(command, timeout),
+            None => output(command, false),
+        };
+        let exit_code = output.as_ref().ok().and_then(|output| output.status.code());
+        self.output.record(command, exit_code);
+        output
+    }
+
+    /// Runs a long-lived command, printing its output line by line with `prefix`.
+    pub fn stream(&self, command: &mut Command, prefix: &str) -> io::Result<ExitStatus> {
+        if self.dry_run {
+            self.output
+                .notice(format!("Would run: {}", describe_command(command)));
+            return Ok(ExitStatus::default());
+        }
+
+        info!(command = %describe_program(command), "Running");
+        let started = Instant::now();
+        let mut child = match command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(err) => {
+                self.output.record(command, None);
+                return Err(err);
+            }
+        };
+        let stdout = child.stdout.take().expect("Child stdout is piped");
+        let stderr = child.stderr.take().expect("Child stderr is piped");
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                    println!("{} | {}", prefix, line);
+                }
+            });
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                eprintln!("{} | {}", prefix, line);
+            }
+        });
+        let status = child.wait();
+        log_status(command, &status, started);
+        self.output
+            .record(command, status.as_ref().ok().and_then(ExitStatus::code));
+        status
+    }
+
+    /// Runs a command attached to the terminal.
+    pub fn interactive(&self, command: &mut Command) -> io::Result<ExitStatus> {
+        if self.dry_run {
+            self.output
+                .notice(format!("Would run: {}", describe_command(command)));
+            return Ok(ExitStatus::default());
+        }
+        info!(command = %describe_program(command), "Running");
+        let started = Instant::now();
+        let status = command.status();
+        log_status(command, &status, started);
+        self.output
+            .record(command, status.as_ref().ok().and_then(ExitStatus::code));
+        status
+    }
+
+    /// Calls `attempt` until it succeeds or the retries run out, returning
+    /// the last error then. `what` names the operation in the notices.
+    pub fn retry<T, E: fmt::Display>(
+        &self,
+        what: &str,
+        mut attempt: impl FnMut() -> Result<T, E>,
+    ) -> Result<T, E> {
+        for retry in 1..=self.retry.count {
+            match attempt() {
+                Ok(value) => return Ok(value),
+                Err(err) => self.wait_to_retry(what, retry, &err.to_string()),
+            }
+        }
+        attempt()
+    }
+
+    /// Runs a command that changes state like [`Runner::run`], running it
+    /// again as [`Runner::retry`] does while it exits with an error.
+    pub fn run_retrying(&self, what: &str, command: &mut Command) -> io::Result<process::Output> {
+        for retry in 1..=self.retry.count {
+            match self.run(command) {
+                Ok(output) if !output.status.success() => {
+                    self.wait_to_retry(what, retry, &String::from_utf8_lossy(&output.stderr))
+                }
+                result => return result,
+            }
+        }
+        self.run(command)
+    }
+
+    /// Says why `what` is retried and waits before retry number `retry`.
+    fn wait_to_retry(&self, what: &str, retry: u32, reason: &str) {
+        let backoff = self
+            .retry
+            .backoff
+            .saturating_mul(2u32.saturating_pow(retry - 1));
+        let reason = reason.trim().lines().last().unwrap_or_default();
+        self.output.notice(format!(
+            "{} failed, retrying in {}s ({} of {}): {}",
+            what,
+            backoff.as_secs(),
+            retry,
+            self.retry.count,
+            reason
+        ));
+        thread::sleep(backoff);
+    }
+
+    /// Runs a read-only command, which also happens in dry-run mode so the plan
+    /// reflects the current state.
+    pub fn query(&self, command: &mut Command) -> io::Result<process::Output> {
+        output(command, true)
+    }
+}
+
+/// Runs `command` to completion, logging its command line, how it ended and
+/// what it printed. Queries are frequent, so only `-vv` shows them.
+fn output(command: &mut Command, query: bool) -> io::Result<process::Output> {
+    let line = describe_program(command);
+    if query {
+        debug!(command = %line, "Running");
+    } else {
+        info!(command = %line, "Running");
+    }
+    let started = Instant::now();
+    let output = command.output();
+    match output {
+        Ok(ref output) => {
+            debug!(
+                command = %line,
+                exit_code = ?output.status.code(),
+                duration = ?started.elapsed(),
+                "Finished"
+            );
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if !stderr.trim().is_empty() {
+                debug!(command = %line, stderr = %stderr.trim_end(), "Error output");
+            }
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            if !stdout.trim().is_empty() {
+                trace!(command = %line, stdout = %stdout.trim_end(), "Output");
+            }
+        }
+        Err(ref err) => debug!(command = %line, error = %err, "Failed to run"),
+    }
+    output
+}
+
+/// Runs `command` like [`output`], killing it once it has run for `timeout`.
+/// Its output is read on threads of their own, which are left behind when it
+/// is killed since what it spawned may still hold the pipes open.
+fn output_within(command: &mut Command, timeout: Duration) -> io::Result<process::Output> {
+    let line = describe_program(command);
+    info!(command = %line, timeout = ?timeout, "Running");
+    let started = Instant::now();
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .inspect_err(|err| debug!(command = %line, error = %err, "Failed to run"))?;
+    let read = |mut pipe: Box<dyn io::Read + Send>| {
+        thread::spawn(move || {
+            let mut content = Vec::new();
+            let _ = pipe.read_to_end(&mut content);
+            content
+        })
+    };
+    let stdout = read(Box::new(
+        child.stdout.take().expect("Child stdout is piped"),
+    ));
+    let stderr = read(Box::new(
+        child.stderr.take().expect("Child stderr is piped"),
+    ));
+
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if started.elapsed() >= timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            debug!(command = %line, timeout = ?timeout, "Killed");
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("Killed after {}s without finishing", timeout.as_secs()),
+            ));
+        }
+        thread::sleep(Duration::from_millis(100));
+    };
+    let output = process::Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    };
+    debug!(
+        command = %line,
+        exit_code = ?output.status.code(),
+        duration = ?started.elapsed(),
+        "Finished"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !stderr.trim().is_empty() {
+        debug!(command = %line, stderr = %stderr.trim_end(), "Error output");
+    }
+    Ok(output)
+}
+
+/// Logs how a command whose output went to the terminal ended.
+fn log_status(command: &Command, status: &io::Result<ExitStatus>, started: Instant) {
+    let line = describe_program(command);
+    match status {
+        Ok(status) => debug!(
+            command = %line,
+            exit_code = ?status.code(),
+            duration = ?started.elapsed(),
+            "Finished"
+        ),
+        Err(err) => debug!(command = %line, error = %err, "Failed to run"),
+    }
+}
+
+/// Formats a command as it would be typed in a shell.
+fn describe_command(command: &Command) -> String {
+    let mut words = Vec::new();
+    for (key, value) in command.get_envs() {
+        if let Some(value) = value {
+            words.push(format!(
+                "{}={}",
+                key.to_string_lossy(),
+                value.to_string_lossy()
+            ));
+        }
+    }
+    words.push(describe_program(command));
+    words.join(" ")
+}
+
+/// Formats a command as it would be typed in a shell, without its environment.
+fn describe_program(command: &Command) -> String {
+    let mut words = vec![command.get_program().to_string_lossy().to_string()];
+    for arg in command.get_args() {
+        let arg = arg.to_string_lossy();
+        if arg.is_empty() || arg.contains(char::is_whitespace) {
+            words.push(format!("'{}'", arg));
+        } else {
+            words.push(arg.to_string());
+        }
+    }
+    match command.get_current_dir() {
+        Some(dir) => format!("{} (in '{}')", words.join(" "), dir.display()),
+        None => words.join(" "),
+    }
+}
@@ -0,0 +1,275 @@
+//! Host ports published by compose projects, read from `compose config`, and
+//! whether they collide with each other or with ports already in use.
+//!
+//! With `remap_ports` set, a colliding port is moved to a free one. The move is
+//! recorded in the configuration and applied on start through a compose
+//! override that replaces the ports of the affected services.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::net::{TcpListener, UdpSocket};
+
+/// A host port a service publishes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublishedPort {
+    pub service: String,
+    /// Address the port is bound to, every address when unset
+    pub host_ip: Option<String>,
+    pub port: u16,
+    /// Container port the host port forwards to
+    pub target: u16,
+    pub protocol: String,
+}
+
+impl PublishedPort {
+    /// Whether both can't be published at the same time.
+    pub fn overlaps(&self, other: &PublishedPort) -> bool {
+        self.port == other.port
+            && self.protocol == other.protocol
+            && match (self.host_ip(), other.host_ip()) {
+                (None, _) | (_, None) => true,
+                (Some(ip), Some(other_ip)) => ip == other_ip,
+            }
+    }
+
+    /// The host address, `None` for the wildcard ones.
+    fn host_ip(&self) -> Option<&str> {
+        self.host_ip
+            .as_deref()
+            .filter(|ip| !["", "0.0.0.0", "::"].contains(ip))
+    }
+
+    /// Whether something on the host already listens on the port.
+    pub fn is_bound(&self) -> bool {
+        let address = (self.host_ip().unwrap_or("0.0.0.0"), self.port);
+        let bound = match self.protocol.as_str() {
+            "udp" => UdpSocket::bind(address).map(|_| ()),
+            _ => TcpListener::bind(address).map(|_| ()),
+        };
+        // Other errors, like privileged ports, don't mean the port is taken
+        matches!(bound, Err(err) if err.kind() == io::ErrorKind::AddrInUse)
+    }
+}
+
+impl fmt::Display for PublishedPort {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.host_ip() {
+            Some(ip) => write!(f, "{}:{}/{}", ip, self.port, self.protocol),
+            None => write!(f, "{}/{}", self.port, self.protocol),
+        }
+    }
+}
+
+/// Reads the published ports from `compose config --format json`, expanding
+/// port ranges.
+pub fn parse_compose_config(stdout: &str) -> Result<Vec<PublishedPort>, String> {
+    let config: serde_json::Value = serde_json::from_str(stdout).map_err(|err| err.to_string())?;
+    let mut ports = Vec::new();
+    let services = config["services"].as_object().into_iter().flatten();
+    for (service, definition) in services {
+        for port in definition["ports"].as_array().into_iter().flatten() {
+            // Older compose releases write numbers, newer ones strings
+            let published = match port["published"] {
+                serde_json::Value::Number(ref number) => number.to_string(),
+                serde_json::Value::String(ref published) => published.clone(),
+                _ => continue,
+            };
+            let (first, last) = published
+                .split_once('-')
+                .unwrap_or((published.as_str(), published.as_str()));
+            let parse = |port: &str| {
+                port.trim()
+                    .parse::<u16>()
+                    .map_err(|_| format!("Invalid published port '{}' of '{}'", published, service))
+            };
+            for number in parse(first)?..=parse(last)? {
+                ports.push(PublishedPort {
+                    service: service.clone(),
+                    host_ip: port["host_ip"].as_str().map(String::from),
+                    port: number,
+                    target: port["target"]
+                        .as_u64()
+                        .and_then(|target| u16::try_from(target).ok())
+                        .unwrap_or(number),
+                    protocol: port["protocol"].as_str().unwrap_or("tcp").to_string(),
+                });
+            }
+        }
+    }
+    Ok(ports)
+}
+
+/// A published port moved to another host port, stored in the
+/// `remapped_ports` of a configuration.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PortRemap {
+    pub service: String,
+    /// The port the compose file publishes
+    pub port: u16,
+    #[serde(default = "default_protocol")]
+    pub protocol: String,
+    /// The port it is published on instead
+    pub host_port: u16,
+}
+
+fn default_protocol() -> String {
+    "tcp".to_string()
+}
+
+impl PortRemap {
+    pub fn applies_to(&self, port: &PublishedPort) -> bool {
+        self.service == port.service && self.port == port.port && self.protocol == port.protocol
+    }
+}
+
+/// `port` as published once `remaps` are applied.
+pub fn remapped(port: &PublishedPort, remaps: &[PortRemap]) -> PublishedPort {
+    let mut port = port.clone();
+    if let Some(remap) = remaps.iter().find(|remap| remap.applies_to(&port)) {
+        port.port = remap.host_port;
+    }
+    port
+}
+
+/// The first port above `port` that `taken` accepts, trying the ports after
+/// the original one so remapped ports stay recognizable.
+pub fn free_port(port: &PublishedPort, taken: impl Fn(&PublishedPort) -> bool) -> Option<u16> {
+    (port.port.checked_add(1)?..=u16::MAX).find(|&number| {
+        let candidate = PublishedPort {
+            port: number,
+            ..port.clone()
+        };
+        !taken(&candidate)
+    })
+}
+
+/// A compose override replacing the ports of every service with a remapped
+/// port. `!override` needs compose 2.24.4 or later.
+pub fn override_file(ports: &[PublishedPort], remaps: &[PortRemap]) -> String {
+    let mut content = String::from("services:\n");
+    let mut services: Vec<&str> = remaps.iter().map(|remap| remap.service.as_str()).collect();
+    services.sort();
+    services.dedup();
+    for service in services {
+        content.push_str(&format!(
+            "  {}:\n    ports: !override\n",
+            yaml_string(service)
+        ));
+        for port in ports.iter().filter(|port| port.service == service) {
+            let published = remapped(port, remaps);
+            content.push_str(&format!("      - target: {}\n", port.target));
+            content.push_str(&format!("        published: \"{}\"\n", published.port));
+            content.push_str(&format!("        protocol: {}\n", port.protocol));
+            if let Some(ref host_ip) = port.host_ip {
+                content.push_str(&format!("        host_ip: {}\n", yaml_string(host_ip)));
+            }
+        }
+    }
+    content
+}
+
+/// Quotes a string for YAML.
+pub(crate) fn yaml_string(value: &str) -> String {
+    serde_json::to_string(value).expect("Strings serialize to JSON")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn port(service: &str, host_ip: Option<&str>, port: u16) -> PublishedPort {
+        PublishedPort {
+            service: service.to_string(),
+            host_ip: host_ip.map(String::from),
+            port,
+            target: 80,
+            protocol: "tcp".to_string(),
+        }
+    }
+
+    #[test]
+    fn wildcard_addresses_overlap_every_address() {
+        assert!(port("a", None, 8080).overlaps(&port("b", Some("127.0.0.1"), 8080)));
+        assert!(port("a", Some("0.0.0.0"), 8080).overlaps(&port("b", Some("::1"), 8080)));
+        assert!(!port("a", Some("127.0.0.1"), 8080).overlaps(&port("b", Some("127.0.0.2"), 8080)));
+        assert!(!port("a", None, 8080).overlaps(&port("b", None, 8081)));
+        let udp = PublishedPort {
+            protocol: "udp".to_string(),
+            ..port("b", None, 8080)
+        };
+        assert!(!port("a", None, 8080).overlaps(&udp));
+    }
+
+    #[test]
+    fn published_ports_are_read_from_compose_config() {
+        let config = r#"{"services": {
+            "web": {"ports": [
+                {"target": 80, "published": "8080", "protocol": "tcp"},
+                {"target": 443, "published": 8443, "host_ip": "127.0.0.1"}
+            ]},
+            "dns": {"ports": [{"target": 53, "published": "5353-5354", "protocol": "udp"}]},
+            "worker": {"ports": [{"target": 9000}]}
+        }}"#;
+        let ports = parse_compose_config(config).unwrap();
+        let described: Vec<String> = ports
+            .iter()
+            .map(|port| format!("{} {} -> {}", port.service, port, port.target))
+            .collect();
+        assert_eq!(
+            described,
+            [
+                "dns 5353/udp -> 53",
+                "dns 5354/udp -> 53",
+                "web 8080/tcp -> 80",
+                "web 127.0.0.1:8443/tcp -> 443",
+            ]
+        );
+        assert!(parse_compose_config(
+            r#"{"services": {"web": {"ports": [{"published": "http"}]}}}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn remaps_move_matching_ports_only() {
+        let remaps = [PortRemap {
+            service: "web".to_string(),
+            port: 8080,
+            protocol: "tcp".to_string(),
+            host_port: 8081,
+        }];
+        assert_eq!(remapped(&port("web", None, 8080), &remaps).port, 8081);
+        assert_eq!(remapped(&port("api", None, 8080), &remaps).port, 8080);
+        assert_eq!(remapped(&port("web", None, 9090), &remaps).port, 9090);
+    }
+
+    #[test]
+    fn free_ports_are_searched_upwards() {
+        let taken = [8081, 8082];
+        let free = free_port(&port("web", None, 8080), |candidate| {
+            taken.contains(&candidate.port)
+        });
+        assert_eq!(free, Some(8083));
+        assert_eq!(free_port(&port("web", None, u16::MAX), |_| false), None);
+    }
+
+    #[test]
+    fn overrides_replace_the_ports_of_remapped_services() {
+        let ports = [
+            port("web", None, 8080),
+            port("web", Some("127.0.0.1"), 9090),
+            port("api", None, 3000),
+        ];
+        let remaps = [PortRemap {
+            service: "web".to_string(),
+            port: 8080,
+            protocol: "tcp".to_string(),
+            host_port: 8081,
+        }];
+        assert_eq!(
+            override_file(&ports, &remaps),
+            "services:\n  \"web\":\n    ports: !override\n      - target: 80\n        published: \"8081\"\n        protocol: tcp\n      - target: 80\n        published: \"9090\"\n        protocol: tcp\n        host_ip: \"127.0.0.1\"\n"
+        );
+    }
+}
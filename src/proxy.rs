@@ -0,0 +1,151 @@
+//! Routing `http://<config>.localhost` to started configurations through a
+//! reverse proxy on the shared network, enabled with the `proxy` setting.
+//!
+//! The proxy runs as its own compose project publishing port 80, and each
+//! project gets one of its services labeled for it through a compose override
+//! when it starts. Browsers resolve `*.localhost` to the loopback address, so
+//! no `/etc/hosts` entries are needed.
+
+use crate::hosts::host_label;
+use crate::ports::{self, PublishedPort};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// Reverse proxy picking up the labeled containers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Proxy {
+    /// Traefik, configured through container labels
+    Traefik,
+    /// nginx-proxy, configured through `VIRTUAL_HOST` environment variables
+    NginxProxy,
+}
+
+impl Proxy {
+    pub fn name(self) -> &'static str {
+        match self {
+            Proxy::Traefik => "traefik",
+            Proxy::NginxProxy => "nginx-proxy",
+        }
+    }
+
+    /// The compose project running the proxy on `network`.
+    pub fn compose_file(self, network: &str) -> String {
+        let mut content = String::from("services:\n  proxy:\n");
+        match self {
+            Proxy::Traefik => {
+                content.push_str("    image: traefik:v3\n    command:\n");
+                for arg in [
+                    "--providers.docker=true".to_string(),
+                    "--providers.docker.exposedbydefault=false".to_string(),
+                    format!("--providers.docker.network={}", network),
+                    "--entrypoints.web.address=:80".to_string(),
+                ] {
+                    content.push_str(&format!("      - {}\n", ports::yaml_string(&arg)));
+                }
+                content.push_str("    volumes:\n");
+                content.push_str("      - /var/run/docker.sock:/var/run/docker.sock:ro\n");
+            }
+            Proxy::NginxProxy => {
+                content.push_str("    image: nginxproxy/nginx-proxy\n    volumes:\n");
+                content.push_str("      - /var/run/docker.sock:/tmp/docker.sock:ro\n");
+            }
+        }
+        content.push_str("    ports:\n      - \"80:80\"\n");
+        content.push_str("    networks:\n      - shared\n");
+        content.push_str("    restart: unless-stopped\n");
+        content.push_str(&format!(
+            "networks:\n  shared:\n    name: {}\n    external: true\n",
+            ports::yaml_string(network)
+        ));
+        content
+    }
+
+    /// A compose override routing `hostname` to the service of `route`.
+    pub fn override_file(
+        self,
+        network: &str,
+        route: &Route,
+        config_name: &str,
+        hostname: &str,
+    ) -> String {
+        let mut content = format!("services:\n  {}:\n", ports::yaml_string(&route.service));
+        match self {
+            Proxy::Traefik => {
+                let router = format!("comphost-{}", host_label(config_name));
+                let mut labels = vec![
+                    ("traefik.enable".to_string(), "true".to_string()),
+                    ("traefik.docker.network".to_string(), network.to_string()),
+                    (
+                        format!("traefik.http.routers.{}.rule", router),
+                        format!("Host(`{}`)", hostname),
+                    ),
+                    (
+                        format!("traefik.http.routers.{}.entrypoints", router),
+                        "web".to_string(),
+                    ),
+                ];
+                if let Some(port) = route.port {
+                    labels.push((
+                        format!("traefik.http.services.{}.loadbalancer.server.port", router),
+                        port.to_string(),
+                    ));
+                }
+                content.push_str("    labels:\n");
+                for (key, value) in labels {
+                    content.push_str(&format!(
+                        "      {}: {}\n",
+                        ports::yaml_string(&key),
+                        ports::yaml_string(&value)
+                    ));
+                }
+            }
+            Proxy::NginxProxy => {
+                content.push_str("    environment:\n");
+                content.push_str(&format!(
+                    "      VIRTUAL_HOST: {}\n",
+                    ports::yaml_string(hostname)
+                ));
+                if let Some(port) = route.port {
+                    content.push_str(&format!("      VIRTUAL_PORT: \"{}\"\n", port));
+                }
+            }
+        }
+        content
+    }
+}
+
+/// The compose project name of the proxy for `network`.
+pub fn project_name(network: &str) -> String {
+    format!("{}-proxy", network)
+}
+
+/// The service of a project the proxy sends its requests to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub service: String,
+    /// Container port to forward to
+    pub port: Option<u16>,
+}
+
+/// Picks the service to route to: `service` when given, otherwise the first
+/// one publishing a TCP port. The container port is that of its first
+/// published TCP port.
+pub fn route(published: &[PublishedPort], service: Option<&str>) -> Option<Route> {
+    let tcp = |port: &&PublishedPort| port.protocol == "tcp";
+    let service = match service {
+        Some(service) => service.to_string(),
+        None => published.iter().find(tcp)?.service.clone(),
+    };
+    let port = published
+        .iter()
+        .filter(tcp)
+        .find(|port| port.service == service)
+        .map(|port| port.target);
+    Some(Route { service, port })
+}
+
+/// `<config>.localhost`, with the characters a hostname can't hold replaced.
+pub fn hostname(config_name: &str) -> String {
+    format!("{}.localhost", host_label(config_name))
+}
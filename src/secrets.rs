@@ -0,0 +1,132 @@
+//! Secrets of configurations, kept out of the config in an age-encrypted file
+//! next to it and passed to compose as environment variables on `start`.
+//!
+//! The file is encrypted to a key generated on first use in `identity.txt` of
+//! the config directory, readable only by the user. Whoever can read that key
+//! can read the secrets, so keep it out of backups and shared repositories.
+
+use crate::error::Error;
+use crate::workspace;
+use age::secrecy::ExposeSecret;
+use age::x25519::Identity;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// The secrets of every configuration of one config file.
+pub struct Secrets {
+    path: PathBuf,
+    /// Secret environment variables of each configuration
+    pub configs: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl Secrets {
+    /// Decrypts the secrets belonging to the config at `config_path`, which
+    /// are empty until the first one is set.
+    pub fn open(config_path: &Path) -> Result<Self, Error> {
+        let path = config_path.with_extension("secrets.age");
+        let ciphertext = match fs::read(&path) {
+            Ok(ciphertext) => ciphertext,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Ok(Secrets {
+                    path,
+                    configs: BTreeMap::new(),
+                })
+            }
+            Err(source) => {
+                return Err(Error::Read {
+                    path: path.display().to_string(),
+                    source,
+                })
+            }
+        };
+
+        let decrypt_error = |message: String| Error::Decrypt {
+            path: path.display().to_string(),
+            message,
+        };
+        let identity_path = identity_path()?;
+        let identity = read_identity(&identity_path)?
+            .ok_or_else(|| decrypt_error(format!("'{}' is missing", identity_path.display())))?;
+        let plaintext =
+            age::decrypt(&identity, &ciphertext).map_err(|err| decrypt_error(err.to_string()))?;
+        let configs =
+            serde_json::from_slice(&plaintext).map_err(|err| decrypt_error(err.to_string()))?;
+        Ok(Secrets { path, configs })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Encrypts the secrets back to their file, generating the key on first use.
+    pub fn save(&self) -> Result<(), Error> {
+        let identity_path = identity_path()?;
+        let identity = match read_identity(&identity_path)? {
+            Some(identity) => identity,
+            None => {
+                let identity = Identity::generate();
+                let content = format!("{}\n", identity.to_string().expose_secret());
+                write_private(&identity_path, content.as_bytes()).map_err(|source| {
+                    Error::Write {
+                        path: identity_path.display().to_string(),
+                        source,
+                    }
+                })?;
+                identity
+            }
+        };
+
+        let plaintext = serde_json::to_vec(&self.configs).expect("Secrets serialize to JSON");
+        let ciphertext =
+            age::encrypt(&identity.to_public(), &plaintext).map_err(|err| Error::Encrypt {
+                path: self.path.display().to_string(),
+                message: err.to_string(),
+            })?;
+        write_private(&self.path, &ciphertext).map_err(|source| Error::Write {
+            path: self.path.display().to_string(),
+            source,
+        })
+    }
+}
+
+fn identity_path() -> Result<PathBuf, Error> {
+    Ok(workspace::config_dir()?.join("identity.txt"))
+}
+
+/// Reads the key the secrets are encrypted to, skipping comment lines.
+fn read_identity(path: &Path) -> Result<Option<Identity>, Error> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(source) => {
+            return Err(Error::Read {
+                path: path.display().to_string(),
+                source,
+            })
+        }
+    };
+    content
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+        .and_then(|line| line.parse().ok())
+        .map(Some)
+        .ok_or_else(|| Error::Decrypt {
+            path: path.display().to_string(),
+            message: "no age identity found".to_string(),
+        })
+}
+
+/// Writes a file only the user can read.
+fn write_private(path: &Path, content: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(content)
+}
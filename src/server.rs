@@ -0,0 +1,393 @@
+//! `comphost serve`: a small HTTP API on localhost to list, toggle, start and
+//! stop configurations and to stream their logs, for editors, launchers and
+//! dashboards that would otherwise shell out to the CLI.
+//!
+//! - `GET /configs` lists the configurations like `list --output json`
+//! - `POST /configs/<name>/toggle`, `/start` and `/stop` answer with the
+//!   events the CLI reports, with status 500 when one of them failed
+//! - `GET /configs/<name>/logs?tail=<n>&follow=true` streams compose logs as text
+//!
+//! Errors come as `{"message": ...}`. Only requests addressed to localhost are
+//! answered and requests made by web pages are refused, so a site open in the
+//! browser can't drive the environment. Like the dashboard, the config is only
+//! locked while it is read or changed.
+
+use crate::compose::{ComposeOps, StartOptions, StopOptions};
+use crate::config::ConfigStore;
+use crate::docker::{NetworkOptions, Runtime};
+use crate::error::Error;
+use crate::output::{Output, OutputFormat, Retry, Runner, Status};
+use crate::secrets::Secrets;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::Mutex;
+use std::thread;
+
+/// Hosts a request may be addressed to, anything else may be DNS rebinding.
+const LOCAL_HOSTS: [&str; 2] = ["localhost", "127.0.0.1"];
+
+/// The parts of a request the API looks at.
+struct Request {
+    method: String,
+    path: String,
+    query: BTreeMap<String, String>,
+    /// Headers with lowercase names
+    headers: BTreeMap<String, String>,
+}
+
+struct Server<'a> {
+    path: &'a Path,
+    dry_run: bool,
+    output: &'a Output,
+    compose: ComposeOps<'a>,
+    /// Taken by requests that report events, so they don't get each other's
+    actions: Mutex<()>,
+}
+
+/// Serves the API for the config at `path` on `port` until the process is stopped.
+pub fn run(
+    path: &Path,
+    runtime: Runtime,
+    network: String,
+    network_options: NetworkOptions,
+    retry: Retry,
+    dry_run: bool,
+    port: u16,
+) -> Result<(), Error> {
+    let address = format!("127.0.0.1:{}", port);
+    let listener = TcpListener::bind(&address).map_err(|source| Error::Listen {
+        path: address.clone(),
+        source,
+    })?;
+    eprintln!("Listening on http://{}, press Ctrl-C to stop", address);
+
+    // Collect events to answer with instead of printing them
+    let output = Output::new(OutputFormat::Json);
+    let runner = Runner {
+        output: &output,
+        dry_run,
+        retry,
+    };
+    let server = Server {
+        path,
+        dry_run,
+        output: &output,
+        compose: ComposeOps::new(&runner, runtime, network, network_options),
+        actions: Mutex::new(()),
+    };
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            let server = &server;
+            scope.spawn(move || {
+                // The client went away, nothing left to tell it
+                let _ = server.handle(stream);
+            });
+        }
+    });
+    Ok(())
+}
+
+impl Server<'_> {
+    fn handle(&self, mut stream: TcpStream) -> io::Result<()> {
+        let request = match read_request(&stream) {
+            Ok(request) => request,
+            Err(err) => return respond_error(&mut stream, 400, &err.to_string()),
+        };
+        // Browsers say which page a request comes from, scripts and editors don't
+        if request.headers.contains_key("origin") {
+            return respond_error(&mut stream, 403, "Requests from web pages are refused");
+        }
+        let host = request.headers.get("host").map(|host| {
+            host.rsplit_once(':')
+                .map_or(host.as_str(), |(host, _)| host)
+        });
+        if !host.is_some_and(|host| LOCAL_HOSTS.contains(&host)) {
+            return respond_error(&mut stream, 403, "Requests must be addressed to localhost");
+        }
+
+        let segments: Vec<String> = request
+            .path
+            .trim_matches('/')
+            .split('/')
+            .map(decode)
+            .collect();
+        let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+        match (request.method.as_str(), segments.as_slice()) {
+            ("GET", ["configs"]) => self.list(&mut stream),
+            ("POST", ["configs", name, "toggle"]) => self.toggle(&mut stream, name),
+            ("POST", ["configs", name, "start"]) => self.start_or_stop(&mut stream, name, true),
+            ("POST", ["configs", name, "stop"]) => self.start_or_stop(&mut stream, name, false),
+            ("GET", ["configs", name, "logs"]) => self.logs(&mut stream, name, &request.query),
+            (_, ["configs"] | ["configs", _, "toggle" | "start" | "stop" | "logs"]) => {
+                respond_error(&mut stream, 405, "Method not allowed")
+            }
+            _ => respond_error(&mut stream, 404, "Not found"),
+        }
+    }
+
+    /// Loads the config without keeping it locked.
+    fn load(&self) -> Result<ConfigStore, Error> {
+        let mut store = ConfigStore::open(self.path, || {})?;
+        store.unlock();
+        Ok(store)
+    }
+
+    fn list(&self, stream: &mut TcpStream) -> io::Result<()> {
+        match self.load() {
+            Ok(store) => respond(stream, 200, &self.compose.status(&store.configs)),
+            Err(err) => respond_error(stream, 500, &err.to_string()),
+        }
+    }
+
+    /// Flips the active flag of a configuration.
+    fn toggle(&self, stream: &mut TcpStream, name: &str) -> io::Result<()> {
+        let _action = self.actions.lock().unwrap();
+        let mut store = match ConfigStore::open(self.path, || {}) {
+            Ok(store) => store,
+            Err(err) => return respond_error(stream, 500, &err.to_string()),
+        };
+        let Some(config) = store.configs.get_mut(name) else {
+            return respond_not_found(stream, name);
+        };
+        config.active = !config.active;
+        let message = format!(
+            "Configuration '{}' turned {}.",
+            name,
+            if config.active { "on" } else { "off" }
+        );
+        if self.dry_run {
+            self.output
+                .notice(format!("Would write '{}'", self.path.display()));
+        } else if let Err(err) = store.save() {
+            return respond_error(stream, 500, &err.to_string());
+        } else {
+            self.output.ok(name, message);
+        }
+        self.respond_events(stream)
+    }
+
+    /// Starts or stops the project of a configuration.
+    fn start_or_stop(&self, stream: &mut TcpStream, name: &str, start: bool) -> io::Result<()> {
+        let _action = self.actions.lock().unwrap();
+        let store = match self.load() {
+            Ok(store) => store,
+            Err(err) => return respond_error(stream, 500, &err.to_string()),
+        };
+        let Some(config) = store.get(name) else {
+            return respond_not_found(stream, name);
+        };
+        if config.clone_path.is_none() {
+            return respond_error(
+                stream,
+                409,
+                &format!("Configuration '{}' is not cloned.", name),
+            );
+        }
+
+        if start {
+            let secrets = match Secrets::open(self.path) {
+                Ok(secrets) => secrets.configs,
+                Err(err) => return respond_error(stream, 500, &err.to_string()),
+            };
+            let options = StartOptions {
+                proxy: store.settings.proxy,
+                override_template: store.settings.override_template.clone(),
+                wait_for_timeout: store.settings.wait_for_timeout,
+                up_timeout: store.settings.up_timeout,
+                secrets,
+                ..StartOptions::default()
+            };
+            if self.compose.ensure_network() {
+                if let Some(proxy) = options.proxy {
+                    self.compose.ensure_proxy(proxy);
+                }
+                self.compose.start(&options, name, config);
+            }
+        } else {
+            let options = StopOptions {
+                down_timeout: store.settings.down_timeout,
+                ..StopOptions::default()
+            };
+            self.compose.stop(&options, name, config);
+        }
+        self.respond_events(stream)
+    }
+
+    /// Streams the compose logs of a configuration until they end or the
+    /// client goes away.
+    fn logs(
+        &self,
+        stream: &mut TcpStream,
+        name: &str,
+        query: &BTreeMap<String, String>,
+    ) -> io::Result<()> {
+        let store = match self.load() {
+            Ok(store) => store,
+            Err(err) => return respond_error(stream, 500, &err.to_string()),
+        };
+        let Some(config) = store.get(name) else {
+            return respond_not_found(stream, name);
+        };
+        let Some(project) = config.project(name) else {
+            return respond_error(
+                stream,
+                409,
+                &format!("Configuration '{}' is not cloned.", name),
+            );
+        };
+
+        let mut logs_command = project.command(self.compose.runtime());
+        logs_command.args(["logs", "--no-color"]);
+        if query.get("follow").is_some_and(|follow| follow == "true") {
+            logs_command.arg("--follow");
+        }
+        if let Some(tail) = query.get("tail") {
+            let Ok(tail) = tail.parse::<usize>() else {
+                return respond_error(stream, 400, &format!("Invalid tail '{}'", tail));
+            };
+            logs_command.arg("--tail").arg(tail.to_string());
+        }
+        // Errors of compose show up where the server runs
+        let mut child = match logs_command.stdout(Stdio::piped()).spawn() {
+            Ok(child) => child,
+            Err(err) => {
+                return respond_error(
+                    stream,
+                    500,
+                    &format!(
+                        "Failed to execute {} compose logs command: {}",
+                        self.compose.runtime().binary(),
+                        err
+                    ),
+                )
+            }
+        };
+
+        // Without a length the body ends when the connection closes
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nConnection: close\r\n\r\n"
+        )?;
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        let copied = io::copy(&mut stdout, stream);
+        let _ = child.kill();
+        let _ = child.wait();
+        copied.map(|_| ())
+    }
+
+    /// Answers with the events reported since the last answer.
+    fn respond_events(&self, stream: &mut TcpStream) -> io::Result<()> {
+        let events = self.output.take_events();
+        let failed = events.iter().any(|event| event.status == Status::Failed);
+        respond(stream, if failed { 500 } else { 200 }, &events)
+    }
+}
+
+/// Reads the request line and headers, leaving any body unread.
+fn read_request(stream: &TcpStream) -> io::Result<Request> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(invalid("Malformed request line"));
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let query = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode(key), decode(value))
+        })
+        .collect();
+    let mut request = Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query,
+        headers: BTreeMap::new(),
+    };
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid("Unexpected end of request"));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            return Ok(request);
+        }
+        let (name, value) = header
+            .split_once(':')
+            .ok_or_else(|| invalid("Malformed header"))?;
+        request
+            .headers
+            .insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+    }
+}
+
+/// Decodes a percent-encoded path segment or query value.
+fn decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = bytes
+            .get(index + 1..index + 3)
+            .filter(|_| bytes[index] == b'%')
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(if bytes[index] == b'+' {
+                    b' '
+                } else {
+                    bytes[index]
+                });
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn respond(stream: &mut TcpStream, status: u16, body: &impl Serialize) -> io::Result<()> {
+    let body = serde_json::to_string_pretty(body).expect("Could not serialize to JSON");
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        _ => "Internal Server Error",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        body.len(),
+        body
+    )
+}
+
+fn respond_error(stream: &mut TcpStream, status: u16, message: &str) -> io::Result<()> {
+    respond(stream, status, &json!({ "message": message }))
+}
+
+fn respond_not_found(stream: &mut TcpStream, name: &str) -> io::Result<()> {
+    respond_error(stream, 404, &format!("Configuration '{}' not found.", name))
+}
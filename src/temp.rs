@@ -0,0 +1,108 @@
+//! Private temporary directories, for files other users of a shared temporary
+//! directory mustn't read or plant: compose overrides, edited configs and
+//! fresh clones.
+//!
+//! A directory is created anew under a name that isn't in use yet, only its
+//! owner can enter it and it is deleted with everything in it when dropped.
+
+use std::env;
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Directories tried before giving up, when others take the names first.
+const ATTEMPTS: usize = 100;
+
+/// Numbers the directories of this process.
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// A new empty directory named after `purpose` in the temporary directory.
+    pub fn create(purpose: &str) -> io::Result<Self> {
+        let mut builder = DirBuilder::new();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(0o700);
+        }
+        let mut last_error = None;
+        for _ in 0..ATTEMPTS {
+            let path = env::temp_dir().join(format!(
+                "comphost-{}-{}-{}",
+                purpose,
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            ));
+            // Unlike `create_dir_all`, fails on anything already there,
+            // symlinks included
+            match builder.create(&path) {
+                Ok(()) => return Ok(TempDir { path }),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => last_error = Some(err),
+                Err(err) => return Err(err),
+            }
+        }
+        Err(last_error.expect("At least one directory was tried"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes a new file `name` in the directory that only its owner can read,
+    /// returning its path.
+    pub fn write(&self, name: &str, content: &str) -> io::Result<PathBuf> {
+        let path = self.path.join(name);
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        options.open(&path)?.write_all(content.as_bytes())?;
+        Ok(path)
+    }
+
+    /// Deletes the directory now rather than when dropped.
+    pub fn remove(&self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directories_are_private_and_removed() {
+        let dir = TempDir::create("test").unwrap();
+        let other = TempDir::create("test").unwrap();
+        assert_ne!(dir.path(), other.path());
+
+        let path = dir.write("file.yaml", "services: {}\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "services: {}\n");
+        assert!(dir.write("file.yaml", "again").is_err());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+            assert_eq!(mode(dir.path()), 0o700);
+            assert_eq!(mode(&path), 0o600);
+        }
+
+        let kept = dir.path().to_path_buf();
+        drop(dir);
+        assert!(!kept.exists());
+    }
+}
@@ -0,0 +1,76 @@
+//! Compose overrides rendered from minijinja templates, set with
+//! `override_template` on a configuration or as a default in `[settings]`.
+//!
+//! A template sees the configuration through `name`, `clone_path`, `network`,
+//! `hostname` (`<name>.localhost`), `env` and `ports`, each port with
+//! `service`, `host_ip`, `port` (after remapping), `target` and `protocol`.
+//! Unknown variables are errors, so typos don't silently render as nothing.
+
+use crate::error::Error;
+use crate::ports::PublishedPort;
+use crate::workspace;
+use minijinja::{AutoEscape, Environment, UndefinedBehavior};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// The variables a template is rendered with.
+#[derive(Debug, Serialize)]
+pub struct Context<'a> {
+    pub name: &'a str,
+    pub clone_path: &'a str,
+    pub network: &'a str,
+    pub hostname: String,
+    pub env: &'a BTreeMap<String, String>,
+    pub ports: Vec<Port>,
+}
+
+/// A published port as templates see it.
+#[derive(Debug, Serialize)]
+pub struct Port {
+    pub service: String,
+    pub host_ip: Option<String>,
+    pub port: u16,
+    pub target: u16,
+    pub protocol: String,
+}
+
+impl From<PublishedPort> for Port {
+    fn from(port: PublishedPort) -> Self {
+        Port {
+            service: port.service,
+            host_ip: port.host_ip,
+            port: port.port,
+            target: port.target,
+            protocol: port.protocol,
+        }
+    }
+}
+
+/// Where a template lives: `~/` is the home directory and relative paths are
+/// in the config directory, so templates can sit next to the config.
+pub fn path(template: &str) -> Result<PathBuf, Error> {
+    if let (Some(rest), Some(home)) = (template.strip_prefix("~/"), dirs::home_dir()) {
+        return Ok(home.join(rest));
+    }
+    let path = PathBuf::from(template);
+    if path.is_absolute() {
+        return Ok(path);
+    }
+    Ok(workspace::config_dir()?.join(path))
+}
+
+/// Renders the template `source`, named `name` in error messages.
+pub fn render(name: &str, source: &str, context: &Context) -> Result<String, String> {
+    let mut environment = Environment::new();
+    environment.set_undefined_behavior(UndefinedBehavior::Strict);
+    // Values are inserted as they are, `tojson` quotes them for YAML
+    environment.set_auto_escape_callback(|_| AutoEscape::None);
+    // Keep the indentation YAML depends on
+    environment.set_trim_blocks(true);
+    environment.set_lstrip_blocks(true);
+    environment
+        .add_template(name, source)
+        .and_then(|()| environment.get_template(name)?.render(context))
+        .map_err(|err| format!("{:#}", err))
+}
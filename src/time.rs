@@ -0,0 +1,95 @@
+//! Calendar time in UTC for the names of config backups and the audit log,
+//! without pulling in a date library.
+
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A point in time broken down into its UTC calendar date and time of day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Utc {
+    pub year: u64,
+    pub month: u64,
+    pub day: u64,
+    pub hour: u64,
+    pub minute: u64,
+    pub second: u64,
+    pub millisecond: u32,
+}
+
+impl Utc {
+    pub fn now() -> Self {
+        SystemTime::now().into()
+    }
+}
+
+impl From<SystemTime> for Utc {
+    fn from(time: SystemTime) -> Self {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let seconds = since_epoch.as_secs();
+        let (days, seconds) = (seconds / 86_400, seconds % 86_400);
+
+        // Civil date from days since 1970-01-01, after Howard Hinnant's algorithm
+        let days = days + 719_468;
+        let era = days / 146_097;
+        let day_of_era = days % 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let month = if month_index < 10 {
+            month_index + 3
+        } else {
+            month_index - 9
+        };
+
+        Utc {
+            year: year_of_era + era * 400 + u64::from(month <= 2),
+            month,
+            day: day_of_year - (153 * month_index + 2) / 5 + 1,
+            hour: seconds / 3_600,
+            minute: seconds % 3_600 / 60,
+            second: seconds % 60,
+            millisecond: since_epoch.subsec_millis(),
+        }
+    }
+}
+
+/// RFC 3339, such as `2024-06-01T12:30:00Z`.
+impl fmt::Display for Utc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn at(seconds: u64, millis: u64) -> Utc {
+        (UNIX_EPOCH + Duration::from_millis(seconds * 1_000 + millis)).into()
+    }
+
+    #[test]
+    fn epoch() {
+        assert_eq!(at(0, 0).to_string(), "1970-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn civil_dates() {
+        assert_eq!(at(946_684_799, 0).to_string(), "1999-12-31T23:59:59Z");
+        assert_eq!(at(951_782_400, 0).to_string(), "2000-02-29T00:00:00Z");
+        assert_eq!(at(1_717_245_000, 0).to_string(), "2024-06-01T12:30:00Z");
+        // 2100 isn't a leap year
+        assert_eq!(at(4_107_542_400, 0).to_string(), "2100-03-01T00:00:00Z");
+    }
+
+    #[test]
+    fn milliseconds() {
+        assert_eq!(at(1_717_245_000, 250).millisecond, 250);
+    }
+}
@@ -0,0 +1,335 @@
+//! The `ui` dashboard: every configuration with its state, the containers and
+//! recent logs of the selected one, and keys to start, stop and toggle them.
+//!
+//! The config is only locked while it is read or changed, so other comphost
+//! invocations keep working while the dashboard is open.
+
+use crate::compose::{ComposeOps, ContainerEntry, StartOptions, StopOptions};
+use crate::config::ConfigStore;
+use crate::docker::{NetworkOptions, Runtime};
+use crate::error::Error;
+use crate::output::{Output, OutputFormat, Retry, Runner, Status};
+use crate::secrets::Secrets;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use std::path::Path;
+use std::time::Duration;
+
+/// Lines of logs shown for the selected configuration.
+const LOG_LINES: usize = 20;
+
+/// How often the dashboard refreshes on its own.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+const HELP: &str = "↑/↓ select  a toggle active  s start  x stop  r refresh  q quit";
+
+/// A configuration as shown in the dashboard.
+struct Entry {
+    name: String,
+    active: bool,
+    cloned: bool,
+    running: Option<bool>,
+}
+
+struct Dashboard<'a> {
+    path: &'a Path,
+    dry_run: bool,
+    output: &'a Output,
+    compose: ComposeOps<'a>,
+    entries: Vec<Entry>,
+    table: TableState,
+    containers: Result<Vec<ContainerEntry>, String>,
+    logs: String,
+    /// Outcome of the last action, red when it failed
+    status: Option<(String, bool)>,
+}
+
+/// Opens the dashboard for the config at `path` until the user quits.
+pub fn run(
+    path: &Path,
+    runtime: Runtime,
+    network: String,
+    network_options: NetworkOptions,
+    retry: Retry,
+    dry_run: bool,
+) -> Result<(), Error> {
+    // Collect events instead of printing them over the dashboard
+    let output = Output::new(OutputFormat::Json);
+    let runner = Runner {
+        output: &output,
+        dry_run,
+        retry,
+    };
+    let mut dashboard = Dashboard {
+        path,
+        dry_run,
+        output: &output,
+        compose: ComposeOps::new(&runner, runtime, network, network_options),
+        entries: Vec::new(),
+        table: TableState::default().with_selected(0),
+        containers: Ok(Vec::new()),
+        logs: String::new(),
+        status: None,
+    };
+    dashboard.refresh()?;
+
+    let mut terminal = ratatui::init();
+    let result = dashboard.run(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+impl Dashboard<'_> {
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<(), Error> {
+        loop {
+            terminal
+                .draw(|frame| self.draw(frame))
+                .map_err(Error::Terminal)?;
+
+            if !event::poll(REFRESH_INTERVAL).map_err(Error::Terminal)? {
+                self.refresh()?;
+                continue;
+            }
+            let Event::Key(key) = event::read().map_err(Error::Terminal)? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Down | KeyCode::Char('j') => self.select(1),
+                KeyCode::Up | KeyCode::Char('k') => self.select(-1),
+                KeyCode::Char('r') => self.refresh()?,
+                KeyCode::Char('a') | KeyCode::Char(' ') => self.toggle()?,
+                KeyCode::Char('s') => self.start_or_stop(true)?,
+                KeyCode::Char('x') => self.start_or_stop(false)?,
+                _ => {}
+            }
+        }
+    }
+
+    /// Loads the config without keeping it locked.
+    fn load(&self) -> Result<ConfigStore, Error> {
+        let mut store = ConfigStore::open(self.path, || {})?;
+        store.unlock();
+        Ok(store)
+    }
+
+    fn selected_name(&self) -> Option<String> {
+        let entry = self.entries.get(self.table.selected()?)?;
+        Some(entry.name.clone())
+    }
+
+    fn select(&mut self, offset: isize) {
+        if self.entries.is_empty() {
+            return;
+        }
+        let current = self.table.selected().unwrap_or(0);
+        let next = current
+            .saturating_add_signed(offset)
+            .min(self.entries.len() - 1);
+        if next != current {
+            self.table.select(Some(next));
+            self.refresh_details(self.load().ok().as_ref());
+        }
+    }
+
+    /// Reloads the configurations and the state of their projects.
+    fn refresh(&mut self) -> Result<(), Error> {
+        let store = self.load()?;
+        self.entries = store
+            .configs
+            .iter()
+            .map(|(name, config)| Entry {
+                name: name.clone(),
+                active: config.active,
+                cloned: config.clone_path.is_some(),
+                running: config
+                    .project(name)
+                    .and_then(|project| self.compose.is_running(&project)),
+            })
+            .collect();
+        let last = self.entries.len().saturating_sub(1);
+        self.table
+            .select(Some(self.table.selected().unwrap_or(0).min(last)));
+        self.refresh_details(Some(&store));
+        Ok(())
+    }
+
+    /// Reloads the containers and logs of the selected configuration.
+    fn refresh_details(&mut self, store: Option<&ConfigStore>) {
+        let name = self.selected_name();
+        let project = name
+            .as_deref()
+            .and_then(|name| store?.get(name)?.project(name));
+        match project {
+            Some(project) => {
+                self.containers = self.compose.containers(&project);
+                self.logs = self
+                    .compose
+                    .logs(&project, LOG_LINES)
+                    .unwrap_or_else(|err| err);
+            }
+            None => {
+                self.containers = Ok(Vec::new());
+                self.logs = String::new();
+            }
+        }
+    }
+
+    /// Flips the active flag of the selected configuration.
+    fn toggle(&mut self) -> Result<(), Error> {
+        let Some(name) = self.selected_name() else {
+            return Ok(());
+        };
+        let mut store = ConfigStore::open(self.path, || {})?;
+        if let Some(config) = store.configs.get_mut(&name) {
+            config.active = !config.active;
+            let message = format!(
+                "Configuration '{}' turned {}.",
+                name,
+                if config.active { "on" } else { "off" }
+            );
+            if self.dry_run {
+                self.status = Some((format!("Would write '{}'", self.path.display()), false));
+            } else {
+                store.save()?;
+                self.status = Some((message, false));
+            }
+        }
+        self.refresh()
+    }
+
+    /// Starts or stops the project of the selected configuration.
+    fn start_or_stop(&mut self, start: bool) -> Result<(), Error> {
+        let Some(name) = self.selected_name() else {
+            return Ok(());
+        };
+        let store = self.load()?;
+        match store.get(&name) {
+            Some(config) if config.clone_path.is_some() => {
+                if start {
+                    let options = StartOptions {
+                        proxy: store.settings.proxy,
+                        override_template: store.settings.override_template.clone(),
+                        wait_for_timeout: store.settings.wait_for_timeout,
+                        up_timeout: store.settings.up_timeout,
+                        secrets: Secrets::open(self.path)?.configs,
+                        ..StartOptions::default()
+                    };
+                    if self.compose.ensure_network() {
+                        if let Some(proxy) = options.proxy {
+                            self.compose.ensure_proxy(proxy);
+                        }
+                        self.compose.start(&options, &name, config);
+                    }
+                } else {
+                    let options = StopOptions {
+                        down_timeout: store.settings.down_timeout,
+                        ..StopOptions::default()
+                    };
+                    self.compose.stop(&options, &name, config);
+                }
+
+                // Show the last message, or the first failure
+                let events = self.output.take_events();
+                let failure = events.iter().find(|event| event.status == Status::Failed);
+                self.status = failure.or(events.last()).map(|event| {
+                    let message = match event.stderr {
+                        Some(ref stderr) => format!("{}: {}", event.message, stderr),
+                        None => event.message.clone(),
+                    };
+                    (message, event.status == Status::Failed)
+                });
+            }
+            Some(_) => {
+                self.status = Some((format!("Configuration '{}' is not cloned.", name), true));
+            }
+            None => {}
+        }
+        self.refresh()
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [configs_area, details_area, footer_area] = Layout::vertical([
+            Constraint::Percentage(40),
+            Constraint::Min(5),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [containers_area, logs_area] =
+            Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+                .areas(details_area);
+
+        let yes_no = |value: bool| if value { "yes" } else { "no" };
+        let rows = self.entries.iter().map(|entry| {
+            let running = match (entry.cloned, entry.running) {
+                (false, _) => "-",
+                (true, Some(true)) => "yes",
+                (true, Some(false)) => "no",
+                (true, None) => "unknown",
+            };
+            Row::new([
+                entry.name.clone(),
+                yes_no(entry.active).to_string(),
+                yes_no(entry.cloned).to_string(),
+                running.to_string(),
+            ])
+        });
+        let configs = Table::new(
+            rows,
+            [
+                Constraint::Fill(1),
+                Constraint::Length(8),
+                Constraint::Length(8),
+                Constraint::Length(8),
+            ],
+        )
+        .header(
+            Row::new(["NAME", "ACTIVE", "CLONED", "RUNNING"])
+                .style(Style::new().add_modifier(Modifier::BOLD)),
+        )
+        .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+        .block(Block::bordered().title(" Configurations "));
+        frame.render_stateful_widget(configs, configs_area, &mut self.table);
+
+        let containers_block = Block::bordered().title(" Containers ");
+        match self.containers {
+            Ok(ref containers) => {
+                let rows = containers.iter().map(|container| {
+                    Row::new([container.service.clone(), container.status.clone()])
+                });
+                let table = Table::new(rows, [Constraint::Fill(1), Constraint::Fill(2)])
+                    .block(containers_block);
+                frame.render_widget(table, containers_area);
+            }
+            Err(ref err) => {
+                let error = Paragraph::new(err.as_str())
+                    .style(Style::new().fg(Color::Red))
+                    .block(containers_block);
+                frame.render_widget(error, containers_area);
+            }
+        }
+
+        // Keep the end of the logs in view
+        let lines: Vec<Line> = self.logs.lines().map(Line::raw).collect();
+        let visible = logs_area.height.saturating_sub(2) as usize;
+        let scroll = lines.len().saturating_sub(visible);
+        let logs = Paragraph::new(lines)
+            .scroll((u16::try_from(scroll).unwrap_or(u16::MAX), 0))
+            .block(Block::bordered().title(" Logs "));
+        frame.render_widget(logs, logs_area);
+
+        let footer = match self.status {
+            Some((ref message, true)) => Line::styled(message.as_str(), Color::Red),
+            Some((ref message, false)) => Line::raw(message.as_str()),
+            None => Line::styled(HELP, Color::DarkGray),
+        };
+        frame.render_widget(footer, footer_area);
+    }
+}
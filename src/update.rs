@@ -0,0 +1,187 @@
+//! `comphost self-update`: replaces the running executable with the binary of
+//! the latest GitHub release.
+//!
+//! Every release has one binary per platform named `comphost-<os>-<arch>`,
+//! like `comphost-linux-x86_64` or `comphost-windows-x86_64.exe`, next to a
+//! `<binary>.sha256` file with its SHA-256 checksum, which the download has to
+//! match before anything is replaced.
+
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// The latest release of comphost, without drafts and prereleases.
+const LATEST_RELEASE_URL: &str = "https://api.github.com/repos/l-bianchi/comphost/releases/latest";
+
+/// The version of the running executable.
+pub const CURRENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+const USER_AGENT: &str = concat!("comphost/", env!("CARGO_PKG_VERSION"));
+
+/// Failures are described as they are reported.
+pub type Error = String;
+
+#[derive(Deserialize)]
+struct GithubRelease {
+    tag_name: String,
+    assets: Vec<GithubAsset>,
+}
+
+#[derive(Deserialize)]
+struct GithubAsset {
+    name: String,
+    browser_download_url: String,
+}
+
+/// A release with the binary for this platform.
+pub struct Release {
+    pub version: String,
+    binary_url: String,
+    checksum_url: String,
+}
+
+impl Release {
+    /// Whether the release is newer than the running executable.
+    pub fn is_newer(&self) -> bool {
+        version_numbers(&self.version) > version_numbers(CURRENT_VERSION)
+    }
+}
+
+/// The name of the binary for this platform in a release.
+pub fn binary_name() -> String {
+    format!(
+        "comphost-{}-{}{}",
+        env::consts::OS,
+        env::consts::ARCH,
+        env::consts::EXE_SUFFIX
+    )
+}
+
+/// Looks up the latest release.
+pub fn latest() -> Result<Release, Error> {
+    let release: GithubRelease = ureq::get(LATEST_RELEASE_URL)
+        .header("User-Agent", USER_AGENT)
+        .header("Accept", "application/vnd.github+json")
+        .call()
+        .and_then(|mut response| response.body_mut().read_to_string())
+        .map_err(|err| err.to_string())
+        .and_then(|body| serde_json::from_str(&body).map_err(|err| err.to_string()))
+        .map_err(|err| format!("Failed to look up the latest release: {}", err))?;
+
+    let binary_name = binary_name();
+    let checksum_name = format!("{}.sha256", binary_name);
+    let url = |name: &str| {
+        release
+            .assets
+            .iter()
+            .find(|asset| asset.name == name)
+            .map(|asset| asset.browser_download_url.clone())
+            .ok_or_else(|| format!("Release {} has no '{}'", release.tag_name, name))
+    };
+    Ok(Release {
+        version: release.tag_name.trim_start_matches('v').to_string(),
+        binary_url: url(&binary_name)?,
+        checksum_url: url(&checksum_name)?,
+    })
+}
+
+/// Downloads the binary of `release`, checks it against its checksum and puts
+/// it in place of the running executable, which is returned.
+pub fn install(release: &Release) -> Result<PathBuf, Error> {
+    let binary = download(&release.binary_url)?;
+    let checksum = String::from_utf8(download(&release.checksum_url)?)
+        .map_err(|_| "The checksum file isn't text".to_string())?;
+    // `sha256sum` output, the checksum followed by the file name
+    let expected = checksum.split_whitespace().next().unwrap_or_default();
+    let actual = format!("{:x}", Sha256::digest(&binary));
+    if !expected.eq_ignore_ascii_case(&actual) {
+        return Err(format!(
+            "The checksum of '{}' is {} instead of {}",
+            binary_name(),
+            actual,
+            expected
+        ));
+    }
+
+    let executable = env::current_exe()
+        .and_then(fs::canonicalize)
+        .map_err(|err| format!("Failed to find the running executable: {}", err))?;
+    replace(&executable, &binary)
+        .map_err(|err| format!("Failed to replace '{}': {}", executable.display(), err))?;
+    Ok(executable)
+}
+
+fn download(url: &str) -> Result<Vec<u8>, Error> {
+    let mut content = Vec::new();
+    ureq::get(url)
+        .header("User-Agent", USER_AGENT)
+        .call()
+        .map_err(|err| err.to_string())
+        .and_then(|mut response| {
+            response
+                .body_mut()
+                .with_config()
+                .limit(u64::MAX)
+                .reader()
+                .read_to_end(&mut content)
+                .map_err(|err| err.to_string())
+        })
+        .map_err(|err| format!("Failed to download '{}': {}", url, err))?;
+    Ok(content)
+}
+
+/// Writes `binary` next to `executable` and moves it over it, so a failed
+/// write leaves the executable alone. A running executable can be renamed but
+/// not overwritten on Windows, so there it is moved aside first.
+fn replace(executable: &Path, binary: &[u8]) -> std::io::Result<()> {
+    let new = executable.with_extension("new");
+    fs::write(&new, binary)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&new, fs::Permissions::from_mode(0o755))?;
+    }
+    #[cfg(windows)]
+    {
+        let old = executable.with_extension("old");
+        let _ = fs::remove_file(&old);
+        fs::rename(executable, &old)?;
+    }
+    fs::rename(&new, executable)
+}
+
+/// The numbers of a `1.2.3` version, for comparing versions.
+fn version_numbers(version: &str) -> Vec<u64> {
+    version
+        .split(['.', '-', '+'])
+        .map_while(|part| part.parse().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_compare_numerically() {
+        assert!(version_numbers("0.10.0") > version_numbers("0.9.9"));
+        assert!(version_numbers("1.0.0") > version_numbers("0.99.0"));
+        assert!(version_numbers("1.2.3") == version_numbers("1.2.3"));
+        assert!(version_numbers("1.2.10") > version_numbers("1.2.9"));
+    }
+
+    #[test]
+    fn prerelease_and_build_suffixes_are_ignored() {
+        assert_eq!(version_numbers("1.2.3-beta.1"), [1, 2, 3]);
+        assert_eq!(version_numbers("1.2.3+build"), [1, 2, 3]);
+        assert_eq!(version_numbers("garbage"), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn binary_names_follow_the_platform() {
+        assert!(binary_name().starts_with(&format!("comphost-{}-", env::consts::OS)));
+    }
+}
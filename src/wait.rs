@@ -0,0 +1,62 @@
+//! Endpoints outside comphost that configurations wait for before starting,
+//! set with `wait_for`.
+//!
+//! A `host:port` endpoint is up once a TCP connection to it succeeds, an
+//! `http://` or `https://` URL once a request to it gets a response that isn't
+//! an error status.
+
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long `start` waits for the endpoints when `wait_for_timeout` is unset.
+pub const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long a single check may take.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait between checks of an endpoint that is down.
+const INTERVAL: Duration = Duration::from_secs(1);
+
+/// Checks once whether `endpoint` is up, with the reason when it isn't.
+pub fn check(endpoint: &str) -> Result<(), String> {
+    if endpoint.starts_with("http://") || endpoint.starts_with("https://") {
+        let agent: ureq::Agent = ureq::Agent::config_builder()
+            .timeout_global(Some(CHECK_TIMEOUT))
+            .build()
+            .into();
+        return agent
+            .get(endpoint)
+            .call()
+            .map(drop)
+            .map_err(|err| err.to_string());
+    }
+
+    let addresses = endpoint
+        .to_socket_addrs()
+        .map_err(|err| format!("Invalid address '{}': {}", endpoint, err))?;
+    let mut error = format!("'{}' resolves to no address", endpoint);
+    for address in addresses {
+        match TcpStream::connect_timeout(&address, CHECK_TIMEOUT) {
+            Ok(_) => return Ok(()),
+            Err(err) => error = err.to_string(),
+        }
+    }
+    Err(error)
+}
+
+/// Waits until every endpoint is up, giving up after `timeout` with the first
+/// one that is still down and why.
+pub fn wait(endpoints: &[String], timeout: Duration) -> Result<(), (String, String)> {
+    let deadline = Instant::now() + timeout;
+    for endpoint in endpoints {
+        loop {
+            match check(endpoint) {
+                Ok(()) => break,
+                Err(err) if Instant::now() >= deadline => return Err((endpoint.clone(), err)),
+                Err(_) => thread::sleep(INTERVAL),
+            }
+        }
+    }
+    Ok(())
+}
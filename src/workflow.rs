@@ -0,0 +1,506 @@
+//! Workflows over several configurations at once, behind the commands:
+//! picking the configurations a command acts on, pulling them, starting them
+//! in dependency order and rolling a failed start back, finding repositories
+//! to import and editing the config file.
+
+use crate::compose::{dependency_waves, ComposeOps, StartOptions, StopOptions, READY_TIMEOUT};
+use crate::config::{ConfigStore, Configuration};
+use crate::error::{Error, Failure};
+use crate::git::{CloneOptions, GitOps};
+use crate::output::{Output, Report, Runner};
+use crate::run_parallel;
+use crate::temp::TempDir;
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Expands name patterns and `--tag` options into configuration names, next to
+/// the explicit names. Patterns and tags matching nothing are reported.
+pub fn with_tagged(
+    configs: &BTreeMap<String, Configuration>,
+    names: Vec<String>,
+    tags: &[String],
+    output: &Output,
+) -> Vec<String> {
+    let mut names: Vec<String> = names
+        .into_iter()
+        .flat_map(|name| {
+            if !is_pattern(&name) {
+                return vec![name];
+            }
+            let matching: Vec<String> = configs
+                .keys()
+                .filter(|config_name| matches_pattern(&name, config_name))
+                .cloned()
+                .collect();
+            if matching.is_empty() {
+                let mut report = Report::global();
+                report.failed_as(
+                    Failure::NotFound,
+                    format!("No configuration matches '{}'.", name),
+                    &[],
+                );
+                output.report(report);
+            }
+            matching
+        })
+        .collect();
+    for tag in tags {
+        let tagged: Vec<&String> = configs
+            .iter()
+            .filter(|(_, config)| config.tags.contains(tag))
+            .map(|(config_name, _)| config_name)
+            .collect();
+        if tagged.is_empty() {
+            let mut report = Report::global();
+            report.failed_as(
+                Failure::NotFound,
+                format!("No configuration tagged '{}'.", tag),
+                &[],
+            );
+            output.report(report);
+        }
+        names.extend(tagged.into_iter().cloned());
+    }
+    let mut seen = BTreeSet::new();
+    names.retain(|config_name| seen.insert(config_name.clone()));
+    names
+}
+
+/// Whether a name is a pattern with `*` or `?` rather than a configuration name.
+pub fn is_pattern(name: &str) -> bool {
+    name.contains(['*', '?'])
+}
+
+/// Matches `name` against a pattern in which `*` matches any characters and `?`
+/// any one character.
+pub fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where to continue after the last `*` when the rest doesn't match
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    n = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Picks the cloned configurations a command operates on: the named and tagged
+/// ones when names are given, otherwise the ones selected by `tags`. Unknown or
+/// uncloned names are reported and left out.
+pub fn select_cloned(
+    configs: &BTreeMap<String, Configuration>,
+    names: Vec<String>,
+    tags: &[String],
+    output: &Output,
+) -> Vec<String> {
+    if names.is_empty() {
+        return configs
+            .iter()
+            .filter(|(_, config)| config.is_selected(tags) && config.clone_path.is_some())
+            .map(|(config_name, _)| config_name.clone())
+            .collect();
+    }
+
+    let mut selected = with_tagged(configs, names, tags, output);
+    selected.retain(|config_name| match configs.get(config_name) {
+        Some(config) if config.clone_path.is_some() => true,
+        Some(_) => {
+            output.skipped(
+                config_name,
+                format!("Configuration '{}' is not cloned.", config_name),
+            );
+            false
+        }
+        None => {
+            output.not_found(config_name);
+            false
+        }
+    });
+    selected
+}
+
+/// How a configuration is pulled, stashing changes when `autostash` is given
+/// even if the configuration doesn't ask for it.
+pub fn pull_options(config: &Configuration, autostash: bool) -> CloneOptions {
+    CloneOptions {
+        autostash: autostash || config.autostash,
+        ..config.clone_options()
+    }
+}
+
+/// Takes the configurations whose clones have uncommitted changes out of
+/// `selected`, so a pull doesn't merge into work in progress, and returns them.
+/// Stashed changes are out of the way, so with `autostash` nothing is taken out.
+pub fn skip_dirty(
+    configs: &BTreeMap<String, Configuration>,
+    selected: &mut Vec<String>,
+    autostash: bool,
+    git: &GitOps,
+) -> Vec<String> {
+    let mut dirty = Vec::new();
+    selected.retain(|config_name| {
+        let config = &configs[config_name];
+        if pull_options(config, autostash).autostash {
+            return true;
+        }
+        let clone_path = config
+            .clone_path
+            .as_deref()
+            .expect("Selected configurations are cloned");
+        if git.has_changes(config_name, clone_path) {
+            dirty.push(config_name.clone());
+            return false;
+        }
+        true
+    });
+    dirty
+}
+
+/// Names the clones left alone by [`skip_dirty`] once everything else is done.
+pub fn report_dirty(dirty: &[String], output: &Output) {
+    if !dirty.is_empty() {
+        output.notice(format!(
+            "Not pulled because of uncommitted changes: {}",
+            dirty.join(", ")
+        ));
+    }
+}
+
+/// Pulls the images of the selected configurations concurrently, returning the
+/// ones that succeeded.
+pub fn pull_images(
+    configs: &BTreeMap<String, Configuration>,
+    selected: Vec<String>,
+    jobs: usize,
+    compose: &ComposeOps,
+) -> BTreeSet<String> {
+    let pulled = run_parallel(jobs, selected.clone(), |config_name| {
+        let project = configs[&config_name]
+            .project(&config_name)
+            .expect("Selected configurations are cloned");
+        compose.pull_images(&config_name, &project)
+    });
+    selected
+        .into_iter()
+        .zip(pulled)
+        .filter(|(_, pulled)| *pulled)
+        .map(|(config_name, _)| config_name)
+        .collect()
+}
+
+/// Starts the selected configurations after the ones they depend on are ready,
+/// skipping those with port conflicts, on the wrong branch or whose
+/// dependencies failed. Ports moved with `remap` are recorded in `configs`.
+#[allow(clippy::too_many_arguments)]
+pub fn start_in_order(
+    configs: &mut BTreeMap<String, Configuration>,
+    selected: &[String],
+    options: &StartOptions,
+    remap: bool,
+    jobs: usize,
+    compose: &ComposeOps,
+    git: &GitOps,
+    output: &Output,
+) {
+    let check = compose.check_ports(configs, selected, remap);
+    for (config_name, remaps) in check.remapped {
+        if let Some(config) = configs.get_mut(&config_name) {
+            config.remapped_ports = remaps;
+        }
+    }
+    let configs = &*configs;
+    let selected: Vec<String> = selected
+        .iter()
+        .filter(|config_name| !check.conflicting.contains(*config_name))
+        .cloned()
+        .collect();
+    if let (false, Some(proxy)) = (selected.is_empty(), options.proxy) {
+        compose.ensure_proxy(proxy);
+    }
+    for config_name in &selected {
+        for dependency in &configs[config_name].depends_on {
+            if !selected.contains(dependency) {
+                output.skipped(
+                    config_name,
+                    format!(
+                        "'{}' depends on '{}', which is not being started",
+                        config_name, dependency
+                    ),
+                );
+            }
+        }
+    }
+
+    let needed: BTreeSet<&String> = selected
+        .iter()
+        .flat_map(|config_name| &configs[config_name].depends_on)
+        .filter(|dependency| selected.contains(dependency))
+        .collect();
+
+    match dependency_waves(configs, &selected) {
+        Ok(waves) => {
+            let mut failed = BTreeSet::new();
+            for wave in waves {
+                // Don't start anything whose dependencies didn't come up
+                let (blocked, ready): (Vec<String>, Vec<String>) =
+                    wave.into_iter().partition(|config_name| {
+                        configs[config_name]
+                            .depends_on
+                            .iter()
+                            .any(|dependency| failed.contains(dependency))
+                    });
+                for config_name in blocked {
+                    output.skipped(
+                        &config_name,
+                        format!("Skipping '{}', a dependency failed to start", config_name),
+                    );
+                    failed.insert(config_name);
+                }
+
+                let started = run_parallel(jobs, ready.clone(), |config_name| {
+                    let config = &configs[&config_name];
+                    let on_branch = config.clone_path.as_deref().is_none_or(|clone_path| {
+                        git.verify_branch(&config_name, clone_path, config.branch.as_deref())
+                    });
+                    // Started isn't ready, dependents would race its services
+                    let dependency_options;
+                    let options = if needed.contains(&config_name) {
+                        let default_timeout = match options.wait {
+                            true => options.wait_timeout,
+                            false => READY_TIMEOUT,
+                        };
+                        dependency_options = StartOptions {
+                            wait: true,
+                            wait_timeout: config.ready_timeout.unwrap_or(default_timeout),
+                            ..options.clone()
+                        };
+                        &dependency_options
+                    } else {
+                        options
+                    };
+                    on_branch && compose.start(options, &config_name, config)
+                });
+                for (config_name, started) in ready.into_iter().zip(started) {
+                    if !started {
+                        failed.insert(config_name);
+                    }
+                }
+            }
+        }
+        Err(cycle) => {
+            let mut report = Report::global();
+            report.failed(
+                format!("Dependency cycle detected: {}", cycle.join(" -> ")),
+                &[],
+            );
+            output.report(report);
+        }
+    }
+}
+
+/// The selected configurations with running containers.
+pub fn running(
+    configs: &BTreeMap<String, Configuration>,
+    selected: &[String],
+    compose: &ComposeOps,
+) -> BTreeSet<String> {
+    selected
+        .iter()
+        .filter(|config_name| {
+            configs[*config_name]
+                .project(config_name)
+                .and_then(|project| compose.is_running(&project))
+                == Some(true)
+        })
+        .cloned()
+        .collect()
+}
+
+/// Stops the selected configurations that weren't running before a start,
+/// dependents before their dependencies, so a failed start leaves nothing half up.
+pub fn roll_back(
+    configs: &BTreeMap<String, Configuration>,
+    selected: &[String],
+    was_running: &BTreeSet<String>,
+    options: &StopOptions,
+    compose: &ComposeOps,
+    output: &Output,
+) {
+    let started: Vec<String> = running(configs, selected, compose)
+        .difference(was_running)
+        .cloned()
+        .collect();
+    if started.is_empty() {
+        return;
+    }
+    output.notice(format!(
+        "Rolling back, stopping {}",
+        started
+            .iter()
+            .map(|config_name| format!("'{}'", config_name))
+            .collect::<Vec<_>>()
+            .join(", ")
+    ));
+    // Cycles would have kept everything from starting
+    let waves = dependency_waves(configs, &started).unwrap_or_else(|_| vec![started.clone()]);
+    for config_name in waves.iter().rev().flatten() {
+        compose.stop(options, config_name, &configs[config_name]);
+    }
+}
+
+/// Collects the git repositories in `dir` and up to `depth` levels below it,
+/// without looking inside repositories or hidden directories.
+pub fn find_repos(dir: &Path, depth: usize, repos: &mut Vec<PathBuf>) {
+    if dir.join(".git").exists() {
+        repos.push(dir.to_path_buf());
+        return;
+    }
+    if depth == 0 {
+        return;
+    }
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut dirs: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+        .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_dir()))
+        .map(|entry| entry.path())
+        .collect();
+    dirs.sort();
+    for dir in dirs {
+        find_repos(&dir, depth - 1, repos);
+    }
+}
+
+/// Editor used when neither `VISUAL` nor `EDITOR` is set.
+#[cfg(windows)]
+const DEFAULT_EDITOR: &str = "notepad";
+#[cfg(not(windows))]
+const DEFAULT_EDITOR: &str = "vi";
+
+/// Lets the user edit a copy of the config until it is valid or they give up,
+/// then replaces the config with it.
+pub fn edit(store: &mut ConfigStore, runner: &Runner) -> Result<(), Error> {
+    let output = runner.output;
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .ok()
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_EDITOR.to_string());
+    // Editors may come with arguments, like `code --wait`
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or(DEFAULT_EDITOR);
+    let editor_args: Vec<&str> = words.collect();
+
+    // Edit a copy so the config stays valid while the editor is open
+    let original = store.text()?;
+    let mut content = original.clone();
+    // It may be a decrypted encrypted config, so it stays out of the config
+    // directory in a private one that is deleted afterwards
+    let edit_file = TempDir::create("edit").and_then(|dir| {
+        let path = dir.write("config.toml", "")?;
+        Ok((dir, path))
+    });
+    let edited = loop {
+        let edit_path = match edit_file {
+            Ok((_, ref path)) => path,
+            Err(ref err) => break Err(format!("Failed to create a file to edit: {}", err)),
+        };
+        if let Err(err) = fs::write(edit_path, &content) {
+            break Err(format!(
+                "Failed to write '{}': {}",
+                edit_path.display(),
+                err
+            ));
+        }
+        let status = runner.interactive(Command::new(program).args(&editor_args).arg(edit_path));
+        let edited = match status {
+            Ok(status) if status.success() => fs::read_to_string(edit_path),
+            Ok(status) => Err(io::Error::other(format!(
+                "'{}' exited with {}",
+                editor, status
+            ))),
+            Err(err) => Err(io::Error::other(format!(
+                "Failed to run '{}': {}",
+                editor, err
+            ))),
+        };
+        match edited {
+            Err(err) => break Err(err.to_string()),
+            Ok(edited) => match store.validate(&edited) {
+                Ok(()) => break Ok(edited),
+                Err(err) => {
+                    content = edited;
+                    output.notice(err.to_string());
+                    let answer = output.prompt("Edit again? [Y/n]");
+                    if matches!(answer.as_str(), "n" | "N" | "no") {
+                        break Err("Discarded the invalid changes".to_string());
+                    }
+                }
+            },
+        }
+    };
+    drop(edit_file);
+
+    let path = store.path().display().to_string();
+    let mut report = Report::global();
+    match edited {
+        Err(message) => report.failed(message, &[]),
+        Ok(edited) if edited == original => report.skipped(format!("No changes to '{}'", path)),
+        Ok(edited) => {
+            store.replace(&edited)?;
+            report.ok(format!("Saved '{}'", path));
+        }
+    }
+    output.report(report);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns_match_whole_names() {
+        assert!(matches_pattern("api-*", "api-gateway"));
+        assert!(matches_pattern("api-*", "api-"));
+        assert!(!matches_pattern("api-*", "web-api-gateway"));
+        assert!(matches_pattern("*-api", "billing-api"));
+        assert!(matches_pattern("a*b*c", "aXbYbZc"));
+        assert!(!matches_pattern("a*b*c", "aXbYbZ"));
+        assert!(matches_pattern("web?", "web1"));
+        assert!(!matches_pattern("web?", "web"));
+        assert!(matches_pattern("*", ""));
+        assert!(!matches_pattern("", "web"));
+    }
+
+    #[test]
+    fn only_wildcards_make_patterns() {
+        assert!(is_pattern("api-*"));
+        assert!(is_pattern("web?"));
+        assert!(!is_pattern("api"));
+    }
+}
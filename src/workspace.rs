@@ -0,0 +1,122 @@
+//! Named workspaces: separate config files under `workspaces/`, each with its
+//! own configurations and shared network, next to the default `config.toml`.
+//!
+//! The workspace picked with `workspace use` is remembered in the
+//! `current-workspace` file of the config directory.
+
+use crate::error::Error;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// The workspace kept in `config.toml`, used until another one is selected.
+pub const DEFAULT: &str = "default";
+
+/// `$XDG_CONFIG_HOME/comphost` when it is set to an absolute path, otherwise
+/// `~/.config/comphost`, or `%APPDATA%\comphost` on Windows.
+pub fn config_dir() -> Result<PathBuf, Error> {
+    if let Some(config_home) = env::var_os("XDG_CONFIG_HOME") {
+        // The spec says to ignore relative paths
+        let config_home = PathBuf::from(config_home);
+        if config_home.is_absolute() {
+            return Ok(config_home.join("comphost"));
+        }
+    }
+    // macOS users keep ~/.config rather than ~/Library/Application Support
+    #[cfg(windows)]
+    let config_home = dirs::config_dir();
+    #[cfg(not(windows))]
+    let config_home = dirs::home_dir().map(|home| home.join(".config"));
+    Ok(config_home.ok_or(Error::NoHome)?.join("comphost"))
+}
+
+/// The config file of a workspace.
+pub fn path(name: &str) -> Result<PathBuf, Error> {
+    if name == DEFAULT {
+        return Ok(config_dir()?.join("config.toml"));
+    }
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(Error::InvalidWorkspace(name.to_string()));
+    }
+    Ok(config_dir()?
+        .join("workspaces")
+        .join(format!("{}.toml", name)))
+}
+
+/// Whether a workspace has a config file, which the default one always has.
+pub fn exists(name: &str) -> Result<bool, Error> {
+    Ok(name == DEFAULT || path(name)?.is_file())
+}
+
+/// The names of the default workspace and of every workspace file.
+pub fn list() -> Result<Vec<String>, Error> {
+    let dir = config_dir()?.join("workspaces");
+    let mut names = match fs::read_dir(&dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let path = entry.path();
+                let name = path.file_stem()?.to_str()?.to_string();
+                (path.extension()? == "toml").then_some(name)
+            })
+            .collect(),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(source) => {
+            return Err(Error::Read {
+                path: dir.display().to_string(),
+                source,
+            })
+        }
+    };
+    names.sort();
+    names.insert(0, DEFAULT.to_string());
+    Ok(names)
+}
+
+/// The workspace selected with [`set_current`], the default one if none is.
+pub fn current() -> Result<String, Error> {
+    let path = config_dir()?.join("current-workspace");
+    match fs::read_to_string(&path) {
+        Ok(name) if !name.trim().is_empty() => Ok(name.trim().to_string()),
+        Ok(_) => Ok(DEFAULT.to_string()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(DEFAULT.to_string()),
+        Err(source) => Err(Error::Read {
+            path: path.display().to_string(),
+            source,
+        }),
+    }
+}
+
+/// Makes `name` the workspace commands use without `--workspace`.
+pub fn set_current(name: &str) -> Result<(), Error> {
+    // Check the name before remembering it
+    path(name)?;
+    let path = config_dir()?.join("current-workspace");
+    let written = if name == DEFAULT {
+        fs::remove_file(&path).or_else(|err| match err.kind() {
+            io::ErrorKind::NotFound => Ok(()),
+            _ => Err(err),
+        })
+    } else {
+        fs::write(&path, format!("{}\n", name))
+    };
+    written.map_err(|source| Error::Write {
+        path: path.display().to_string(),
+        source,
+    })
+}
+
+/// The shared network of a workspace when the settings don't name one, so
+/// workspaces don't see each other's containers.
+pub fn default_network(name: &str) -> String {
+    if name == DEFAULT {
+        "comphost".to_string()
+    } else {
+        format!("comphost-{}", name)
+    }
+}
@@ -0,0 +1,368 @@
+//! Drives the library the way other tools would: through `ConfigStore` for the
+//! configurations and `compose::dependency_waves` for their start order.
+
+use comphost::compose::dependency_waves;
+use comphost::config::{FileFormat, Imported, Merge, SharedConfig};
+use comphost::encryption;
+use comphost::ports::PortRemap;
+use comphost::secrets::Secrets;
+use comphost::{ConfigStore, Configuration, Error};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// A fresh directory for one test. Backups go to the config directory, which
+/// is pointed into the temporary directory too so tests never touch `~`.
+fn test_dir(name: &str) -> PathBuf {
+    let root = env::temp_dir().join(format!("comphost-tests-{}", std::process::id()));
+    env::set_var("XDG_CONFIG_HOME", root.join("config-home"));
+    let dir = root.join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn config(depends_on: &[&str]) -> Configuration {
+    Configuration {
+        depends_on: depends_on.iter().map(|name| name.to_string()).collect(),
+        ..Configuration::new("https://example.com/repo.git".to_string())
+    }
+}
+
+#[test]
+fn saved_configurations_load_back() {
+    let path = test_dir("round-trip").join("config.toml");
+    let mut store = ConfigStore::open(&path, || {}).unwrap();
+    assert!(store.configs.is_empty());
+    store.configs.insert("api".to_string(), config(&[]));
+    store.configs.insert(
+        "web".to_string(),
+        Configuration {
+            active: false,
+            tags: vec!["frontend".to_string()],
+            ..config(&["api"])
+        },
+    );
+    assert!(store.save().unwrap());
+
+    let store = ConfigStore::read(&path).unwrap();
+    assert_eq!(store.configs.len(), 2);
+    assert_eq!(store.get("web").unwrap().depends_on, ["api"]);
+    assert_eq!(store.selected(&[]), ["api"]);
+    assert_eq!(store.selected(&["frontend".to_string()]), ["web"]);
+}
+
+#[test]
+fn saving_without_changes_writes_nothing() {
+    let path = test_dir("unchanged").join("config.toml");
+    let mut store = ConfigStore::open(&path, || {}).unwrap();
+    store.configs.insert("api".to_string(), config(&[]));
+    assert!(store.save().unwrap());
+
+    let store = ConfigStore::open(&path, || {}).unwrap();
+    assert!(!store.save().unwrap());
+}
+
+#[test]
+fn hand_edited_files_are_only_rewritten_on_changes() {
+    let path = test_dir("hand-edited").join("config.toml");
+    let hand_edited = "# Written by hand\nversion = 1\n\n[configs.api]\nurl = \"https://example.com/api.git\"   # the API\nactive = true\ntags = []\n";
+    fs::write(&path, hand_edited).unwrap();
+
+    let store = ConfigStore::open(&path, || {}).unwrap();
+    assert!(store.changes().unwrap().is_none());
+    assert!(!store.save().unwrap());
+    assert_eq!(fs::read_to_string(&path).unwrap(), hand_edited);
+
+    let mut store = ConfigStore::open(&path, || {}).unwrap();
+    store.configs.get_mut("api").unwrap().active = false;
+    assert!(store.save().unwrap());
+    assert!(!ConfigStore::read(&path).unwrap().get("api").unwrap().active);
+}
+
+#[test]
+fn rename_updates_dependents() {
+    let path = test_dir("rename").join("config.toml");
+    let mut store = ConfigStore::open(&path, || {}).unwrap();
+    store.configs.insert("api".to_string(), config(&[]));
+    store.configs.insert("web".to_string(), config(&["api"]));
+
+    assert!(store.rename("api", "backend"));
+    assert!(!store.rename("missing", "other"));
+    assert!(store.get("api").is_none());
+    assert_eq!(store.get("web").unwrap().depends_on, ["backend"]);
+}
+
+#[test]
+fn dependencies_start_in_earlier_waves() {
+    let path = test_dir("waves").join("config.toml");
+    let mut store = ConfigStore::open(&path, || {}).unwrap();
+    store.configs.insert("db".to_string(), config(&[]));
+    store.configs.insert("api".to_string(), config(&["db"]));
+    store
+        .configs
+        .insert("web".to_string(), config(&["api", "cdn"]));
+    let selected: Vec<String> = store.configs.keys().cloned().collect();
+
+    // `cdn` isn't a configuration, so `web` only waits for `api`
+    let waves = dependency_waves(&store.configs, &selected).unwrap();
+    assert_eq!(waves, [vec!["db"], vec!["api"], vec!["web"]]);
+}
+
+#[test]
+fn dependency_cycles_are_reported() {
+    let path = test_dir("cycle").join("config.toml");
+    let mut store = ConfigStore::open(&path, || {}).unwrap();
+    store.configs.insert("a".to_string(), config(&["b"]));
+    store.configs.insert("b".to_string(), config(&["a"]));
+    store.configs.insert("c".to_string(), config(&[]));
+    let selected: Vec<String> = store.configs.keys().cloned().collect();
+
+    let cycle = dependency_waves(&store.configs, &selected).unwrap_err();
+    assert!(cycle.contains(&"a".to_string()) && cycle.contains(&"b".to_string()));
+}
+
+#[test]
+fn synced_configurations_keep_local_fields() {
+    let path = test_dir("sync").join("config.toml");
+    let mut store = ConfigStore::open(&path, || {}).unwrap();
+    let remap = PortRemap {
+        service: "web".to_string(),
+        port: 8080,
+        protocol: "tcp".to_string(),
+        host_port: 8081,
+    };
+    store.configs.insert(
+        "api".to_string(),
+        Configuration {
+            active: false,
+            clone_path: Some("/src/api".to_string()),
+            remapped_ports: vec![remap.clone()],
+            ..config(&[])
+        },
+    );
+
+    let shared = SharedConfig {
+        profiles: BTreeMap::new(),
+        configs: BTreeMap::from([
+            ("api".to_string(), config(&["db"])),
+            ("db".to_string(), config(&[])),
+            (
+                "evil".to_string(),
+                Configuration::new("--upload-pack=touch /tmp/pwned".to_string()),
+            ),
+        ]),
+    };
+    let outcome = store.import(shared, Merge::Update);
+    assert_eq!(
+        outcome.configs,
+        [
+            ("api".to_string(), Imported::Replaced),
+            ("db".to_string(), Imported::Added),
+            ("evil".to_string(), Imported::Refused),
+        ]
+    );
+
+    let api = store.get("api").unwrap();
+    assert_eq!(api.depends_on, ["db"]);
+    assert!(!api.active);
+    assert_eq!(api.clone_path.as_deref(), Some("/src/api"));
+    assert_eq!(api.remapped_ports, [remap]);
+    assert!(store.get("db").unwrap().active);
+    assert!(store.get("evil").is_none());
+}
+
+#[test]
+fn undo_reverts_the_last_change() {
+    let path = test_dir("undo").join("config.toml");
+    let mut store = ConfigStore::open(&path, || {}).unwrap();
+    store.keep_history("add api".to_string());
+    store.configs.insert("api".to_string(), config(&[]));
+    assert!(store.save().unwrap());
+
+    let mut store = ConfigStore::open(&path, || {}).unwrap();
+    store.keep_history("off api".to_string());
+    store.configs.get_mut("api").unwrap().active = false;
+    assert!(store.save().unwrap());
+
+    let mut store = ConfigStore::open(&path, || {}).unwrap();
+    let commands: Vec<String> = store
+        .history()
+        .into_iter()
+        .map(|change| change.command)
+        .collect();
+    assert_eq!(commands, ["add api", "off api"]);
+    assert_eq!(store.undo().unwrap().as_deref(), Some("off api"));
+    assert!(ConfigStore::read(&path).unwrap().get("api").unwrap().active);
+
+    // A file changed since by hand is left alone
+    fs::write(&path, "version = 1\n").unwrap();
+    let mut store = ConfigStore::open(&path, || {}).unwrap();
+    assert!(matches!(store.undo(), Err(Error::Undo(command)) if command == "add api"));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "version = 1\n");
+}
+
+#[test]
+fn old_backups_are_rotated_out() {
+    let path = test_dir("backups").join("config.toml");
+    let mut store = ConfigStore::open(&path, || {}).unwrap();
+    store.settings.keep_backups = Some(2);
+    assert!(store.save().unwrap());
+    // The first save had no file to back up
+    assert!(ConfigStore::read(&path)
+        .unwrap()
+        .backups()
+        .unwrap()
+        .is_empty());
+
+    for tag in ["one", "two", "three"] {
+        // Backups are named by the millisecond
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        let mut store = ConfigStore::open(&path, || {}).unwrap();
+        store.configs.insert(
+            tag.to_string(),
+            Configuration {
+                tags: vec![tag.to_string()],
+                ..config(&[])
+            },
+        );
+        assert!(store.save().unwrap());
+    }
+
+    let mut store = ConfigStore::open(&path, || {}).unwrap();
+    let backups = store.backups().unwrap();
+    assert_eq!(backups.len(), 2);
+    // The oldest kept backup is from before `two` was added
+    let oldest = backups[0]
+        .file_name()
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string();
+    assert!(oldest.starts_with("config-"));
+    store.restore(&oldest).unwrap();
+    let restored = ConfigStore::read(&path).unwrap();
+    let names: Vec<&String> = restored.configs.keys().collect();
+    assert_eq!(names, ["one"]);
+}
+
+#[test]
+fn exports_import_back_in_every_format() {
+    let path = test_dir("export").join("config.toml");
+    let mut store = ConfigStore::open(&path, || {}).unwrap();
+    store.configs.insert(
+        "api".to_string(),
+        Configuration {
+            clone_path: Some("/src/api".to_string()),
+            ..config(&[])
+        },
+    );
+    store.configs.insert("web".to_string(), config(&["api"]));
+    store
+        .profiles
+        .insert("backend".to_string(), vec!["api".to_string()]);
+
+    for format in [FileFormat::Json, FileFormat::Yaml, FileFormat::Toml] {
+        let exported = format.serialize(&store.export()).unwrap();
+        assert!(!exported.contains("/src/api"), "{:?}", format);
+        let shared = format.deserialize(&exported).unwrap();
+        assert_eq!(shared.profiles["backend"], ["api"]);
+        assert_eq!(shared.configs["web"].depends_on, ["api"]);
+        assert_eq!(shared.configs["api"].clone_path, None);
+    }
+}
+
+#[test]
+fn imports_merge_existing_names_as_asked() {
+    let path = test_dir("import").join("config.toml");
+    let mut store = ConfigStore::open(&path, || {}).unwrap();
+    store.configs.insert(
+        "api".to_string(),
+        Configuration {
+            clone_path: Some("/src/api".to_string()),
+            ..config(&[])
+        },
+    );
+    let shared = || SharedConfig {
+        profiles: BTreeMap::new(),
+        configs: BTreeMap::from([(
+            "api".to_string(),
+            Configuration {
+                active: false,
+                ..config(&["db"])
+            },
+        )]),
+    };
+
+    for (merge, outcome) in [
+        (Merge::Refuse, Imported::Conflict),
+        (Merge::SkipExisting, Imported::Skipped),
+    ] {
+        let imported = store.import(shared(), merge);
+        assert_eq!(imported.configs, [("api".to_string(), outcome)]);
+        assert!(store.get("api").unwrap().depends_on.is_empty());
+    }
+
+    let imported = store.import(shared(), Merge::Overwrite);
+    assert_eq!(imported.configs, [("api".to_string(), Imported::Replaced)]);
+    let api = store.get("api").unwrap();
+    assert_eq!(api.depends_on, ["db"]);
+    assert!(!api.active);
+    assert_eq!(api.clone_path.as_deref(), Some("/src/api"));
+
+    let imported = store.import(shared(), Merge::Refuse);
+    assert_eq!(imported.configs, [("api".to_string(), Imported::Unchanged)]);
+}
+
+#[test]
+fn secrets_are_encrypted_and_read_back() {
+    let path = test_dir("secrets").join("config.toml");
+    let mut secrets = Secrets::open(&path).unwrap();
+    assert!(secrets.configs.is_empty());
+    secrets.configs.insert(
+        "api".to_string(),
+        BTreeMap::from([("API_TOKEN".to_string(), "s3cr3t".to_string())]),
+    );
+    secrets.save().unwrap();
+
+    let ciphertext = fs::read(secrets.path()).unwrap();
+    assert!(!String::from_utf8_lossy(&ciphertext).contains("s3cr3t"));
+    let secrets = Secrets::open(&path).unwrap();
+    assert_eq!(secrets.configs["api"]["API_TOKEN"], "s3cr3t");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(secrets.path()).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
+
+#[test]
+fn encrypted_configs_load_back_without_plain_text_copies() {
+    let path = test_dir("encrypted").join("config.toml");
+    let mut store = ConfigStore::open(&path, || {}).unwrap();
+    store.configs.insert("api".to_string(), config(&[]));
+    assert!(store.save().unwrap());
+    let mut store = ConfigStore::open(&path, || {}).unwrap();
+    store.configs.insert("web".to_string(), config(&["api"]));
+    assert!(store.save().unwrap());
+    assert_eq!(
+        ConfigStore::read(&path).unwrap().backups().unwrap().len(),
+        1
+    );
+
+    let mut store = ConfigStore::open(&path, || {}).unwrap();
+    store.set_passphrase(Some("correct horse".to_string().into()));
+    assert!(store.save().unwrap());
+    let content = fs::read_to_string(&path).unwrap();
+    assert!(encryption::is_encrypted(&content));
+    assert!(!content.contains("example.com"));
+
+    env::set_var("COMPHOST_PASSPHRASE", "correct horse");
+    let store = ConfigStore::read(&path).unwrap();
+    assert!(store.is_encrypted());
+    assert_eq!(store.get("web").unwrap().depends_on, ["api"]);
+    // The plain text backup went with the encryption
+    assert!(store.backups().unwrap().is_empty());
+}